@@ -57,78 +57,77 @@ echo
 # Step 2: MPC Step 1 - Generate nonces
 print_step "Step 2: MPC Step 1 - Generating nonces"
 
+echo "Getting recent blockhash..."
+recent_blockhash=$(cargo run -- recent-block-hash --net devnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
+print_info "Recent blockhash: $recent_blockhash"
+
+template_output=$(cargo run -- template-token \
+  --mint "$mint_address" \
+  --amount 1000000 \
+  --decimals 6 \
+  --to "$creator_pubkey" \
+  --wallet "$agg_key" \
+  --recent-block-hash "$recent_blockhash" \
+  --net devnet 2>/dev/null)
+message_digest=$(echo "$template_output" | grep "message digest:" | cut -d' ' -f3)
+transaction_template=$(echo "$template_output" | grep "transaction template:" | cut -d' ' -f3)
+print_info "Message digest: $message_digest"
+
 echo "Generating nonces for participant 1..."
-nonce_output1=$(cargo run -- agg-send-step-one "$privkey1" 2>/dev/null)
+nonce_output1=$(cargo run -- agg-send-step-one "$privkey1" --message-digest "$message_digest" 2>/dev/null)
+session_id=$(echo "$nonce_output1" | grep "session id:" | cut -d' ' -f3)
 secret_share1=$(echo "$nonce_output1" | grep "secret share:" | cut -d' ' -f3)
 public_share1=$(echo "$nonce_output1" | grep "public share:" | cut -d' ' -f3)
 
 echo "Generating nonces for participant 2..."
-nonce_output2=$(cargo run -- agg-send-step-one "$privkey2" 2>/dev/null)
+nonce_output2=$(cargo run -- agg-send-step-one "$privkey2" --message-digest "$message_digest" --session-id "$session_id" 2>/dev/null)
 secret_share2=$(echo "$nonce_output2" | grep "secret share:" | cut -d' ' -f3)
 public_share2=$(echo "$nonce_output2" | grep "public share:" | cut -d' ' -f3)
 
 echo "Generating nonces for participant 3..."
-nonce_output3=$(cargo run -- agg-send-step-one "$privkey3" 2>/dev/null)
+nonce_output3=$(cargo run -- agg-send-step-one "$privkey3" --message-digest "$message_digest" --session-id "$session_id" 2>/dev/null)
 secret_share3=$(echo "$nonce_output3" | grep "secret share:" | cut -d' ' -f3)
 public_share3=$(echo "$nonce_output3" | grep "public share:" | cut -d' ' -f3)
 
 print_success "Generated nonces for all participants"
+print_info "Session id: $session_id"
 print_info "Secret share 1 length: ${#secret_share1}"
 print_info "Public share 1 length: ${#public_share1}"
 echo
 
-# Step 3: Get recent blockhash and perform MPC steps quickly
+# Step 3: Perform the remaining MPC steps quickly
 print_step "Step 3: MPC Steps 2 & 3 - Signature generation and aggregation"
 
-echo "Getting recent blockhash..."
-recent_blockhash=$(cargo run -- recent-block-hash --net devnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
-print_info "Recent blockhash: $recent_blockhash"
-
 echo "Performing MPC Step 2 for all participants..."
 
 # Participant 1 (excludes their own public share)
 print_info "Generating signature from participant 1..."
-sig_output1=$(cargo run -- agg-send-step-two-token \
+sig_output1=$(cargo run -- agg-send-step-two \
   --private-key "$privkey1" \
-  --mint "$mint_address" \
-  --amount 1000000 \
-  --decimals 6 \
-  --to "$creator_pubkey" \
-  --recent-block-hash "$recent_blockhash" \
+  --template "$transaction_template" \
   --keys "$pubkey1,$pubkey2,$pubkey3" \
   --first-messages "$public_share2,$public_share3" \
-  --secret-state "$secret_share1" \
-  --net devnet 2>/dev/null)
+  --secret-state "$secret_share1" 2>/dev/null)
 signature1=$(echo "$sig_output1" | grep "partial signature:" | cut -d' ' -f3)
 
 # Participant 2 (excludes their own public share)
 print_info "Generating signature from participant 2..."
-sig_output2=$(cargo run -- agg-send-step-two-token \
+sig_output2=$(cargo run -- agg-send-step-two \
   --private-key "$privkey2" \
-  --mint "$mint_address" \
-  --amount 1000000 \
-  --decimals 6 \
-  --to "$creator_pubkey" \
-  --recent-block-hash "$recent_blockhash" \
+  --template "$transaction_template" \
   --keys "$pubkey1,$pubkey2,$pubkey3" \
   --first-messages "$public_share1,$public_share3" \
-  --secret-state "$secret_share2" \
-  --net devnet 2>/dev/null)
+  --secret-state "$secret_share2" 2>/dev/null)
 signature2=$(echo "$sig_output2" | grep "partial signature:" | cut -d' ' -f3)
 
 # Participant 3 (excludes their own public share)
 print_info "Generating signature from participant 3..."
-sig_output3=$(cargo run -- agg-send-step-two-token \
+sig_output3=$(cargo run -- agg-send-step-two \
   --private-key "$privkey3" \
-  --mint "$mint_address" \
-  --amount 1000000 \
-  --decimals 6 \
-  --to "$creator_pubkey" \
-  --recent-block-hash "$recent_blockhash" \
+  --template "$transaction_template" \
   --keys "$pubkey1,$pubkey2,$pubkey3" \
   --first-messages "$public_share1,$public_share2" \
-  --secret-state "$secret_share3" \
-  --net devnet 2>/dev/null)
+  --secret-state "$secret_share3" 2>/dev/null)
 signature3=$(echo "$sig_output3" | grep "partial signature:" | cut -d' ' -f3)
 
 print_success "Generated partial signatures from all participants"
@@ -138,14 +137,11 @@ print_info "Signature 3: $signature3"
 
 # Step 4: Aggregate signatures and broadcast
 echo "Performing MPC Step 3 - Aggregating signatures and broadcasting..."
-final_output=$(cargo run -- aggregate-signatures-and-broadcast-token \
+final_output=$(cargo run -- aggregate-signatures-and-broadcast \
+  --template "$transaction_template" \
   --signatures "$signature1,$signature2,$signature3" \
-  --mint "$mint_address" \
-  --amount 1000000 \
-  --decimals 6 \
-  --to "$creator_pubkey" \
-  --recent-block-hash "$recent_blockhash" \
   --keys "$pubkey1,$pubkey2,$pubkey3" \
+  --first-messages "$public_share1,$public_share2,$public_share3" \
   --net devnet 2>/dev/null)
 
 if [[ $final_output == *"Transaction"* ]]; then
@@ -258,75 +258,74 @@ echo -e "1. Generate nonces (random numbers for security)"
 echo -e "2. Create partial signatures from each participant" 
 echo -e "3. Aggregate signatures and broadcast transaction${NC}"
 
+print_step "Getting recent blockhash for transaction"
+recent_blockhash=$(cargo run -- recent-block-hash --net localnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
+print_info "Blockhash: $recent_blockhash"
+
+# Build the transaction once, every participant signs exactly this template
+template_output=$(cargo run -- template-token \
+    --mint $mint_address \
+    --amount 10 \
+    --decimals 6 \
+    --to $creator_public \
+    --wallet $aggregated_key \
+    --recent-block-hash $recent_blockhash \
+    --net localnet 2>/dev/null)
+message_digest=$(echo "$template_output" | grep "message digest:" | cut -d' ' -f3)
+transaction_template=$(echo "$template_output" | grep "transaction template:" | cut -d' ' -f3)
+print_info "Message digest: $message_digest"
+
 print_step "MPC Step 1: Generating nonces from all participants"
 echo -e "${PURPLE}Each participant generates random nonces for this signing session...${NC}"
 
-# Generate nonces for all participants
+# Generate nonces for all participants, Alice starts the session and the others join it
 echo "Alice generating nonces..."
-alice_nonce=$(cargo run -- agg-send-step-one $participant1_private 2>/dev/null)
+alice_nonce=$(cargo run -- agg-send-step-one $participant1_private --message-digest $message_digest 2>/dev/null)
+session_id=$(echo "$alice_nonce" | grep "session id:" | cut -d' ' -f3)
 alice_secret=$(echo "$alice_nonce" | grep "secret share:" | cut -d' ' -f3)
 alice_public_msg=$(echo "$alice_nonce" | grep "public share:" | cut -d' ' -f3)
 
 echo "Bob generating nonces..."
-bob_nonce=$(cargo run -- agg-send-step-one $participant2_private 2>/dev/null)
+bob_nonce=$(cargo run -- agg-send-step-one $participant2_private --message-digest $message_digest --session-id $session_id 2>/dev/null)
 bob_secret=$(echo "$bob_nonce" | grep "secret share:" | cut -d' ' -f3)
 bob_public_msg=$(echo "$bob_nonce" | grep "public share:" | cut -d' ' -f3)
 
 echo "Charlie generating nonces..."
-charlie_nonce=$(cargo run -- agg-send-step-one $participant3_private 2>/dev/null)
+charlie_nonce=$(cargo run -- agg-send-step-one $participant3_private --message-digest $message_digest --session-id $session_id 2>/dev/null)
 charlie_secret=$(echo "$charlie_nonce" | grep "secret share:" | cut -d' ' -f3)
 charlie_public_msg=$(echo "$charlie_nonce" | grep "public share:" | cut -d' ' -f3)
 
 print_success "All participants generated nonces!"
-print_info "These nonces ensure each signature is unique and secure"
-
-print_step "Getting recent blockhash for transaction"
-recent_blockhash=$(cargo run -- recent-block-hash --net localnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
-print_info "Blockhash: $recent_blockhash"
+print_info "These nonces ensure each signature is unique and secure, and can only sign this transaction"
 
 print_step "MPC Step 2: Generating partial signatures"
 echo -e "${PURPLE}Each participant signs the transaction with their private key and nonces...${NC}"
 
 echo "Alice creating partial signature..."
-alice_sig=$(cargo run -- agg-send-step-two-token \
+alice_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant1_private \
-    --mint $mint_address \
-    --amount 10 \
-    --decimals 6 \
-    --to $creator_public \
-    --recent-block-hash $recent_blockhash \
+    --template $transaction_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $bob_public_msg,$charlie_public_msg \
-    --secret-state $alice_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $alice_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Alice's partial signature: $alice_sig${NC}"
 
 echo "Bob creating partial signature..."
-bob_sig=$(cargo run -- agg-send-step-two-token \
+bob_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant2_private \
-    --mint $mint_address \
-    --amount 10 \
-    --decimals 6 \
-    --to $creator_public \
-    --recent-block-hash $recent_blockhash \
+    --template $transaction_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $alice_public_msg,$charlie_public_msg \
-    --secret-state $bob_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $bob_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Bob's partial signature: $bob_sig${NC}"
 
 echo "Charlie creating partial signature..."
-charlie_sig=$(cargo run -- agg-send-step-two-token \
+charlie_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant3_private \
-    --mint $mint_address \
-    --amount 10 \
-    --decimals 6 \
-    --to $creator_public \
-    --recent-block-hash $recent_blockhash \
+    --template $transaction_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $alice_public_msg,$bob_public_msg \
-    --secret-state $charlie_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $charlie_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Charlie's partial signature: $charlie_sig${NC}"
 
 print_success "All partial signatures generated!"
@@ -336,7 +335,7 @@ print_info "Each signature is only a piece - none can move funds alone"
 print_step "MPC Step 3: Aggregating signatures and broadcasting"
 echo -e "${PURPLE}Combining all partial signatures into a complete transaction...${NC}"
 
-token_transfer_result=$(cargo run -- aggregate-signatures-and-broadcast-token --signatures "$alice_sig,$bob_sig,$charlie_sig" --mint "$mint_address" --amount 10 --decimals 6 --to "$creator_public" --recent-block-hash "$recent_blockhash" --keys "$participant1_public,$participant2_public,$participant3_public" --net localnet 2>/dev/null)
+token_transfer_result=$(cargo run -- aggregate-signatures-and-broadcast --template "$transaction_template" --signatures "$alice_sig,$bob_sig,$charlie_sig" --keys "$participant1_public,$participant2_public,$participant3_public" --first-messages "$alice_public_msg,$bob_public_msg,$charlie_public_msg" --net localnet 2>/dev/null)
 
 if [[ $token_transfer_result == *"Transaction ID"* ]]; then
     token_tx_id=$(echo "$token_transfer_result" | grep "Transaction ID:" | cut -d' ' -f3)
@@ -380,69 +379,64 @@ echo -e "We'll send 0.1 SOL from the aggregated wallet to the creator."
 
 print_step "MPC SOL Transfer - Generating fresh nonces"
 
+# Get fresh blockhash and build the template of the transfer
+sol_blockhash=$(cargo run -- recent-block-hash --net localnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
+sol_template_output=$(cargo run -- template-sol \
+    --amount 0.1 \
+    --to $creator_public \
+    --memo "MPC SOL Demo Transfer" \
+    --wallet $aggregated_key \
+    --recent-block-hash $sol_blockhash 2>/dev/null)
+sol_digest=$(echo "$sol_template_output" | grep "message digest:" | cut -d' ' -f3)
+sol_template=$(echo "$sol_template_output" | grep "transaction template:" | cut -d' ' -f3)
+
 # Generate fresh nonces for SOL transfer
-alice_sol_nonce=$(cargo run -- agg-send-step-one $participant1_private 2>/dev/null)
+alice_sol_nonce=$(cargo run -- agg-send-step-one $participant1_private --message-digest $sol_digest 2>/dev/null)
+sol_session_id=$(echo "$alice_sol_nonce" | grep "session id:" | cut -d' ' -f3)
 alice_sol_secret=$(echo "$alice_sol_nonce" | grep "secret share:" | cut -d' ' -f3)
 alice_sol_public=$(echo "$alice_sol_nonce" | grep "public share:" | cut -d' ' -f3)
 
-bob_sol_nonce=$(cargo run -- agg-send-step-one $participant2_private 2>/dev/null)
+bob_sol_nonce=$(cargo run -- agg-send-step-one $participant2_private --message-digest $sol_digest --session-id $sol_session_id 2>/dev/null)
 bob_sol_secret=$(echo "$bob_sol_nonce" | grep "secret share:" | cut -d' ' -f3)
 bob_sol_public=$(echo "$bob_sol_nonce" | grep "public share:" | cut -d' ' -f3)
 
-charlie_sol_nonce=$(cargo run -- agg-send-step-one $participant3_private 2>/dev/null)
+charlie_sol_nonce=$(cargo run -- agg-send-step-one $participant3_private --message-digest $sol_digest --session-id $sol_session_id 2>/dev/null)
 charlie_sol_secret=$(echo "$charlie_sol_nonce" | grep "secret share:" | cut -d' ' -f3)
 charlie_sol_public=$(echo "$charlie_sol_nonce" | grep "public share:" | cut -d' ' -f3)
 
-# Get fresh blockhash
-sol_blockhash=$(cargo run -- recent-block-hash --net localnet 2>/dev/null | grep "Recent blockhash:" | cut -d' ' -f3)
-
 print_step "Creating partial signatures for SOL transfer"
 
-alice_sol_sig=$(cargo run -- agg-send-step-two-sol \
+alice_sol_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant1_private \
-    --amount 0.1 \
-    --to $creator_public \
-    --memo "MPC SOL Demo Transfer" \
-    --recent-block-hash $sol_blockhash \
+    --template $sol_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $bob_sol_public,$charlie_sol_public \
-    --secret-state $alice_sol_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $alice_sol_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Alice's SOL partial signature: $alice_sol_sig${NC}"
 
-bob_sol_sig=$(cargo run -- agg-send-step-two-sol \
+bob_sol_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant2_private \
-    --amount 0.1 \
-    --to $creator_public \
-    --memo "MPC SOL Demo Transfer" \
-    --recent-block-hash $sol_blockhash \
+    --template $sol_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $alice_sol_public,$charlie_sol_public \
-    --secret-state $bob_sol_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $bob_sol_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Bob's SOL partial signature: $bob_sol_sig${NC}"
 
-charlie_sol_sig=$(cargo run -- agg-send-step-two-sol \
+charlie_sol_sig=$(cargo run -- agg-send-step-two \
     --private-key $participant3_private \
-    --amount 0.1 \
-    --to $creator_public \
-    --memo "MPC SOL Demo Transfer" \
-    --recent-block-hash $sol_blockhash \
+    --template $sol_template \
     --keys $participant1_public,$participant2_public,$participant3_public \
     --first-messages $alice_sol_public,$bob_sol_public \
-    --secret-state $charlie_sol_secret \
-    --net localnet 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
+    --secret-state $charlie_sol_secret 2>/dev/null | grep "partial signature:" | cut -d' ' -f3)
 echo -e "${PURPLE}Charlie's SOL partial signature: $charlie_sol_sig${NC}"
 
 print_step "aggregating signatures and broadcasting SOL transfer"
 
-sol_transfer_result=$(cargo run -- aggregate-signatures-and-broadcast-sol \
+sol_transfer_result=$(cargo run -- aggregate-signatures-and-broadcast \
+    --template $sol_template \
     --signatures $alice_sol_sig,$bob_sol_sig,$charlie_sol_sig \
-    --amount 0.1 \
-    --to $creator_public \
-    --memo "MPC SOL Demo Transfer" \
-    --recent-block-hash $sol_blockhash \
     --keys $participant1_public,$participant2_public,$participant3_public \
+    --first-messages $alice_sol_public,$bob_sol_public,$charlie_sol_public \
     --net localnet 2>/dev/null)
 
 if [[ $sol_transfer_result == *"Transaction ID"* ]]; then
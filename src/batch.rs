@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use solana_sdk::pubkey::Pubkey;
+use spl_token::native_mint;
+
+use crate::error::Error;
+use crate::serialization::{AggMessage1, PartialSignature, Serialize, TransactionTemplate};
+use crate::session::{write_atomically, Session, SessionFile};
+use crate::template::wrap_shortfall;
+use crate::token::TokenMint;
+
+/// One row of a payout CSV: `recipient,mint,amount`, the amount in tokens such as `12.5`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Payout {
+    /// Line of the row in the CSV, to report on it
+    pub line: usize,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub amount: String,
+}
+
+/// Convert an amount in tokens to base units of a mint with `decimals`, without rounding
+pub fn parse_amount(amount: &str, decimals: u8) -> Result<u64, Error> {
+    let invalid = || Error::BatchError(format!("invalid amount {:?} for a mint with {} decimals", amount, decimals));
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > usize::from(decimals)
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let base_units = format!("{}{:0<width$}", whole, fraction, width = usize::from(decimals));
+    match base_units.parse::<u64>() {
+        Ok(0) | Err(_) => Err(invalid()),
+        Ok(base_units) => Ok(base_units),
+    }
+}
+
+/// Parse the rows of a payout CSV, skipping blank lines, `#` comments and a `recipient,mint,amount` header
+pub fn parse_payouts(csv: &str) -> Result<Vec<Payout>, Error> {
+    let mut payouts = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line_number = index + 1;
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.concat().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if payouts.is_empty() && fields.iter().map(|f| f.to_lowercase()).eq(["recipient", "mint", "amount"]) {
+            continue;
+        }
+        let (recipient, mint, amount) = match fields[..] {
+            [recipient, mint, amount] => (recipient, mint, amount),
+            _ => return Err(Error::BatchError(format!("line {}: expected recipient,mint,amount", line_number))),
+        };
+        let parse_key = |key: &str, what: &str| {
+            key.parse::<Pubkey>()
+                .map_err(|e| Error::BatchError(format!("line {}: invalid {} {}: {}", line_number, what, key, e)))
+        };
+        payouts.push(Payout {
+            line: line_number,
+            recipient: parse_key(recipient, "recipient")?,
+            mint: parse_key(mint, "mint")?,
+            amount: amount.to_string(),
+        });
+    }
+    if payouts.is_empty() {
+        return Err(Error::BatchError("no payouts to make".to_string()));
+    }
+    Ok(payouts)
+}
+
+/// Read a payout CSV
+pub fn read_payouts(path: impl AsRef<Path>) -> Result<Vec<Payout>, Error> {
+    let path = path.as_ref();
+    let csv = fs::read_to_string(path)
+        .map_err(|e| Error::BatchError(format!("Failed to read {}: {}", path.display(), e)))?;
+    parse_payouts(&csv)
+}
+
+/// Instructions of every payout from the MPC `wallet`, which also pays for the token accounts to create.
+/// Every payout to a new token account carries its idempotent creation, so it succeeds in whichever
+/// transaction lands first. The SOL the wrapped SOL payouts are short of is wrapped once, ahead of the first
+/// payout. Every row is checked before failing, so all the bad rows are reported at once.
+pub fn payout_instructions(
+    rpc_client: &RpcClient,
+    payouts: &[Payout],
+    wallet: &Pubkey,
+) -> Result<Vec<Vec<Instruction>>, Error> {
+    let mut mints: HashMap<Pubkey, TokenMint> = HashMap::new();
+    let mut instructions = Vec::new();
+    let mut wrapped_sol: u64 = 0;
+    let mut errors = Vec::new();
+    for payout in payouts {
+        if !mints.contains_key(&payout.mint) {
+            match TokenMint::fetch(rpc_client, &payout.mint) {
+                Ok(token_mint) => {
+                    mints.insert(payout.mint, token_mint);
+                }
+                Err(e) => {
+                    errors.push(format!("line {}: {}", payout.line, e));
+                    continue;
+                }
+            }
+        }
+        let token_mint = &mints[&payout.mint];
+        let result = parse_amount(&payout.amount, token_mint.decimals).and_then(|amount| {
+            if payout.mint == native_mint::id() {
+                wrapped_sol = wrapped_sol
+                    .checked_add(amount)
+                    .ok_or_else(|| Error::BatchError("the wrapped SOL payouts overflow".to_string()))?;
+            }
+            token_mint.transfer_instructions(rpc_client, wallet, &payout.recipient, wallet, amount, None)
+        });
+        match result {
+            Ok(payout_instructions) => instructions.push(payout_instructions),
+            Err(e) => errors.push(format!("line {}: {}", payout.line, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::BatchError(errors.join("; ")));
+    }
+
+    // The first payout lands in the first transaction, broadcast before any other
+    if wrapped_sol > 0 {
+        let wrap = wrap_shortfall(rpc_client, wallet, wrapped_sol)?;
+        instructions[0].splice(0..0, wrap);
+    }
+    Ok(instructions)
+}
+
+/// A transaction of a batch, and the payouts it makes
+#[derive(Clone, Debug, PartialEq)]
+pub struct PackedTransaction {
+    /// Indices of the payouts made by the transaction
+    pub payouts: Vec<usize>,
+    pub instructions: Vec<Instruction>,
+}
+
+/// Pack the instructions of each payout, in order and never split, into as few transactions as fit in a packet.
+/// A token account created by an earlier payout of the same transaction isn't created again.
+/// `build` makes the template of transaction number `i` with everything the final one has besides the payouts,
+/// so its size is the final size.
+pub fn pack(
+    payout_instructions: Vec<Vec<Instruction>>,
+    build: impl Fn(usize, Vec<Instruction>) -> Result<TransactionTemplate, Error>,
+) -> Result<Vec<PackedTransaction>, Error> {
+    let fits = |index: usize, instructions: &[Instruction]| -> Result<bool, Error> {
+        Ok(build(index, instructions.to_vec())?.transaction_size() <= PACKET_DATA_SIZE)
+    };
+    let mut packed = Vec::new();
+    let mut current = PackedTransaction { payouts: Vec::new(), instructions: Vec::new() };
+    for (payout, instructions) in payout_instructions.into_iter().enumerate() {
+        let mut candidate = current.instructions.clone();
+        candidate.extend(
+            instructions
+                .iter()
+                .filter(|ix| ix.program_id != spl_associated_token_account::id() || !current.instructions.contains(ix))
+                .cloned(),
+        );
+        if fits(packed.len(), &candidate)? {
+            current.payouts.push(payout);
+            current.instructions = candidate;
+            continue;
+        }
+        if !current.payouts.is_empty() {
+            packed.push(current);
+        }
+        if !fits(packed.len(), &instructions)? {
+            return Err(Error::BatchError(format!("payout {} doesn't fit in a transaction on its own", payout + 1)));
+        }
+        current = PackedTransaction { payouts: vec![payout], instructions };
+    }
+    if !current.payouts.is_empty() {
+        packed.push(current);
+    }
+    Ok(packed)
+}
+
+/// A transaction of a batch being signed
+#[derive(Debug, PartialEq)]
+pub struct BatchTransaction {
+    /// Indices of the payouts made by the transaction
+    pub payouts: Vec<usize>,
+    pub session: Session,
+}
+
+/// Payouts signed in one MPC session: every transaction has its own signing session and nonces,
+/// and every party runs each round for all of them at once
+#[derive(Debug, PartialEq)]
+pub struct Batch {
+    pub payouts: Vec<Payout>,
+    pub transactions: Vec<BatchTransaction>,
+}
+
+/// On-disk JSON layout of a payout
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PayoutFile {
+    line: usize,
+    recipient: String,
+    mint: String,
+    amount: String,
+}
+
+/// On-disk JSON layout of a batch transaction
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BatchTransactionFile {
+    payouts: Vec<usize>,
+    session: SessionFile,
+}
+
+/// On-disk JSON layout of a batch
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BatchFile {
+    payouts: Vec<PayoutFile>,
+    transactions: Vec<BatchTransactionFile>,
+}
+
+impl Batch {
+    /// Start the signing sessions of packed transactions, signed by the MPC wallet of `keys`
+    pub fn new(
+        payouts: Vec<Payout>,
+        keys: Vec<Pubkey>,
+        transactions: Vec<(Vec<usize>, TransactionTemplate)>,
+    ) -> Result<Self, Error> {
+        let count = transactions.len();
+        let transactions = transactions
+            .into_iter()
+            .enumerate()
+            .map(|(number, (indices, template))| {
+                let described = indices
+                    .iter()
+                    .map(|&i| format!("{} of mint {} to {}", payouts[i].amount, payouts[i].mint, payouts[i].recipient))
+                    .collect::<Vec<_>>();
+                let intent = format!("Batch payout {}/{}: {}", number + 1, count, described.join("; "));
+                Ok(BatchTransaction { payouts: indices, session: Session::new(intent, keys.clone(), template)? })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { payouts, transactions })
+    }
+
+    /// Record a round message of either kind, in the session of the transaction it belongs to
+    pub fn add_message(&mut self, message: &str) -> Result<(), Error> {
+        let session_id = match AggMessage1::deserialize_bs58(message) {
+            Ok(msg) => msg.session_id,
+            Err(_) => {
+                PartialSignature::deserialize_bs58(message)
+                    .map_err(|e| Error::BatchError(format!("Not a first message or partial signature: {}", e)))?
+                    .session_id
+            }
+        };
+        self.session_mut(&session_id)?.add_message(message)
+    }
+
+    fn session_mut(&mut self, session_id: &Hash) -> Result<&mut Session, Error> {
+        self.transactions
+            .iter_mut()
+            .map(|transaction| &mut transaction.session)
+            .find(|session| &session.session_id == session_id)
+            .ok_or_else(|| Error::BatchError(format!("no transaction of the batch is signed in session {}", session_id)))
+    }
+
+    /// Parties that haven't posted their first message for every transaction yet
+    pub fn missing_first_messages(&self) -> Vec<Pubkey> {
+        self.missing(Session::missing_first_messages)
+    }
+
+    /// Parties that haven't posted their partial signature for every transaction yet
+    pub fn missing_partial_signatures(&self) -> Vec<Pubkey> {
+        self.missing(Session::missing_partial_signatures)
+    }
+
+    fn missing(&self, missing_in: impl Fn(&Session) -> Vec<Pubkey>) -> Vec<Pubkey> {
+        let mut missing: Vec<Pubkey> = Vec::new();
+        for transaction in &self.transactions {
+            for key in missing_in(&transaction.session) {
+                if !missing.contains(&key) {
+                    missing.push(key);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Read a batch file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::BatchError(format!("Failed to read {}: {}", path.display(), e)))?;
+        let file: BatchFile = serde_json::from_str(&contents)
+            .map_err(|e| Error::BatchError(format!("Failed to parse {}: {}", path.display(), e)))?;
+
+        let parse_key = |key: &str| {
+            key.parse::<Pubkey>().map_err(|e| Error::BatchError(format!("Invalid key {}: {}", key, e)))
+        };
+        let payouts = file
+            .payouts
+            .into_iter()
+            .map(|payout| {
+                Ok(Payout {
+                    line: payout.line,
+                    recipient: parse_key(&payout.recipient)?,
+                    mint: parse_key(&payout.mint)?,
+                    amount: payout.amount,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let transactions = file
+            .transactions
+            .into_iter()
+            .enumerate()
+            .map(|(number, transaction)| {
+                if transaction.payouts.iter().any(|&i| i >= payouts.len()) {
+                    return Err(Error::BatchError(format!("transaction {} makes unknown payouts", number + 1)));
+                }
+                let source = format!("transaction {} of {}", number + 1, path.display());
+                Ok(BatchTransaction { payouts: transaction.payouts, session: transaction.session.into_session(&source)? })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { payouts, transactions })
+    }
+
+    /// Write the batch file, replacing it atomically
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let file = BatchFile {
+            payouts: self
+                .payouts
+                .iter()
+                .map(|payout| PayoutFile {
+                    line: payout.line,
+                    recipient: payout.recipient.to_string(),
+                    mint: payout.mint.to_string(),
+                    amount: payout.amount.clone(),
+                })
+                .collect(),
+            transactions: self
+                .transactions
+                .iter()
+                .map(|transaction| {
+                    Ok(BatchTransactionFile {
+                        payouts: transaction.payouts.clone(),
+                        session: SessionFile::from_session(&transaction.session)?,
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?,
+        };
+        let contents = serde_json::to_string_pretty(&file)
+            .map_err(|e| Error::BatchError(format!("Failed to encode batch: {}", e)))?;
+        write_atomically(path.as_ref(), contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_ledger, TempFile};
+    use solana_sdk::signature::{Keypair, Signer};
+    use solana_sdk::system_instruction;
+
+    #[test]
+    fn test_parse_payouts() {
+        let (recipient, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let csv = format!("Recipient, Mint, Amount\n\n# March\n{r},{m},12.5\n{r}, {m}, 3\n", r = recipient, m = mint);
+        let payouts = parse_payouts(&csv).unwrap();
+        assert_eq!(payouts.len(), 2);
+        assert_eq!(payouts[0], Payout { line: 4, recipient, mint, amount: "12.5".to_string() });
+        assert_eq!(payouts[1].line, 5);
+        assert!(parse_payouts(&format!("{},{}\n", recipient, mint)).is_err());
+
+        assert_eq!(parse_amount("12.5", 6).unwrap(), 12_500_000);
+        assert_eq!(parse_amount(".25", 2).unwrap(), 25);
+        assert_eq!(parse_amount("7", 0).unwrap(), 7);
+        for invalid in ["1.2345678", "0", "-1", "1e3", "", "."] {
+            assert!(parse_amount(invalid, 6).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_pack_and_route_messages() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+        let wallet = crate::tss::agg_key_to_pubkey(&crate::tss::key_agg(keys.clone(), None).unwrap());
+        let payouts: Vec<Payout> = (0..40)
+            .map(|i| Payout { line: i + 1, recipient: Pubkey::new_unique(), mint: Pubkey::new_unique(), amount: "1".to_string() })
+            .collect();
+        let instructions = payouts
+            .iter()
+            .map(|payout| vec![system_instruction::transfer(&wallet, &payout.recipient, 1)])
+            .collect();
+
+        let block_hash = Hash::new_unique();
+        let options = crate::template::TemplateOptions::default();
+        let build = |_: usize, instructions: Vec<Instruction>| TransactionTemplate::build(instructions, &wallet, block_hash, &options);
+        let packed = pack(instructions, &build).unwrap();
+        assert!(packed.len() > 1);
+        let order: Vec<usize> = packed.iter().flat_map(|transaction| transaction.payouts.clone()).collect();
+        assert_eq!(order, (0..40).collect::<Vec<_>>());
+
+        let transactions = packed
+            .into_iter()
+            .map(|transaction| {
+                let template = build(0, transaction.instructions).unwrap();
+                assert!(template.transaction_size() <= PACKET_DATA_SIZE);
+                (transaction.payouts, template)
+            })
+            .collect();
+        let mut batch = Batch::new(payouts, keys.clone(), transactions).unwrap();
+
+        // Each first message lands in the session of its own transaction
+        let (mut ledger, _ledger_file) = temp_ledger("batch-ledger");
+        let last = &batch.transactions[batch.transactions.len() - 1].session;
+        let (msg, _) = crate::tss::step_one(keypairs[0].insecure_clone(), last.session_id, last.message_digest(), &mut ledger).unwrap();
+        batch.add_message(&msg.serialize_bs58()).unwrap();
+        assert_eq!(batch.transactions.last().unwrap().session.missing_first_messages(), vec![keys[1]]);
+        assert_eq!(batch.missing_first_messages(), keys);
+
+        let file = TempFile::new("batch", "json");
+        batch.save(file.path()).unwrap();
+        assert_eq!(Batch::load(file.path()).unwrap(), batch);
+    }
+
+    #[test]
+    fn test_pack_creates_a_repeated_recipient_account_once() {
+        let wallet = Pubkey::new_unique();
+        let (recipient, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let create = spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            &wallet,
+            &recipient,
+            &mint,
+            &spl_token::id(),
+        );
+        let destination = spl_associated_token_account::get_associated_token_address(&recipient, &mint);
+        let source = spl_associated_token_account::get_associated_token_address(&wallet, &mint);
+        let transfer = spl_token::instruction::transfer_checked(&spl_token::id(), &source, &mint, &destination, &wallet, &[], 1, 0)
+            .unwrap();
+        // Two rows paying the same new account the same amount, like in the CSV `r,m,1` twice
+        let instructions = vec![vec![create.clone(), transfer.clone()], vec![create.clone(), transfer.clone()]];
+
+        let options = crate::template::TemplateOptions::default();
+        let build = |_: usize, instructions: Vec<Instruction>| {
+            TransactionTemplate::build(instructions, &wallet, Hash::new_unique(), &options)
+        };
+        let packed = pack(instructions, &build).unwrap();
+        assert_eq!(packed.len(), 1);
+        assert_eq!(packed[0].payouts, vec![0, 1]);
+        assert_eq!(packed[0].instructions, vec![create, transfer.clone(), transfer]);
+    }
+}
@@ -1,10 +1,27 @@
+use std::path::PathBuf;
 use std::str::FromStr;
 
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, ValueEnum};
+use solana_mpc_tokens::compute_budget::{ComputeBudget, Setting};
+use solana_mpc_tokens::token::MintOptions;
 use solana_sdk::{pubkey::Pubkey};
+use spl_token_2022::instruction::AuthorityType;
 
 use crate::error::Error;
 
+/// Per-transfer commands of earlier versions, and the template flow command that replaces each one.
+/// Their options moved to the template commands, so they fail with a pointer to the new flow.
+pub const REMOVED_COMMANDS: &[(&str, &str)] = &[
+    ("agg-message-digest-token", "template-token"),
+    ("agg-message-digest-sol", "template-sol"),
+    ("agg-send-step-two-token", "agg-send-step-two"),
+    ("agg-send-step-two-sol", "agg-send-step-two"),
+    ("aggregate-signatures-and-broadcast-token", "aggregate-signatures-and-broadcast"),
+    ("aggregate-signatures-and-broadcast-sol", "aggregate-signatures-and-broadcast"),
+    ("threshold-send-step-two-token", "threshold-send-step-two"),
+    ("threshold-aggregate-signatures-and-broadcast-token", "threshold-aggregate-signatures-and-broadcast"),
+];
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Parser)]
 #[clap(about, version, author)]
@@ -65,6 +82,10 @@ pub enum Options {
         /// Number of decimal places for the token (0-9)
         #[clap(long, default_value = "6")]
         decimals: u8,
+        #[clap(flatten)]
+        mint_args: MintArgs,
+        #[clap(flatten)]
+        compute_budget: ComputeBudgetArgs,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
@@ -85,6 +106,11 @@ pub enum Options {
         /// Amount of tokens to transfer (in smallest unit)
         #[clap(long)]
         amount: u64,
+        /// Memo to attach, required by Token-2022 accounts that only accept transfers with one
+        #[clap(long)]
+        memo: Option<String>,
+        #[clap(flatten)]
+        compute_budget: ComputeBudgetArgs,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
@@ -122,6 +148,8 @@ pub enum Options {
         /// Number of decimal places for the token
         #[clap(long, default_value = "6")]
         decimals: u8,
+        #[clap(flatten)]
+        compute_budget: ComputeBudgetArgs,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
@@ -131,50 +159,381 @@ pub enum Options {
     #[clap(display_order = 10)]
     AggSendStepOne {
         /// Private key (base58) of the party participating in MPC signing
-        private_key: String,
+        #[clap(required_unless_present = "key_share")]
+        private_key: Option<String>,
+        /// Sign with a threshold key share (base58) from dkg-finalize or threshold-keygen instead of a private key
+        #[clap(long, conflicts_with = "private_key")]
+        key_share: Option<String>,
+        /// Digest of the transaction template that will be signed (see template-token/sol)
+        #[clap(long)]
+        message_digest: String,
+        /// Signing session id shared by all parties, a new one is generated if omitted
+        #[clap(long)]
+        session_id: Option<String>,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
     },
 
-    /// Generate partial signature for MPC token transfer (Step 2)
+    /// Generate partial signature on a transaction template (Step 2)
     #[clap(display_order = 11)]
-    AggSendStepTwoToken {
+    AggSendStepTwo {
         /// Private key (base58) of the party participating in MPC signing
+        #[clap(long, required_unless_present = "key_share")]
+        private_key: Option<String>,
+        /// Sign with a threshold key share (base58) from dkg-finalize or threshold-keygen instead of a private key
+        #[clap(long, conflicts_with_all = &["private_key", "pool_index"], requires = "signers")]
+        key_share: Option<String>,
+        /// Indices of the key share holders signing together (comma-separated), instead of --keys
+        #[clap(long, value_delimiter = ',', requires = "key_share", conflicts_with = "keys")]
+        signers: Vec<u16>,
+        /// Transaction template to sign (see template-token/sol), all parties must use the same one
         #[clap(long)]
-        private_key: String,
-        /// Token mint address
-        #[clap(long)]
-        mint: Pubkey,
-        /// Amount of tokens to transfer (in smallest unit)
-        #[clap(long)]
-        amount: u64,
-        /// Number of decimal places for the token
+        template: String,
+        /// List of all participant public keys (comma-separated)
+        #[clap(long, value_delimiter = ',')]
+        keys: Vec<Pubkey>,
+        /// List of first messages from step 1 (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',', required_unless_present = "pool_index")]
+        first_messages: Vec<String>,
+        /// Secret state from step 1 (base58 string)
+        #[clap(long, required_unless_present = "pool_index")]
+        secret_state: Option<String>,
+        /// Sign with entry INDEX of the nonce pools instead of a step 1 round
+        #[clap(long, requires = "pools", conflicts_with_all = &["first_messages", "secret_state"])]
+        pool_index: Option<u16>,
+        /// Nonce pools of all participants (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        pools: Vec<String>,
+        /// Secret nonce pool file of this party (default: ~/.solana-mpc-tokens/nonce-pools/)
         #[clap(long)]
-        decimals: u8,
-        /// Public key of the recipient wallet
+        pool_file: Option<PathBuf>,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
         #[clap(long)]
-        to: Pubkey,
-        /// Recent block hash (all parties must use the same hash)
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Aggregate partial signatures and broadcast the transaction of a template (Step 3)
+    #[clap(display_order = 12)]
+    AggregateSignaturesAndBroadcast {
+        /// The transaction template every party signed
         #[clap(long)]
-        recent_block_hash: String,
+        template: String,
+        /// Key share (base58) of any holder of the group, when the parties signed with key shares
+        #[clap(long, conflicts_with = "pool_index", requires = "signers")]
+        key_share: Option<String>,
+        /// Indices of the key share holders that signed (comma-separated), instead of --keys
+        #[clap(long, value_delimiter = ',', requires = "key_share", conflicts_with = "keys")]
+        signers: Vec<u16>,
+        /// List of all partial signatures from step 2, in any order (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        signatures: Vec<String>,
         /// List of all participant public keys (comma-separated)
         #[clap(long, value_delimiter = ',')]
         keys: Vec<Pubkey>,
-        /// List of first messages from step 1 (comma-separated base58 strings)
+        /// List of the first messages of every party from step 1 (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',', required_unless_present = "pool_index")]
+        first_messages: Vec<String>,
+        /// The nonce pool entry the parties signed with, instead of step 1 messages
+        #[clap(long, requires = "pools", conflicts_with = "first_messages")]
+        pool_index: Option<u16>,
+        /// Nonce pools of all participants (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        pools: Vec<String>,
+        /// Only print the signed transaction, don't broadcast it
+        #[clap(long)]
+        no_broadcast: bool,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Split a new key into shares for a t-of-n threshold wallet (trusted dealer)
+    #[clap(display_order = 15)]
+    ThresholdKeygen {
+        /// Number of parties required to sign
+        #[clap(long)]
+        threshold: u16,
+        /// Total number of parties holding a share
+        #[clap(long)]
+        parties: u16,
+    },
+
+    /// Generate nonce commitments for threshold token transfer (Step 1)
+    #[clap(display_order = 16)]
+    ThresholdSendStepOne {
+        /// Key share (base58) of the party participating in threshold signing
+        key_share: String,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Generate partial signature on a transaction template for a threshold wallet (Step 2)
+    #[clap(display_order = 17)]
+    ThresholdSendStepTwo {
+        /// Key share (base58) of the party participating in threshold signing
+        #[clap(long)]
+        key_share: String,
+        /// Transaction template to sign (see template-token/sol), all signers must use the same one
+        #[clap(long)]
+        template: String,
+        /// List of first messages from step 1 of every signing party (comma-separated base58 strings)
         #[clap(long, value_delimiter = ',')]
         first_messages: Vec<String>,
         /// Secret state from step 1 (base58 string)
         #[clap(long)]
         secret_state: String,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Aggregate threshold partial signatures and broadcast the transaction of a template (Step 3)
+    #[clap(display_order = 18)]
+    ThresholdAggregateSignaturesAndBroadcast {
+        /// Key share (base58) of any party of the group, its commitments verify every signer's share
+        #[clap(long)]
+        key_share: String,
+        /// The transaction template every signer signed
+        #[clap(long)]
+        template: String,
+        /// List of first messages from step 1 of every signing party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        first_messages: Vec<String>,
+        /// List of partial signatures from step 2 (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        signatures: Vec<String>,
+        /// Only print the signed transaction, don't broadcast it
+        #[clap(long)]
+        no_broadcast: bool,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
     },
 
-    /// Aggregate partial signatures and broadcast token transfer transaction (Step 3)
-    #[clap(display_order = 12)]
-    AggregateSignaturesAndBroadcastToken {
-        /// List of all partial signatures from step 2 (comma-separated base58 strings)
+    /// Start a distributed key generation, no party ever holds the full key (DKG Round 1)
+    #[clap(display_order = 19)]
+    DkgCommit {
+        /// DKG ceremony id shared by all parties, a new one is generated if omitted
+        #[clap(long)]
+        ceremony_id: Option<String>,
+        /// Our participant index (1 to number of parties)
+        #[clap(long)]
+        index: u16,
+        /// Number of parties required to sign
+        #[clap(long)]
+        threshold: u16,
+        /// Total number of parties holding a share
+        #[clap(long)]
+        parties: u16,
+    },
+
+    /// Produce the private shares to send to each other party (DKG Round 2)
+    #[clap(display_order = 20)]
+    DkgShare {
+        /// Our DKG secret from round 1 (base58 string)
+        #[clap(long)]
+        secret: String,
+        /// Commitments of all parties from round 1, including ours (comma-separated base58 strings)
         #[clap(long, value_delimiter = ',')]
-        signatures: Vec<String>,
+        commitments: Vec<String>,
+    },
+
+    /// Verify the shares we received against the senders' commitments (DKG Round 3)
+    #[clap(display_order = 21)]
+    DkgVerify {
+        /// Our DKG secret from round 1 (base58 string)
+        #[clap(long)]
+        secret: String,
+        /// Commitments of all parties from round 1, including ours (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+        /// Shares addressed to us by every other party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        shares: Vec<String>,
+    },
+
+    /// Combine the received shares into our key share and the group public key (DKG Round 4)
+    #[clap(display_order = 22)]
+    DkgFinalize {
+        /// Our DKG secret from round 1 (base58 string)
+        #[clap(long)]
+        secret: String,
+        /// Commitments of all parties from round 1, including ours (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+        /// Shares addressed to us by every other party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        shares: Vec<String>,
+    },
+
+    /// Start re-randomizing the key shares of a threshold wallet without changing its address (Refresh Round 1)
+    #[clap(display_order = 23)]
+    RefreshCommit {
+        /// Refresh id shared by all parties, a new one is generated if omitted
+        #[clap(long)]
+        refresh_id: Option<String>,
+        /// Our current key share (base58)
+        #[clap(long)]
+        key_share: String,
+        /// Total number of parties holding a share, all of them must take part
+        #[clap(long)]
+        parties: u16,
+    },
+
+    /// Produce the private refresh shares to send to each other party (Refresh Round 2)
+    #[clap(display_order = 24)]
+    RefreshShare {
+        /// Our refresh secret from round 1 (base58 string)
+        #[clap(long)]
+        secret: String,
+        /// Refresh commitments of all parties from round 1, including ours (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+    },
+
+    /// Apply the received refresh shares to our key share (Refresh Round 3)
+    #[clap(display_order = 25)]
+    RefreshFinalize {
+        /// Our current key share (base58)
+        #[clap(long)]
+        key_share: String,
+        /// Our refresh secret from round 1 (base58 string)
+        #[clap(long)]
+        secret: String,
+        /// Refresh commitments of all parties from round 1, including ours (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+        /// Refresh shares addressed to us by every other party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        shares: Vec<String>,
+    },
+
+    /// Verify a refreshed key share still signs for the same wallet address
+    #[clap(display_order = 26)]
+    RefreshVerify {
+        /// The key share before the refresh (base58)
+        #[clap(long)]
+        old_key_share: String,
+        /// The key share after the refresh (base58)
+        #[clap(long)]
+        new_key_share: String,
+        /// Id of the refresh the new key share came out of
+        #[clap(long)]
+        refresh_id: String,
+        /// Refresh commitments of all parties from round 1 (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+    },
+
+    /// Describe a resharing of a threshold wallet to a new set of parties, keeping its address
+    #[clap(display_order = 27)]
+    ReshareTranscript {
+        /// Key share (base58) of any current party, used to read the wallet's public commitments
+        #[clap(long)]
+        key_share: String,
+        /// Current parties dealing the key, at least the threshold (comma-separated `index:identity` pairs)
+        #[clap(long, value_delimiter = ',')]
+        old_parties: Vec<String>,
+        /// Identities of the new parties, in the order of their new indices (comma-separated)
+        #[clap(long, value_delimiter = ',')]
+        new_parties: Vec<Pubkey>,
+        /// Number of new parties required to sign
+        #[clap(long)]
+        new_threshold: u16,
+    },
+
+    /// Sign off on a resharing transcript, every old and new party must approve it
+    #[clap(display_order = 28)]
+    ReshareApprove {
+        /// Resharing transcript (base58 string)
+        #[clap(long)]
+        transcript: String,
+        /// Private key (base58) of our long-term identity
+        #[clap(long)]
+        identity_key: String,
+    },
+
+    /// Deal our share to the new parties (run by each old party in the transcript)
+    #[clap(display_order = 29)]
+    ReshareDeal {
+        /// Our current key share (base58)
+        #[clap(long)]
+        key_share: String,
+        /// Resharing transcript (base58 string)
+        #[clap(long)]
+        transcript: String,
+        /// Approvals of every old and new party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        approvals: Vec<String>,
+    },
+
+    /// Combine the dealt shares into our new key share (run by each new party in the transcript)
+    #[clap(display_order = 30)]
+    ReshareFinalize {
+        /// Resharing transcript (base58 string)
+        #[clap(long)]
+        transcript: String,
+        /// Approvals of every old and new party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        approvals: Vec<String>,
+        /// Our identity as listed in the transcript
+        #[clap(long)]
+        identity: Pubkey,
+        /// Commitments of every old party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        commitments: Vec<String>,
+        /// Shares addressed to us by every old party (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        shares: Vec<String>,
+    },
+
+    /// Pre-generate a pool of nonces so later MPC transfers skip step 1
+    #[clap(display_order = 33)]
+    AggNoncePool {
+        /// Private key (base58) of the party participating in MPC signing
+        private_key: String,
+        /// Number of nonce pairs to generate
+        #[clap(long, default_value = "16")]
+        size: u16,
+        /// Where to keep the secret pool (default: ~/.solana-mpc-tokens/nonce-pools/)
+        #[clap(long)]
+        pool_file: Option<PathBuf>,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Create a durable nonce account so MPC transactions don't expire while parties sign
+    #[clap(display_order = 34)]
+    CreateNonceAccount {
+        /// Private key (base58) of the account paying for the nonce account
+        #[clap(long)]
+        payer_key: String,
+        /// Authority allowed to advance the nonce, usually the aggregated key
+        #[clap(long)]
+        authority: Pubkey,
+        #[clap(flatten)]
+        compute_budget: ComputeBudgetArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Print the current value of a durable nonce account, use it as the recent block hash
+    #[clap(display_order = 35)]
+    NonceValue {
+        /// The durable nonce account
+        nonce_account: Pubkey,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template of a token transfer from an MPC wallet, shared with every party
+    #[clap(display_order = 31)]
+    TemplateToken {
         /// Token mint address
         #[clap(long)]
         mint: Pubkey,
@@ -187,23 +546,19 @@ pub enum Options {
         /// Public key of the recipient wallet
         #[clap(long)]
         to: Pubkey,
-        /// Recent block hash (all parties must use the same hash)
+        /// Memo to attach, required by Token-2022 accounts that only accept transfers with one
         #[clap(long)]
-        recent_block_hash: String,
-        /// List of all participant public keys (comma-separated)
-        #[clap(long, value_delimiter = ',')]
-        keys: Vec<Pubkey>,
+        memo: Option<String>,
+        #[clap(flatten)]
+        args: TemplateArgs,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
     },
 
-    /// Generate partial signature for MPC SOL transfer (Step 2)
-    #[clap(display_order = 13)]
-    AggSendStepTwoSol {
-        /// Private key (base58) of the party participating in MPC signing
-        #[clap(long)]
-        private_key: String,
+    /// Build the transaction template of a SOL transfer from an MPC wallet, shared with every party
+    #[clap(display_order = 32)]
+    TemplateSol {
         /// Amount of SOL to transfer
         #[clap(long)]
         amount: f64,
@@ -213,50 +568,565 @@ pub enum Options {
         /// Optional memo to attach to the transaction
         #[clap(long)]
         memo: Option<String>,
-        /// Recent block hash (all parties must use the same hash)
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template of a message created by another tool, a legacy or v0 message
+    /// or an unsigned transaction, and show its instructions
+    #[clap(display_order = 63)]
+    TemplateImport {
+        /// The serialized message or unsigned transaction
         #[clap(long)]
-        recent_block_hash: String,
-        /// List of all participant public keys (comma-separated)
+        message: String,
+        /// Encoding of --message
+        #[clap(long, value_enum, default_value = "base64")]
+        encoding: Encoding,
+        /// Also start a signing session for the template in this file
+        #[clap(long, requires = "keys")]
+        session_file: Option<PathBuf>,
+        /// List of all participant public keys (comma-separated), needed with --session-file
         #[clap(long, value_delimiter = ',')]
         keys: Vec<Pubkey>,
-        /// List of first messages from step 1 (comma-separated base58 strings)
-        #[clap(long, value_delimiter = ',')]
-        first_messages: Vec<String>,
-        /// Secret state from step 1 (base58 string)
+        /// Description of the transaction shown to the parties of the session
+        #[clap(long, default_value = "Sign an imported transaction")]
+        intent: String,
+    },
+
+    /// Generate nonces for the session in a session file and add our first message to it (Step 1)
+    #[clap(display_order = 36)]
+    SessionStepOne {
+        /// Private key (base58) of the party participating in MPC signing
+        private_key: String,
+        /// The session file
+        #[clap(long)]
+        session_file: PathBuf,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Sign the template of a session file and add our partial signature to it (Step 2)
+    #[clap(display_order = 37)]
+    SessionStepTwo {
+        /// Private key (base58) of the party participating in MPC signing
+        #[clap(long)]
+        private_key: String,
+        /// The session file, with the first messages of every party
+        #[clap(long)]
+        session_file: PathBuf,
+        /// Secret state from session-step-one (base58 string)
         #[clap(long)]
         secret_state: String,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Add first messages or partial signatures produced elsewhere to a session file
+    #[clap(display_order = 38)]
+    SessionAdd {
+        /// The session file
+        #[clap(long)]
+        session_file: PathBuf,
+        /// First messages or partial signatures (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        messages: Vec<String>,
+    },
+
+    /// Show what a session file signs and which parties it is still waiting for
+    #[clap(display_order = 39)]
+    SessionShow {
+        /// The session file
+        session_file: PathBuf,
+    },
+
+    /// Aggregate the partial signatures of a session file and broadcast the transaction (Step 3)
+    #[clap(display_order = 40)]
+    SessionBroadcast {
+        /// The session file, with the partial signatures of every party
+        #[clap(long)]
+        session_file: PathBuf,
+        /// Only print the signed transaction, don't broadcast it
+        #[clap(long)]
+        no_broadcast: bool,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
     },
 
-    /// Aggregate partial signatures and broadcast SOL transfer transaction (Step 3)
-    #[clap(display_order = 14)]
-    AggregateSignaturesAndBroadcastSol {
-        /// List of all partial signatures from step 2 (comma-separated base58 strings)
-        #[clap(long, value_delimiter = ',')]
-        signatures: Vec<String>,
-        /// Amount of SOL to transfer
+    /// Relay the signing session of a session file between the parties over TCP, then broadcast the result
+    #[clap(display_order = 41)]
+    Coordinate {
+        /// The session file to sign
         #[clap(long)]
-        amount: f64,
+        session_file: PathBuf,
+        /// Address to listen on for the parties
+        #[clap(long, default_value = "127.0.0.1:7070")]
+        listen: String,
+        /// Seconds to wait for a party before aborting the session
+        #[clap(long, default_value = "300")]
+        timeout: u64,
+        /// Only print the signed transaction, don't broadcast it
+        #[clap(long)]
+        no_broadcast: bool,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Take part in a signing session relayed by a coordinator (both MPC steps in one go)
+    #[clap(display_order = 42)]
+    Participate {
+        /// Private key (base58) of the party participating in MPC signing
+        private_key: String,
+        /// Address of the coordinator
+        #[clap(long, default_value = "127.0.0.1:7070")]
+        coordinator: String,
+        /// Only sign a transaction with this digest (see template-token/sol)
+        #[clap(long)]
+        message_digest: Option<String>,
+        /// Sign without asking for confirmation
+        #[clap(long)]
+        yes: bool,
+        /// Seconds to wait for the coordinator before giving up
+        #[clap(long, default_value = "300")]
+        timeout: u64,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Run a long-lived signer for one party, driven by JSON-RPC requests over localhost or a unix socket
+    #[clap(display_order = 43)]
+    Daemon {
+        /// Private key (base58) of the party the daemon signs for
+        private_key: String,
+        /// Local address to listen on, only loopback addresses are allowed
+        #[clap(long, default_value = "127.0.0.1:7071")]
+        listen: String,
+        /// Listen on this unix socket instead of TCP
+        #[clap(long)]
+        socket: Option<PathBuf>,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Build the transaction template creating an address lookup table owned by an MPC wallet
+    #[clap(display_order = 44)]
+    TemplateCreateLookupTable {
+        /// Recent slot the table address is derived from (default: the current slot of --net)
+        #[clap(long)]
+        recent_slot: Option<u64>,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template adding addresses to a lookup table owned by an MPC wallet
+    #[clap(display_order = 45)]
+    TemplateExtendLookupTable {
+        /// The address lookup table
+        #[clap(long)]
+        lookup_table: Pubkey,
+        /// Addresses to add to the table (comma-separated)
+        #[clap(long, value_delimiter = ',', required = true)]
+        addresses: Vec<Pubkey>,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template creating a mint whose mint and freeze authority is an MPC wallet
+    #[clap(display_order = 46)]
+    TemplateCreateMint {
+        /// Number of decimal places for the token (0-9)
+        #[clap(long, default_value = "6")]
+        decimals: u8,
+        /// Seed deriving the mint address from the wallet, at most 32 characters (default: random)
+        #[clap(long)]
+        seed: Option<String>,
+        /// Leave the mint without a freeze authority
+        #[clap(long)]
+        no_freeze_authority: bool,
+        #[clap(flatten)]
+        mint_args: MintArgs,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template minting tokens with an MPC wallet as mint authority
+    #[clap(display_order = 47)]
+    TemplateMintTo {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        /// Amount of tokens to mint (in smallest unit)
+        #[clap(long)]
+        amount: u64,
+        /// Number of decimal places for the token
+        #[clap(long)]
+        decimals: u8,
         /// Public key of the recipient wallet
         #[clap(long)]
         to: Pubkey,
-        /// Optional memo to attach to the transaction
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template burning tokens held by an MPC wallet
+    #[clap(display_order = 48)]
+    TemplateBurn {
+        /// Token mint address
         #[clap(long)]
-        memo: Option<String>,
-        /// Recent block hash (all parties must use the same hash)
+        mint: Pubkey,
+        /// Amount of tokens to burn (in smallest unit)
         #[clap(long)]
-        recent_block_hash: String,
-        /// List of all participant public keys (comma-separated)
+        amount: u64,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template freezing a token account, with an MPC wallet as freeze authority
+    #[clap(display_order = 49)]
+    TemplateFreeze {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        /// Owner of the token account to freeze
+        #[clap(long)]
+        owner: Pubkey,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template thawing a frozen token account, with an MPC wallet as freeze authority
+    #[clap(display_order = 50)]
+    TemplateThaw {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        /// Owner of the token account to thaw
+        #[clap(long)]
+        owner: Pubkey,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template closing an empty token account of an MPC wallet, reclaiming its rent
+    #[clap(display_order = 51)]
+    TemplateCloseAccount {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        /// Where the rent goes (default: the wallet)
+        #[clap(long)]
+        destination: Option<Pubkey>,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template letting a delegate spend tokens of an MPC wallet
+    #[clap(display_order = 52)]
+    TemplateApprove {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        /// Public key of the delegate
+        #[clap(long)]
+        delegate: Pubkey,
+        /// Most the delegate may transfer or burn (in smallest unit)
+        #[clap(long)]
+        amount: u64,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template removing the delegate of a token account of an MPC wallet
+    #[clap(display_order = 53)]
+    TemplateRevoke {
+        /// Token mint address
+        #[clap(long)]
+        mint: Pubkey,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template handing over an authority an MPC wallet holds on a mint or token account
+    #[clap(display_order = 54)]
+    TemplateSetAuthority {
+        /// The mint or token account
+        #[clap(long)]
+        account: Pubkey,
+        /// The authority to hand over
+        #[clap(long, value_enum)]
+        authority_type: Authority,
+        /// The new authority
+        #[clap(long, required_unless_present = "remove")]
+        new_authority: Option<Pubkey>,
+        /// Remove the authority for good instead, nobody can hold it afterwards
+        #[clap(long, conflicts_with = "new_authority")]
+        remove: bool,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template wrapping SOL of an MPC wallet into its wrapped SOL token account
+    #[clap(display_order = 61)]
+    TemplateWrapSol {
+        /// Amount of SOL to wrap
+        #[clap(long)]
+        amount: f64,
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transaction template unwrapping all the wrapped SOL of an MPC wallet, closing its account
+    #[clap(display_order = 62)]
+    TemplateUnwrapSol {
+        #[clap(flatten)]
+        args: TemplateArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Build the transactions paying every row of a CSV from an MPC wallet, signed together in a batch file
+    #[clap(display_order = 55)]
+    TemplateBatch {
+        /// CSV of the payouts, one `recipient,mint,amount` row each, the amount in tokens (e.g. 12.5)
+        #[clap(long)]
+        csv: PathBuf,
+        /// The MPC wallet paying out (aggregated public key)
+        #[clap(long)]
+        wallet: Pubkey,
+        /// Recent block hash, unless every transaction uses a durable nonce
+        #[clap(long, required_unless_present = "nonce_accounts", conflicts_with = "nonce_accounts")]
+        recent_block_hash: Option<String>,
+        /// Durable nonce accounts of the wallet (comma-separated), one for each transaction of the batch
+        #[clap(long, value_delimiter = ',')]
+        nonce_accounts: Vec<Pubkey>,
+        /// Address lookup tables to load accounts from (comma-separated), building v0 messages
         #[clap(long, value_delimiter = ',')]
+        lookup_tables: Vec<Pubkey>,
+        /// The batch file to write
+        #[clap(long)]
+        batch_file: PathBuf,
+        /// List of all participant public keys (comma-separated)
+        #[clap(long, value_delimiter = ',', required = true)]
         keys: Vec<Pubkey>,
+        #[clap(flatten)]
+        compute_budget: ComputeBudgetArgs,
+        /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
+        #[clap(default_value = "localnet", long)]
+        net: Network,
+    },
+
+    /// Generate nonces for every transaction of a batch file and add our first messages to it (Step 1)
+    #[clap(display_order = 56)]
+    BatchStepOne {
+        /// Private key (base58) of the party participating in MPC signing
+        private_key: String,
+        /// The batch file
+        #[clap(long)]
+        batch_file: PathBuf,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Sign every transaction of a batch file and add our partial signatures to it (Step 2)
+    #[clap(display_order = 57)]
+    BatchStepTwo {
+        /// Private key (base58) of the party participating in MPC signing
+        #[clap(long)]
+        private_key: String,
+        /// The batch file, with the first messages of every party
+        #[clap(long)]
+        batch_file: PathBuf,
+        /// Secret states from batch-step-one (comma-separated base58 strings, in the order printed)
+        #[clap(long, value_delimiter = ',', required = true)]
+        secret_states: Vec<String>,
+        /// Nonce ledger file of this party (default: ~/.solana-mpc-tokens/nonce-ledger/)
+        #[clap(long)]
+        nonce_ledger: Option<PathBuf>,
+    },
+
+    /// Add first messages or partial signatures produced elsewhere to a batch file
+    #[clap(display_order = 58)]
+    BatchAdd {
+        /// The batch file
+        #[clap(long)]
+        batch_file: PathBuf,
+        /// First messages or partial signatures of any transaction of the batch (comma-separated base58 strings)
+        #[clap(long, value_delimiter = ',')]
+        messages: Vec<String>,
+    },
+
+    /// Show the payouts of a batch file, its transactions and which parties it is still waiting for
+    #[clap(display_order = 59)]
+    BatchShow {
+        /// The batch file
+        batch_file: PathBuf,
+    },
+
+    /// Aggregate and broadcast every transaction of a batch file, reporting the outcome of each payout (Step 3)
+    #[clap(display_order = 60)]
+    BatchBroadcast {
+        /// The batch file, with the partial signatures of every party
+        #[clap(long)]
+        batch_file: PathBuf,
+        /// Only print the signed transactions, don't broadcast them
+        #[clap(long)]
+        no_broadcast: bool,
         /// Choose the desired network: Mainnet/Testnet/Devnet/Localnet
         #[clap(default_value = "localnet", long)]
         net: Network,
     },
 }
 
+/// Authorities over a mint or token account
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Authority {
+    MintTokens,
+    FreezeAccount,
+    AccountOwner,
+    CloseAccount,
+    /// Token-2022 only
+    TransferFeeConfig,
+    /// Token-2022 only
+    WithheldWithdraw,
+    /// Token-2022 only
+    InterestRate,
+}
+
+impl Authority {
+    pub fn to_authority_type(self) -> AuthorityType {
+        match self {
+            Self::MintTokens => AuthorityType::MintTokens,
+            Self::FreezeAccount => AuthorityType::FreezeAccount,
+            Self::AccountOwner => AuthorityType::AccountOwner,
+            Self::CloseAccount => AuthorityType::CloseAccount,
+            Self::TransferFeeConfig => AuthorityType::TransferFeeConfig,
+            Self::WithheldWithdraw => AuthorityType::WithheldWithdraw,
+            Self::InterestRate => AuthorityType::InterestRate,
+        }
+    }
+}
+
+/// Token program and Token-2022 extensions of a new mint
+#[derive(Debug, Args)]
+pub struct MintArgs {
+    /// Create a Token-2022 mint instead of an SPL Token one
+    #[clap(long)]
+    pub token_2022: bool,
+    /// Transfer fee of the Token-2022 mint, in basis points of the amount transferred
+    #[clap(long, requires_all = &["token_2022", "max_transfer_fee"])]
+    pub transfer_fee_basis_points: Option<u16>,
+    /// Most a single transfer pays in fees (in smallest unit)
+    #[clap(long, requires = "transfer_fee_basis_points")]
+    pub max_transfer_fee: Option<u64>,
+    /// Interest rate of the Token-2022 mint, in basis points per year
+    #[clap(long, requires = "token_2022")]
+    pub interest_rate: Option<i16>,
+}
+
+impl MintArgs {
+    pub fn to_mint_options(&self) -> MintOptions {
+        MintOptions {
+            token_2022: self.token_2022,
+            transfer_fee: self.transfer_fee_basis_points.zip(self.max_transfer_fee),
+            interest_rate: self.interest_rate,
+        }
+    }
+}
+
+/// Arguments shared by the commands building a transaction template
+#[derive(Debug, Args)]
+pub struct TemplateArgs {
+    /// The MPC wallet signing and paying for the transaction (aggregated or group public key)
+    #[clap(long)]
+    pub wallet: Pubkey,
+    /// Recent block hash, or the current value of --nonce-account
+    #[clap(long)]
+    pub recent_block_hash: String,
+    /// Durable nonce account of the wallet, --recent-block-hash must then be its current nonce value
+    #[clap(long)]
+    pub nonce_account: Option<Pubkey>,
+    /// Address lookup tables to build a v0 transaction with (comma-separated)
+    #[clap(long, value_delimiter = ',')]
+    pub lookup_tables: Vec<Pubkey>,
+    /// Also start a signing session for the template in this file
+    #[clap(long, requires = "keys")]
+    pub session_file: Option<PathBuf>,
+    /// List of all participant public keys (comma-separated), needed with --session-file
+    #[clap(long, value_delimiter = ',')]
+    pub keys: Vec<Pubkey>,
+    #[clap(flatten)]
+    pub compute_budget: ComputeBudgetArgs,
+}
+
+/// Compute budget of a transaction, part of the template so every party signs the same fee settings
+#[derive(Debug, Args)]
+pub struct ComputeBudgetArgs {
+    /// Priority fee in micro-lamports per compute unit, or `auto` for the recent fees paid on the same accounts
+    #[clap(long)]
+    pub compute_unit_price: Option<Setting<u64>>,
+    /// Most compute units the transaction may use, or `auto` to simulate it
+    #[clap(long)]
+    pub compute_unit_limit: Option<Setting<u32>>,
+}
+
+impl ComputeBudgetArgs {
+    pub fn to_compute_budget(&self) -> ComputeBudget {
+        ComputeBudget { unit_price: self.compute_unit_price, unit_limit: self.compute_unit_limit }
+    }
+}
+
+#[derive(Debug, Clone, ValueEnum)]
+pub enum Encoding {
+    Base58,
+    Base64,
+}
+
 #[derive(Debug, Clone, ValueEnum)]
 pub enum Network {
     Mainnet,
@@ -0,0 +1,197 @@
+use std::str::FromStr;
+
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{Message, VersionedMessage};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::error::Error;
+
+/// Most compute units a transaction can ask for
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+/// A compute budget value, fixed or estimated from the cluster
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting<T> {
+    Auto,
+    Fixed(T),
+}
+
+impl<T: FromStr> FromStr for Setting<T>
+where
+    T::Err: std::fmt::Display,
+{
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Self::Auto);
+        }
+        s.parse().map(Self::Fixed).map_err(|e| format!("expected a number or `auto`: {}", e))
+    }
+}
+
+/// Priority fee and compute unit limit of a transaction, left to the cluster's defaults when unset
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ComputeBudget {
+    /// Priority fee, in micro-lamports per compute unit
+    pub unit_price: Option<Setting<u64>>,
+    /// Compute units the transaction may use
+    pub unit_limit: Option<Setting<u32>>,
+}
+
+/// Prepend the compute budget instructions for `unit_price` and `unit_limit` to `instructions`
+pub fn with_compute_budget(
+    mut instructions: Vec<Instruction>,
+    unit_price: Option<u64>,
+    unit_limit: Option<u32>,
+) -> Vec<Instruction> {
+    if let Some(unit_limit) = unit_limit {
+        instructions.insert(0, ComputeBudgetInstruction::set_compute_unit_limit(unit_limit));
+    }
+    if let Some(unit_price) = unit_price {
+        instructions.insert(0, ComputeBudgetInstruction::set_compute_unit_price(unit_price));
+    }
+    instructions
+}
+
+/// The static accounts a message writes to, the ones whose fee market matters
+fn writable_accounts(message: &VersionedMessage) -> Vec<Pubkey> {
+    let header = message.header();
+    let keys = message.static_account_keys();
+    let num_signers = usize::from(header.num_required_signatures);
+    let num_writable_signers = num_signers - usize::from(header.num_readonly_signed_accounts);
+    let num_writable_unsigned = keys.len() - usize::from(header.num_readonly_unsigned_accounts);
+    keys.iter()
+        .enumerate()
+        .filter(|(i, _)| *i < num_writable_signers || (*i >= num_signers && *i < num_writable_unsigned))
+        .map(|(_, key)| *key)
+        .collect()
+}
+
+/// Priority fee paid by the 75th percentile of the recent slots for the accounts of `message`
+pub fn estimate_unit_price(rpc_client: &RpcClient, message: &VersionedMessage) -> Result<u64, Error> {
+    let mut fees: Vec<u64> = rpc_client
+        .get_recent_prioritization_fees(&writable_accounts(message))
+        .map_err(|e| Error::ComputeBudgetError(format!("Failed to get recent prioritization fees: {}", e)))?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect();
+    if fees.is_empty() {
+        return Ok(0);
+    }
+    fees.sort_unstable();
+    Ok(fees[(fees.len() - 1) * 3 / 4])
+}
+
+/// Compute units `message` consumes in a simulation, with a 10% margin
+pub fn estimate_unit_limit(rpc_client: &RpcClient, message: &VersionedMessage) -> Result<u32, Error> {
+    let tx = VersionedTransaction {
+        signatures: vec![Signature::default(); usize::from(message.header().num_required_signatures)],
+        message: message.clone(),
+    };
+    let config = RpcSimulateTransactionConfig { sig_verify: false, replace_recent_blockhash: true, ..Default::default() };
+    let result = rpc_client
+        .simulate_transaction_with_config(&tx, config)
+        .map_err(|e| Error::ComputeBudgetError(format!("Failed to simulate the transaction: {}", e)))?
+        .value;
+    if let Some(err) = result.err {
+        return Err(Error::ComputeBudgetError(format!(
+            "the transaction fails in simulation: {}, logs: {:?}",
+            err,
+            result.logs.unwrap_or_default()
+        )));
+    }
+    let units = result
+        .units_consumed
+        .ok_or_else(|| Error::ComputeBudgetError("the simulation didn't report the compute units used".to_string()))?;
+    Ok(u32::try_from(units + units / 10).unwrap_or(MAX_COMPUTE_UNIT_LIMIT).min(MAX_COMPUTE_UNIT_LIMIT))
+}
+
+impl ComputeBudget {
+    /// Fix every setting to a value, estimating the `auto` ones.
+    /// `compile` builds the message of the transaction with a given price and limit, it's only called for estimates.
+    pub fn resolve(
+        &self,
+        rpc_client: &RpcClient,
+        compile: impl Fn(Option<u64>, Option<u32>) -> Result<VersionedMessage, Error>,
+    ) -> Result<(Option<u64>, Option<u32>), Error> {
+        let fixed_price = match self.unit_price {
+            Some(Setting::Fixed(price)) => Some(price),
+            _ => None,
+        };
+        let fixed_limit = match self.unit_limit {
+            Some(Setting::Fixed(limit)) => Some(limit),
+            _ => None,
+        };
+        if self.unit_price != Some(Setting::Auto) && self.unit_limit != Some(Setting::Auto) {
+            return Ok((fixed_price, fixed_limit));
+        }
+
+        // Simulate without a limit getting in the way, the compute budget instructions are part of what's measured
+        let draft_limit = match self.unit_limit {
+            Some(Setting::Auto) => Some(MAX_COMPUTE_UNIT_LIMIT),
+            _ => fixed_limit,
+        };
+        let draft_price = match self.unit_price {
+            Some(Setting::Auto) => Some(0),
+            _ => fixed_price,
+        };
+        let draft = compile(draft_price, draft_limit)?;
+        let unit_price = match self.unit_price {
+            Some(Setting::Auto) => Some(estimate_unit_price(rpc_client, &draft)?),
+            _ => fixed_price,
+        };
+        let unit_limit = match self.unit_limit {
+            Some(Setting::Auto) => Some(estimate_unit_limit(rpc_client, &draft)?),
+            _ => fixed_limit,
+        };
+        Ok((unit_price, unit_limit))
+    }
+
+    /// Prepend the compute budget instructions to the instructions of a transaction paid by `payer`,
+    /// estimating the `auto` settings on a legacy message
+    pub fn apply(
+        &self,
+        rpc_client: &RpcClient,
+        instructions: Vec<Instruction>,
+        payer: &Pubkey,
+    ) -> Result<Vec<Instruction>, Error> {
+        let (unit_price, unit_limit) = self.resolve(rpc_client, |price, limit| {
+            let draft = with_compute_budget(instructions.clone(), price, limit);
+            Ok(VersionedMessage::Legacy(Message::new(&draft, Some(payer))))
+        })?;
+        Ok(with_compute_budget(instructions, unit_price, unit_limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_settings_need_no_cluster() {
+        assert_eq!("auto".parse::<Setting<u64>>().unwrap(), Setting::Auto);
+        assert_eq!("5000".parse::<Setting<u64>>().unwrap(), Setting::Fixed(5000));
+        assert!("lots".parse::<Setting<u32>>().is_err());
+
+        // Nothing to estimate, so the unreachable cluster is never asked
+        let rpc_client = RpcClient::new("http://127.0.0.1:1".to_string());
+        let budget = ComputeBudget { unit_price: Some(Setting::Fixed(5000)), unit_limit: Some(Setting::Fixed(300_000)) };
+        let payer = Pubkey::new_unique();
+        let transfer = solana_sdk::system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+        let instructions = budget.apply(&rpc_client, vec![transfer.clone()], &payer).unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                ComputeBudgetInstruction::set_compute_unit_price(5000),
+                ComputeBudgetInstruction::set_compute_unit_limit(300_000),
+                transfer
+            ]
+        );
+    }
+}
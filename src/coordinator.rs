@@ -0,0 +1,406 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::error::Error;
+use crate::nonce_ledger::NonceLedger;
+use crate::serialization::{AggMessage1, Envelope, PartialSignature, Serialize, TransactionTemplate, TransportHello};
+use crate::session::Session;
+use crate::transport::{self, Transport};
+use crate::tss;
+
+/// How long a new connection has to register, parties register as soon as they connect.
+/// It is kept short so a silent connection can't hold up the parties queued behind it.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Messages between the coordinator and the parties, one JSON object per line.
+/// The coordinator only relays, round messages are encrypted and signed end-to-end between the parties
+/// so it can neither read nor alter them.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// A party announces which key it signs with
+    Register { pubkey: String },
+    /// The coordinator distributes what is being signed once every party registered
+    Session { intent: String, keys: Vec<String>, session_id: String, template: String },
+    /// A party's signed ephemeral transport key
+    Hello { message: String },
+    /// The transport keys of every party
+    Hellos { messages: Vec<String> },
+    /// Encrypted round messages, from a party to the others or relayed to their recipient
+    Envelopes { messages: Vec<String> },
+    /// The aggregated signature of the template, computed by each party
+    Signature { signature: String },
+    /// The session was aborted
+    Error { message: String },
+}
+
+/// A line-delimited JSON connection between the coordinator and a party
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    /// Wrap a TCP stream, giving up on a peer that stays silent for `timeout`
+    pub fn new(stream: TcpStream, timeout: Duration) -> Result<Self, Error> {
+        let writer = stream
+            .try_clone()
+            .map_err(|e| Error::CoordinatorError(format!("Connection setup failed: {}", e)))?;
+        let conn = Self { reader: BufReader::new(stream), writer };
+        conn.set_timeout(timeout)?;
+        Ok(conn)
+    }
+
+    /// Give up on the peer if it stays silent for `timeout` from now on
+    pub fn set_timeout(&self, timeout: Duration) -> Result<(), Error> {
+        let io_error = |e: std::io::Error| Error::CoordinatorError(format!("Connection setup failed: {}", e));
+        self.writer.set_read_timeout(Some(timeout)).map_err(io_error)?;
+        self.writer.set_write_timeout(Some(timeout)).map_err(io_error)
+    }
+
+    /// Connect to a coordinator
+    pub fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> Result<Self, Error> {
+        let stream = TcpStream::connect(addr)
+            .map_err(|e| Error::CoordinatorError(format!("Failed to connect to the coordinator: {}", e)))?;
+        Self::new(stream, timeout)
+    }
+
+    pub fn send(&mut self, msg: &Message) -> Result<(), Error> {
+        let mut line = serde_json::to_string(msg)
+            .map_err(|e| Error::CoordinatorError(format!("Failed to encode message: {}", e)))?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .map_err(|e| Error::CoordinatorError(format!("Failed to send message: {}", e)))
+    }
+
+    /// Receive the next message, an `Error` from the peer aborts
+    pub fn receive(&mut self) -> Result<Message, Error> {
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| Error::CoordinatorError(format!("Failed to receive message: {}", e)))?;
+        if read == 0 {
+            return Err(Error::CoordinatorError("Connection closed by peer".to_string()));
+        }
+        match serde_json::from_str(&line) {
+            Ok(Message::Error { message }) => Err(Error::CoordinatorError(format!("Peer aborted: {}", message))),
+            Ok(msg) => Ok(msg),
+            Err(e) => Err(Error::CoordinatorError(format!("Invalid message: {}", e))),
+        }
+    }
+}
+
+fn unexpected(expected: &str, found: &Message) -> Error {
+    Error::CoordinatorError(format!("Expected {}, received {:?}", expected, found))
+}
+
+/// Tell every party the session failed, then return the error
+fn abort(parties: &mut [(Pubkey, Connection)], err: Error) -> Error {
+    for (_, conn) in parties.iter_mut() {
+        let _ = conn.send(&Message::Error { message: err.to_string() });
+    }
+    err
+}
+
+/// Receive a message from every party in turn, aborting the session for everyone if any of them fails
+fn receive_all<T>(
+    parties: &mut [(Pubkey, Connection)],
+    mut check: impl FnMut(&Pubkey, Message) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    let mut received = Vec::with_capacity(parties.len());
+    for i in 0..parties.len() {
+        let result = parties[i].1.receive().and_then(|msg| check(&parties[i].0, msg));
+        match result {
+            Ok(value) => received.push(value),
+            Err(e) => return Err(abort(parties, e)),
+        }
+    }
+    Ok(received)
+}
+
+/// Parse the envelopes a party sent for `round`, exactly one signed envelope for every other party
+fn check_envelopes(
+    sender: &Pubkey,
+    msg: Message,
+    keys: &[Pubkey],
+    session_id: &Hash,
+    round: u8,
+) -> Result<Vec<Envelope>, Error> {
+    let envelopes = match msg {
+        Message::Envelopes { messages } => {
+            messages.iter().map(Envelope::deserialize_bs58).collect::<Result<Vec<_>, _>>()?
+        }
+        other => return Err(unexpected("round messages", &other)),
+    };
+    for envelope in &envelopes {
+        if &envelope.sender != sender || envelope.round != round {
+            return Err(Error::TransportError(format!(
+                "{} sent a round {} message as {}",
+                sender, envelope.round, envelope.sender
+            )));
+        }
+        transport::verify_envelope(envelope, session_id)?;
+    }
+    let mut recipients: Vec<Pubkey> = envelopes.iter().map(|envelope| envelope.recipient).collect();
+    let mut expected: Vec<Pubkey> = keys.iter().filter(|key| *key != sender).copied().collect();
+    recipients.sort();
+    expected.sort();
+    if recipients != expected {
+        return Err(Error::TransportError(format!("{} didn't send one message to every other party", sender)));
+    }
+    Ok(envelopes)
+}
+
+/// Relay one round: collect the envelopes of every party and deliver each to its recipient
+fn relay_round(parties: &mut [(Pubkey, Connection)], session: &Session, round: u8) -> Result<(), Error> {
+    let envelopes: Vec<Envelope> = receive_all(parties, |sender, msg| {
+        check_envelopes(sender, msg, &session.keys, &session.session_id, round)
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    for (pubkey, conn) in parties.iter_mut() {
+        let messages = envelopes
+            .iter()
+            .filter(|envelope| &envelope.recipient == pubkey)
+            .map(|envelope| envelope.serialize_bs58())
+            .collect();
+        conn.send(&Message::Envelopes { messages })?;
+    }
+    Ok(())
+}
+
+/// Relay one signing session: wait until every party of `session` registered, distribute the session
+/// and the parties' transport keys, relay both encrypted rounds, and return the transaction the parties signed.
+pub fn coordinate(listener: &TcpListener, session: &Session, timeout: Duration) -> Result<VersionedTransaction, Error> {
+    // Registration, anyone can connect but only the parties of the session get in, once.
+    // A party can't be impersonated past this point, the transport keys are signed by its identity.
+    let mut parties: Vec<(Pubkey, Connection)> = Vec::with_capacity(session.keys.len());
+    while parties.len() < session.keys.len() {
+        let (stream, _) = listener
+            .accept()
+            .map_err(|e| Error::CoordinatorError(format!("Failed to accept a connection: {}", e)))?;
+        let mut conn = match Connection::new(stream, timeout.min(REGISTRATION_TIMEOUT)) {
+            Ok(conn) => conn,
+            Err(_) => continue,
+        };
+        let pubkey = match conn.receive() {
+            Ok(Message::Register { pubkey }) => pubkey.parse::<Pubkey>().ok(),
+            _ => None,
+        };
+        match pubkey {
+            Some(pubkey) if session.keys.contains(&pubkey) && !parties.iter().any(|(p, _)| p == &pubkey) => {
+                conn.set_timeout(timeout)?;
+                parties.push((pubkey, conn))
+            }
+            _ => {
+                let _ = conn.send(&Message::Error { message: "Not a party of this session".to_string() });
+            }
+        }
+    }
+
+    let session_msg = Message::Session {
+        intent: session.intent.clone(),
+        keys: session.keys.iter().map(|key| key.to_string()).collect(),
+        session_id: session.session_id.to_string(),
+        template: session.template.serialize_bs58(),
+    };
+    for (_, conn) in parties.iter_mut() {
+        conn.send(&session_msg)?;
+    }
+
+    // Transport keys
+    let hellos = receive_all(&mut parties, |sender, msg| {
+        let hello = match msg {
+            Message::Hello { message } => TransportHello::deserialize_bs58(&message)?,
+            other => return Err(unexpected("a transport key", &other)),
+        };
+        if &hello.sender != sender {
+            return Err(Error::TransportError(format!("{} sent a transport key as {}", sender, hello.sender)));
+        }
+        transport::verify_hello(&hello, &session.session_id)?;
+        Ok(hello.serialize_bs58())
+    })?;
+    for (_, conn) in parties.iter_mut() {
+        conn.send(&Message::Hellos { messages: hellos.clone() })?;
+    }
+
+    relay_round(&mut parties, session, transport::ROUND_FIRST_MESSAGE)?;
+    relay_round(&mut parties, session, transport::ROUND_PARTIAL_SIGNATURE)?;
+
+    // Every party aggregates on its own, they must all end up with the same valid transaction
+    let transactions = receive_all(&mut parties, |sender, msg| match msg {
+        Message::Signature { signature } => {
+            let signature = signature
+                .parse::<Signature>()
+                .map_err(|e| Error::CoordinatorError(format!("Invalid signature from {}: {}", sender, e)))?;
+            session.template.to_transaction(signature)
+        }
+        other => Err(unexpected("the signature", &other)),
+    })?;
+    let mut transactions = transactions.into_iter();
+    let tx = transactions.next().expect("a session has at least one party");
+    if transactions.any(|other| other != tx) {
+        let err = Error::CoordinatorError("The parties signed different transactions".to_string());
+        return Err(abort(&mut parties, err));
+    }
+    Ok(tx)
+}
+
+/// Seal `message` for every other party of the session
+fn seal_for_others(
+    channels: &Transport,
+    session: &Session,
+    me: &Pubkey,
+    round: u8,
+    message: &[u8],
+) -> Result<Message, Error> {
+    let messages = session
+        .keys
+        .iter()
+        .filter(|key| *key != me)
+        .map(|key| channels.seal(key, round, message).map(|envelope| envelope.serialize_bs58()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Message::Envelopes { messages })
+}
+
+/// Open the envelopes relayed to us for `round`
+fn open_relayed(conn: &mut Connection, channels: &Transport, round: u8) -> Result<Vec<(Pubkey, Vec<u8>)>, Error> {
+    match conn.receive()? {
+        Message::Envelopes { messages } => messages
+            .iter()
+            .map(|message| {
+                let envelope = Envelope::deserialize_bs58(message)?;
+                Ok((envelope.sender, channels.open(&envelope, round)?))
+            })
+            .collect(),
+        other => Err(unexpected("round messages", &other)),
+    }
+}
+
+/// Take part in a session relayed by the coordinator at `addr`.
+/// `approve` sees the session before any nonce is generated, and can refuse to sign it.
+/// Round messages are exchanged end-to-end encrypted with the other parties, and every party aggregates on its own.
+pub fn participate(
+    addr: impl ToSocketAddrs,
+    keypair: Keypair,
+    ledger: &mut NonceLedger,
+    timeout: Duration,
+    approve: impl FnOnce(&Session) -> Result<(), Error>,
+) -> Result<(Session, VersionedTransaction), Error> {
+    let me = keypair.pubkey();
+    let mut conn = Connection::connect(addr, timeout)?;
+    conn.send(&Message::Register { pubkey: me.to_string() })?;
+
+    let mut session = match conn.receive()? {
+        Message::Session { intent, keys, session_id, template } => {
+            let keys = keys
+                .iter()
+                .map(|key| key.parse::<Pubkey>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| Error::CoordinatorError(format!("Invalid key in the session: {}", e)))?;
+            let session_id = session_id
+                .parse()
+                .map_err(|e| Error::CoordinatorError(format!("Invalid session id: {}", e)))?;
+            Session::join(intent, keys, session_id, TransactionTemplate::deserialize_bs58(&template)?)?
+        }
+        other => return Err(unexpected("the session", &other)),
+    };
+    if let Err(e) = approve(&session) {
+        let _ = conn.send(&Message::Error { message: format!("{} refused to sign: {}", me, e) });
+        return Err(e);
+    }
+
+    // Transport keys
+    let mut channels = Transport::new(&keypair, session.session_id);
+    conn.send(&Message::Hello { message: channels.hello().serialize_bs58() })?;
+    match conn.receive()? {
+        Message::Hellos { messages } => {
+            let hellos = messages.iter().map(TransportHello::deserialize_bs58).collect::<Result<Vec<_>, _>>()?;
+            channels.add_peers(&hellos, &session.keys)?;
+        }
+        other => return Err(unexpected("the transport keys", &other)),
+    }
+
+    // Round one
+    let (first_message, secret_state) =
+        tss::step_one(keypair.insecure_clone(), session.session_id, session.message_digest(), ledger)?;
+    let mut bytes = Vec::with_capacity(first_message.size_hint());
+    first_message.serialize(&mut bytes);
+    conn.send(&seal_for_others(&channels, &session, &me, transport::ROUND_FIRST_MESSAGE, &bytes)?)?;
+    session.add_first_message(first_message)?;
+    for (sender, bytes) in open_relayed(&mut conn, &channels, transport::ROUND_FIRST_MESSAGE)? {
+        let msg = AggMessage1::deserialize(&bytes)?;
+        if msg.sender != sender {
+            return Err(Error::SessionMismatch(format!("{} sent a first message as {}", sender, msg.sender)));
+        }
+        session.add_first_message(msg)?;
+    }
+
+    // Round two
+    let first_messages = session.first_messages_of_others(&me);
+    let partial_signature =
+        tss::step_two(keypair, &session.template, session.keys.clone(), first_messages, secret_state, ledger)?;
+    let mut bytes = Vec::with_capacity(partial_signature.size_hint());
+    partial_signature.serialize(&mut bytes);
+    conn.send(&seal_for_others(&channels, &session, &me, transport::ROUND_PARTIAL_SIGNATURE, &bytes)?)?;
+    session.add_partial_signature(partial_signature)?;
+    for (sender, bytes) in open_relayed(&mut conn, &channels, transport::ROUND_PARTIAL_SIGNATURE)? {
+        let sig = PartialSignature::deserialize(&bytes)?;
+        if sig.sender != sender {
+            return Err(Error::SessionMismatch(format!("{} sent a partial signature as {}", sender, sig.sender)));
+        }
+        session.add_partial_signature(sig)?;
+    }
+
+    // Aggregate ourselves, blaming whoever sent an invalid share
+    let tx = tss::sign_and_broadcast(
+        &session.template,
+        session.keys.clone(),
+        session.first_messages.clone(),
+        session.partial_signatures.clone(),
+    )?;
+    conn.send(&Message::Signature { signature: tx.signatures[0].to_string() })?;
+    Ok((session, tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sol_transfer_session, temp_ledger};
+
+    #[test]
+    fn test_coordinated_session_between_threads() {
+        let (keypairs, session) = sol_transfer_session(3);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeout = Duration::from_secs(30);
+        // A connection that never registers only holds up the others for the registration timeout
+        let _silent = TcpStream::connect(addr).unwrap();
+        let parties: Vec<_> = keypairs
+            .into_iter()
+            .map(|keypair| {
+                std::thread::spawn(move || {
+                    let (mut ledger, _ledger_file) = temp_ledger("coordinator-ledger");
+                    participate(addr, keypair, &mut ledger, timeout, |_| Ok(())).unwrap().1
+                })
+            })
+            .collect();
+
+        let tx = coordinate(&listener, &session, timeout).unwrap();
+        for party in parties {
+            assert_eq!(party.join().unwrap(), tx);
+        }
+        assert!(tx.verify().is_ok());
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::error::Error;
+use crate::nonce_ledger::NonceLedger;
+use crate::serialization::{Serialize, TransactionTemplate};
+use crate::session::Session;
+use crate::signing::{NoncesGenerated, PartiallySigned, SignError, SigningSession};
+use crate::tss;
+
+/// JSON-RPC error code of a request that isn't valid JSON-RPC
+const INVALID_REQUEST: i64 = -32600;
+/// JSON-RPC error code of an unknown method
+const METHOD_NOT_FOUND: i64 = -32601;
+/// JSON-RPC error code of invalid method parameters
+const INVALID_PARAMS: i64 = -32602;
+/// JSON-RPC error code of a request the signer refused or failed
+const SIGNER_ERROR: i64 = -32000;
+
+#[derive(serde::Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(serde::Deserialize)]
+struct StartSessionParams {
+    template: String,
+    keys: Vec<String>,
+    /// Join this session, a new one is started if omitted
+    session_id: Option<String>,
+    #[serde(default)]
+    intent: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SessionParams {
+    session_id: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SubmitParams {
+    session_id: String,
+    messages: Vec<String>,
+}
+
+/// A session this party takes part in, before or after it signed
+enum PartySession {
+    NoncesGenerated(SigningSession<NoncesGenerated>),
+    PartiallySigned(SigningSession<PartiallySigned>),
+}
+
+impl PartySession {
+    fn session(&self) -> &Session {
+        match self {
+            Self::NoncesGenerated(signing) => signing.session(),
+            Self::PartiallySigned(signing) => signing.session(),
+        }
+    }
+
+    fn add_message(&mut self, message: &str) -> Result<(), Error> {
+        match self {
+            Self::NoncesGenerated(signing) => signing.add_message(message),
+            Self::PartiallySigned(signing) => signing.add_message(message),
+        }
+    }
+}
+
+/// Long-running signer of one party. It holds the party's key and the secret state of its open sessions,
+/// and is driven through JSON-RPC 2.0 requests, one per line.
+///
+/// Methods:
+/// - `pubkey`: the party's public key
+/// - `start_session {template, keys, session_id?, intent?}`: generate our nonces, returns our first message
+/// - `submit {session_id, messages}`: add other parties' first messages or partial signatures
+/// - `sign {session_id}`: sign the template once every first message arrived, returns our partial signature
+/// - `status {session_id}`: missing messages, and the signed transaction once every partial signature arrived
+/// - `sessions`: the ids of all open sessions
+pub struct Daemon {
+    keypair: Keypair,
+    ledger: NonceLedger,
+    sessions: HashMap<Hash, PartySession>,
+}
+
+fn invalid_params(e: impl std::fmt::Display) -> (i64, String) {
+    (INVALID_PARAMS, format!("Invalid params: {}", e))
+}
+
+fn parse_session_id(session_id: &str) -> Result<Hash, (i64, String)> {
+    session_id.parse::<Hash>().map_err(invalid_params)
+}
+
+fn signer_error(e: Error) -> (i64, String) {
+    (SIGNER_ERROR, e.to_string())
+}
+
+fn keys_to_strings(keys: Vec<Pubkey>) -> Vec<String> {
+    keys.iter().map(|key| key.to_string()).collect()
+}
+
+impl Daemon {
+    pub fn new(keypair: Keypair, ledger: NonceLedger) -> Self {
+        Self { keypair, ledger, sessions: HashMap::new() }
+    }
+
+    /// Handle one JSON-RPC request line and return the response line
+    pub fn handle(&mut self, line: &str) -> String {
+        let response = match serde_json::from_str::<Request>(line) {
+            Ok(request) => {
+                let id = request.id.clone();
+                match self.call(&request.method, request.params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err((code, message)) => {
+                        json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+                    }
+                }
+            }
+            Err(e) => json!({
+                "jsonrpc": "2.0",
+                "id": Value::Null,
+                "error": { "code": INVALID_REQUEST, "message": format!("Invalid request: {}", e) }
+            }),
+        };
+        response.to_string()
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, (i64, String)> {
+        match method {
+            "pubkey" => Ok(json!({ "pubkey": self.keypair.pubkey().to_string() })),
+            "start_session" => self.start_session(serde_json::from_value(params).map_err(invalid_params)?),
+            "submit" => self.submit(serde_json::from_value(params).map_err(invalid_params)?),
+            "sign" => self.sign(serde_json::from_value(params).map_err(invalid_params)?),
+            "status" => self.status(serde_json::from_value(params).map_err(invalid_params)?),
+            "sessions" => {
+                Ok(json!({ "sessions": self.sessions.keys().map(|id| id.to_string()).collect::<Vec<_>>() }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+        }
+    }
+
+    fn party_session(&mut self, session_id: &str) -> Result<&mut PartySession, (i64, String)> {
+        let session_id = parse_session_id(session_id)?;
+        self.sessions
+            .get_mut(&session_id)
+            .ok_or_else(|| (SIGNER_ERROR, format!("Unknown session: {}", session_id)))
+    }
+
+    fn start_session(&mut self, params: StartSessionParams) -> Result<Value, (i64, String)> {
+        let keys = params
+            .keys
+            .iter()
+            .map(|key| key.parse::<Pubkey>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid_params)?;
+        let template = TransactionTemplate::deserialize_bs58(&params.template).map_err(invalid_params)?;
+        let session = match params.session_id {
+            Some(session_id) => Session::join(params.intent, keys, parse_session_id(&session_id)?, template),
+            None => Session::new(params.intent, keys, template),
+        }
+        .map_err(signer_error)?;
+        if self.sessions.contains_key(&session.session_id) {
+            return Err((SIGNER_ERROR, format!("Already in session {}", session.session_id)));
+        }
+
+        let signing =
+            SigningSession::start(self.keypair.insecure_clone(), session, &mut self.ledger).map_err(signer_error)?;
+        let result = json!({
+            "session_id": signing.session().session_id.to_string(),
+            "message_digest": signing.session().message_digest().to_string(),
+            "first_message": signing.first_message().serialize_bs58(),
+        });
+        self.sessions.insert(signing.session().session_id, PartySession::NoncesGenerated(signing));
+        Ok(result)
+    }
+
+    fn submit(&mut self, params: SubmitParams) -> Result<Value, (i64, String)> {
+        let party_session = self.party_session(&params.session_id)?;
+        for message in &params.messages {
+            party_session.add_message(message).map_err(signer_error)?;
+        }
+        Ok(json!({ "accepted": params.messages.len() }))
+    }
+
+    fn sign(&mut self, params: SessionParams) -> Result<Value, (i64, String)> {
+        let session_id = parse_session_id(&params.session_id)?;
+        let signing = match self.sessions.remove(&session_id) {
+            Some(PartySession::NoncesGenerated(signing)) => signing,
+            Some(party_session) => {
+                self.sessions.insert(session_id, party_session);
+                return Err(signer_error(Error::NonceReused(format!("session {} was already signed", session_id))));
+            }
+            None => return Err((SIGNER_ERROR, format!("Unknown session: {}", session_id))),
+        };
+
+        match signing.sign(&mut self.ledger) {
+            Ok(signing) => {
+                let result = json!({ "partial_signature": signing.partial_signature().serialize_bs58() });
+                self.sessions.insert(session_id, PartySession::PartiallySigned(signing));
+                Ok(result)
+            }
+            Err(SignError::NotReady(signing, e)) => {
+                self.sessions.insert(session_id, PartySession::NoncesGenerated(signing));
+                Err(signer_error(e))
+            }
+            // Signing consumes the nonces for good, a session that failed to sign is dropped
+            Err(SignError::Failed(e)) => Err(signer_error(e)),
+        }
+    }
+
+    fn status(&mut self, params: SessionParams) -> Result<Value, (i64, String)> {
+        let session = self.party_session(&params.session_id)?.session();
+        let missing_first_messages = session.missing_first_messages();
+        let missing_partial_signatures = session.missing_partial_signatures();
+
+        // Once complete, the aggregated transaction is ready for whoever broadcasts it
+        let transaction = if missing_partial_signatures.is_empty() {
+            let tx = tss::sign_and_broadcast(
+                &session.template,
+                session.keys.clone(),
+                session.first_messages.clone(),
+                session.partial_signatures.clone(),
+            )
+            .map_err(signer_error)?;
+            let tx_bytes = bincode::serialize(&tx)
+                .map_err(|e| signer_error(Error::SerializationError(format!("Failed to encode transaction: {}", e))))?;
+            Some(json!({ "signature": tx.signatures[0].to_string(), "transaction": bs58::encode(tx_bytes).into_string() }))
+        } else {
+            None
+        };
+        Ok(json!({
+            "session_id": session.session_id.to_string(),
+            "intent": session.intent,
+            "message_digest": session.message_digest().to_string(),
+            "missing_first_messages": keys_to_strings(missing_first_messages),
+            "missing_partial_signatures": keys_to_strings(missing_partial_signatures),
+            "transaction": transaction,
+        }))
+    }
+}
+
+/// Answer the requests of one client until it disconnects
+fn serve_connection(daemon: &Mutex<Daemon>, reader: impl std::io::Read, mut writer: impl Write) {
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let response = daemon.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).handle(&line);
+        if writer.write_all(format!("{}\n", response).as_bytes()).and_then(|_| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Serve the daemon on a local TCP address, only loopback addresses are accepted
+pub fn serve_tcp(daemon: Daemon, addr: SocketAddr) -> Result<(), Error> {
+    if !addr.ip().is_loopback() {
+        return Err(Error::DaemonError(format!("refusing to listen on {}, the daemon only serves localhost", addr)));
+    }
+    let listener =
+        TcpListener::bind(addr).map_err(|e| Error::DaemonError(format!("Failed to listen on {}: {}", addr, e)))?;
+    let daemon = Arc::new(Mutex::new(daemon));
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| Error::DaemonError(format!("Failed to accept a connection: {}", e)))?;
+        let writer = stream.try_clone().map_err(|e| Error::DaemonError(format!("Connection setup failed: {}", e)))?;
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || serve_connection(&daemon, stream, writer));
+    }
+    Ok(())
+}
+
+/// Serve the daemon on a unix socket, only accessible to the owner
+#[cfg(unix)]
+pub fn serve_unix(daemon: Daemon, path: &std::path::Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let io_error = |e: std::io::Error| Error::DaemonError(format!("Failed to listen on {}: {}", path.display(), e));
+    let listener = UnixListener::bind(path).map_err(io_error)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).map_err(io_error)?;
+    let daemon = Arc::new(Mutex::new(daemon));
+    for stream in listener.incoming() {
+        let stream = stream.map_err(|e| Error::DaemonError(format!("Failed to accept a connection: {}", e)))?;
+        let writer = stream.try_clone().map_err(|e| Error::DaemonError(format!("Connection setup failed: {}", e)))?;
+        let daemon = Arc::clone(&daemon);
+        std::thread::spawn(move || serve_connection(&daemon, stream, writer));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sol_transfer_session, temp_ledger};
+
+    fn call(daemon: &mut Daemon, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+        let response: Value = serde_json::from_str(&daemon.handle(&request)).unwrap();
+        assert!(response.get("error").is_none(), "{} failed: {}", method, response);
+        response["result"].clone()
+    }
+
+    #[test]
+    fn test_two_daemons_sign_a_template() {
+        let (keypairs, session) = sol_transfer_session(2);
+        let template = session.template;
+
+        let (ledgers, _ledger_files): (Vec<_>, Vec<_>) = (0..2).map(|_| temp_ledger("daemon-ledger")).unzip();
+        let mut daemons: Vec<Daemon> = keypairs
+            .into_iter()
+            .zip(ledgers)
+            .map(|(keypair, ledger)| Daemon::new(keypair, ledger))
+            .collect();
+
+        let keys = keys_to_strings(session.keys);
+        let first = call(&mut daemons[0], "start_session", json!({ "template": template.serialize_bs58(), "keys": keys }));
+        let session_id = first["session_id"].clone();
+        let second = call(
+            &mut daemons[1],
+            "start_session",
+            json!({ "template": template.serialize_bs58(), "keys": keys, "session_id": session_id }),
+        );
+        call(&mut daemons[0], "submit", json!({ "session_id": session_id, "messages": [second["first_message"]] }));
+        call(&mut daemons[1], "submit", json!({ "session_id": session_id, "messages": [first["first_message"]] }));
+
+        let sig0 = call(&mut daemons[0], "sign", json!({ "session_id": session_id }));
+        let sig1 = call(&mut daemons[1], "sign", json!({ "session_id": session_id }));
+        call(&mut daemons[0], "submit", json!({ "session_id": session_id, "messages": [sig1["partial_signature"]] }));
+
+        let status = call(&mut daemons[0], "status", json!({ "session_id": session_id }));
+        assert!(status["transaction"]["signature"].is_string());
+
+        // Signing twice is refused
+        let request = json!({ "jsonrpc": "2.0", "id": 2, "method": "sign", "params": { "session_id": session_id } });
+        let response: Value = serde_json::from_str(&daemons[0].handle(&request.to_string())).unwrap();
+        assert_eq!(response["error"]["code"], SIGNER_ERROR);
+        assert!(sig0["partial_signature"].is_string());
+    }
+}
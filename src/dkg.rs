@@ -0,0 +1,565 @@
+#![allow(non_snake_case)]
+
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use solana_sdk::hash::Hash;
+
+use crate::error::Error;
+use crate::frost::{evaluate_commitments, index_to_scalar, validate_threshold, Polynomial};
+use crate::serialization::{DkgCommitment, DkgSecret, DkgShare, RefreshCommitment, ThresholdKeyShare};
+use crate::tss::hash_to_scalar;
+
+/// Domain separator for the proof of knowledge of each party's secret
+const PROOF_DOMAIN: &[u8] = b"solana-mpc-tokens/dkg/proof-of-knowledge";
+
+/// Domain separator for the proof of knowledge of each party's refresh polynomial
+const REFRESH_PROOF_DOMAIN: &[u8] = b"solana-mpc-tokens/dkg/refresh-proof-of-knowledge";
+
+/// Challenge of the Schnorr proof that the sender knows the discrete log of `public`.
+/// It's bound to the ceremony, so a commitment can't be replayed into another DKG or refresh run.
+fn proof_challenge(
+    domain: &[u8],
+    ceremony_id: &Hash,
+    sender_index: u16,
+    public: &Point<Ed25519>,
+    R: &Point<Ed25519>,
+) -> Scalar<Ed25519> {
+    hash_to_scalar(&[
+        domain,
+        &ceremony_id.to_bytes(),
+        &sender_index.to_le_bytes(),
+        &public.to_bytes(true),
+        &R.to_bytes(true),
+    ])
+}
+
+/// Prove knowledge of `secret`, the discrete log of `public`, giving `(R, mu)`
+fn prove_knowledge(
+    domain: &[u8],
+    ceremony_id: &Hash,
+    sender_index: u16,
+    secret: &Scalar<Ed25519>,
+    public: &Point<Ed25519>,
+) -> (Point<Ed25519>, Scalar<Ed25519>) {
+    let k = Scalar::random();
+    let R = Point::generator() * &k;
+    let challenge = proof_challenge(domain, ceremony_id, sender_index, public, &R);
+    (R, k + challenge * secret)
+}
+
+/// Check a proof of knowledge made with `prove_knowledge`
+fn verify_knowledge(
+    domain: &[u8],
+    ceremony_id: &Hash,
+    sender_index: u16,
+    public: &Point<Ed25519>,
+    R: &Point<Ed25519>,
+    mu: &Scalar<Ed25519>,
+) -> bool {
+    let challenge = proof_challenge(domain, ceremony_id, sender_index, public, R);
+    Point::generator() * mu == R + public * &challenge
+}
+
+/// Round one of the DKG: sample our own sharing polynomial, commit to it and prove knowledge of its secret.
+/// Every party uses the same fresh `ceremony_id`.
+/// The commitment is broadcast to every party, the secret stays with us until `finalize`.
+pub fn commit(ceremony_id: Hash, index: u16, threshold: u16, parties: u16) -> Result<(DkgCommitment, DkgSecret), Error> {
+    validate_threshold(threshold, parties)?;
+    if index == 0 || index > parties {
+        return Err(Error::DkgFailed(format!("participant index must be between 1 and {}, got {}", parties, index)));
+    }
+
+    let polynomial = Polynomial::sample(threshold - 1, Scalar::random());
+    let commitments = polynomial.commit();
+
+    let (proof_R, proof_mu) =
+        prove_knowledge(PROOF_DOMAIN, &ceremony_id, index, &polynomial.coefficients()[0], &commitments[0]);
+
+    Ok((
+        DkgCommitment { ceremony_id, sender_index: index, commitments, proof_R, proof_mu },
+        DkgSecret { index, threshold, parties, coefficients: polynomial.coefficients().to_vec() },
+    ))
+}
+
+/// Check that every party broadcast exactly one well formed commitment for our ceremony,
+/// with a valid proof of knowledge
+fn verify_commitments(secret: &DkgSecret, commitments: &[DkgCommitment]) -> Result<(), Error> {
+    if commitments.len() != usize::from(secret.parties) {
+        return Err(Error::DkgFailed(format!(
+            "expected commitments from {} parties, found {}",
+            secret.parties,
+            commitments.len()
+        )));
+    }
+
+    // Our own commitment must be the one we generated, it tells which ceremony we're in
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+    let ours = commitments.iter().find(|c| c.sender_index == secret.index).ok_or(Error::KeyPairIsNotInKeys)?;
+    if ours.commitments != polynomial.commit() {
+        return Err(Error::DkgFailed("our own commitment doesn't match the DKG secret".to_string()));
+    }
+
+    for index in 1..=secret.parties {
+        let mut from_index = commitments.iter().filter(|c| c.sender_index == index);
+        let commitment = from_index
+            .next()
+            .ok_or_else(|| Error::DkgFailed(format!("missing commitment from party {}", index)))?;
+        if from_index.next().is_some() {
+            return Err(Error::DkgFailed(format!("party {} sent more than one commitment", index)));
+        }
+        if commitment.commitments.len() != usize::from(secret.threshold) {
+            return Err(Error::DkgFailed(format!(
+                "party {} committed to a polynomial of the wrong degree",
+                index
+            )));
+        }
+
+        if commitment.ceremony_id != ours.ceremony_id {
+            return Err(Error::DkgFailed(format!(
+                "party {} committed for ceremony {}, not {}",
+                index, commitment.ceremony_id, ours.ceremony_id
+            )));
+        }
+
+        if !verify_knowledge(
+            PROOF_DOMAIN,
+            &commitment.ceremony_id,
+            index,
+            &commitment.commitments[0],
+            &commitment.proof_R,
+            &commitment.proof_mu,
+        ) {
+            return Err(Error::DkgFailed(format!("party {} sent an invalid proof of knowledge", index)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Round two of the DKG: evaluate our polynomial for every other party.
+/// Each returned share must be delivered privately to its receiver.
+pub fn share(secret: &DkgSecret, commitments: &[DkgCommitment]) -> Result<Vec<DkgShare>, Error> {
+    verify_commitments(secret, commitments)?;
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+
+    Ok((1..=secret.parties)
+        .filter(|&receiver_index| receiver_index != secret.index)
+        .map(|receiver_index| DkgShare {
+            sender_index: secret.index,
+            receiver_index,
+            share: polynomial.evaluate(receiver_index),
+        })
+        .collect())
+}
+
+/// Check that every sender delivered exactly one share to us, matching the point `f_sender(our index) * G`
+/// expected from its commitments, and name every party that didn't
+pub fn check_received_shares(
+    receiver_index: u16,
+    expected: impl Iterator<Item = (u16, Point<Ed25519>)>,
+    shares: &[DkgShare],
+) -> Result<(), Error> {
+    let mut senders = 0;
+    let mut misbehaving = Vec::new();
+    for (sender_index, expected_point) in expected {
+        senders += 1;
+        let mut from_sender = shares.iter().filter(|s| s.sender_index == sender_index);
+        let valid = match (from_sender.next(), from_sender.next()) {
+            (Some(share), None) => {
+                share.receiver_index == receiver_index && Point::generator() * &share.share == expected_point
+            }
+            _ => false,
+        };
+        if !valid {
+            misbehaving.push(sender_index.to_string());
+        }
+    }
+
+    if !misbehaving.is_empty() {
+        return Err(Error::DkgFailed(format!(
+            "missing or invalid shares from parties: {}",
+            misbehaving.join(", ")
+        )));
+    }
+    if shares.len() != senders {
+        return Err(Error::DkgFailed(format!("expected {} shares, found {}", senders, shares.len())));
+    }
+    Ok(())
+}
+
+/// Check the shares we received against the senders' commitments, naming every party that sent a bad share
+pub fn verify_shares(secret: &DkgSecret, commitments: &[DkgCommitment], shares: &[DkgShare]) -> Result<(), Error> {
+    verify_commitments(secret, commitments)?;
+
+    let expected = commitments
+        .iter()
+        .filter(|c| c.sender_index != secret.index)
+        .map(|c| (c.sender_index, evaluate_commitments(&c.commitments, secret.index)));
+    check_received_shares(secret.index, expected, shares)
+}
+
+/// Finish the DKG: combine the received shares into our key share of the group key
+pub fn finalize(secret: &DkgSecret, commitments: &[DkgCommitment], shares: &[DkgShare]) -> Result<ThresholdKeyShare, Error> {
+    verify_shares(secret, commitments, shares)?;
+
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+    let secret_share = shares
+        .iter()
+        .fold(polynomial.evaluate(secret.index), |acc, s| acc + &s.share);
+
+    // The group polynomial is the sum of everyone's polynomial, so are its commitments
+    let group_commitments = (0..usize::from(secret.threshold))
+        .map(|k| {
+            commitments
+                .iter()
+                .fold(Point::zero(), |acc, c| acc + &c.commitments[k])
+        })
+        .collect();
+
+    let key_share = ThresholdKeyShare {
+        index: secret.index,
+        threshold: secret.threshold,
+        secret_share,
+        commitments: group_commitments,
+    };
+    if Point::generator() * &key_share.secret_share != key_share.verifying_share(key_share.index) {
+        return Err(Error::DkgFailed("the final share doesn't match the group commitments".to_string()));
+    }
+    Ok(key_share)
+}
+
+/// Round one of a proactive refresh: sample a polynomial whose constant term is zero and commit to it.
+/// Adding everyone's zero-sharing to the existing shares re-randomizes them while keeping the group key.
+/// Every one of the `parties` share holders must take part with the same fresh `refresh_id`,
+/// otherwise the old and new shares get mixed.
+pub fn refresh_commit(
+    refresh_id: Hash,
+    key_share: &ThresholdKeyShare,
+    parties: u16,
+) -> Result<(RefreshCommitment, DkgSecret), Error> {
+    validate_threshold(key_share.threshold, parties)?;
+    if key_share.index == 0 || key_share.index > parties {
+        return Err(Error::DkgFailed(format!("participant index must be between 1 and {}, got {}", parties, key_share.index)));
+    }
+    if key_share.threshold < 2 {
+        return Err(Error::DkgFailed(
+            "every share of a wallet with threshold 1 is the full key, there is nothing to refresh".to_string(),
+        ));
+    }
+
+    let polynomial = Polynomial::sample(key_share.threshold - 1, Scalar::zero());
+    // The commitment to the zero constant term is the identity, everyone knows it so we don't send it
+    let commitments = polynomial.commit()[1..].to_vec();
+    let (proof_R, proof_mu) = prove_knowledge(
+        REFRESH_PROOF_DOMAIN,
+        &refresh_id,
+        key_share.index,
+        &polynomial.coefficients()[1],
+        &commitments[0],
+    );
+
+    Ok((
+        RefreshCommitment { refresh_id, sender_index: key_share.index, commitments, proof_R, proof_mu },
+        DkgSecret {
+            index: key_share.index,
+            threshold: key_share.threshold,
+            parties,
+            coefficients: polynomial.coefficients().to_vec(),
+        },
+    ))
+}
+
+/// Check that a refresh commitment was made for `refresh_id`, has the right degree and a valid proof of knowledge
+fn check_refresh_commitment(commitment: &RefreshCommitment, refresh_id: &Hash, threshold: u16) -> Result<(), Error> {
+    let index = commitment.sender_index;
+    if &commitment.refresh_id != refresh_id {
+        return Err(Error::DkgFailed(format!(
+            "party {} committed for refresh {}, not {}",
+            index, commitment.refresh_id, refresh_id
+        )));
+    }
+    if commitment.commitments.is_empty() || commitment.commitments.len() + 1 != usize::from(threshold) {
+        return Err(Error::DkgFailed(format!("party {} committed to a polynomial of the wrong degree", index)));
+    }
+    if !verify_knowledge(
+        REFRESH_PROOF_DOMAIN,
+        refresh_id,
+        index,
+        &commitment.commitments[0],
+        &commitment.proof_R,
+        &commitment.proof_mu,
+    ) {
+        return Err(Error::DkgFailed(format!("party {} sent an invalid refresh proof of knowledge", index)));
+    }
+    Ok(())
+}
+
+/// Check that every party broadcast exactly one valid refresh commitment for our refresh, giving its id
+fn verify_refresh_commitments(secret: &DkgSecret, commitments: &[RefreshCommitment]) -> Result<Hash, Error> {
+    if commitments.len() != usize::from(secret.parties) {
+        return Err(Error::DkgFailed(format!(
+            "expected refresh commitments from {} parties, found {}",
+            secret.parties,
+            commitments.len()
+        )));
+    }
+
+    // Our own commitment must be the one we generated, it tells which refresh we're in
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+    let ours = commitments.iter().find(|c| c.sender_index == secret.index).ok_or(Error::KeyPairIsNotInKeys)?;
+    if ours.commitments[..] != polynomial.commit()[1..] {
+        return Err(Error::DkgFailed("our own refresh commitment doesn't match the refresh secret".to_string()));
+    }
+
+    for index in 1..=secret.parties {
+        let from_index: Vec<_> = commitments.iter().filter(|c| c.sender_index == index).collect();
+        match from_index.as_slice() {
+            [commitment] => check_refresh_commitment(commitment, &ours.refresh_id, secret.threshold)?,
+            [] => return Err(Error::DkgFailed(format!("missing refresh commitment from party {}", index))),
+            _ => return Err(Error::DkgFailed(format!("party {} sent more than one refresh commitment", index))),
+        }
+    }
+
+    Ok(ours.refresh_id)
+}
+
+/// The group commitments after adding every party's zero-sharing, the group public key stays the same
+fn refreshed_commitments(
+    group_commitments: &[Point<Ed25519>],
+    commitments: &[RefreshCommitment],
+) -> Vec<Point<Ed25519>> {
+    let mut refreshed = group_commitments.to_vec();
+    for (k, group_commitment) in refreshed.iter_mut().enumerate().skip(1) {
+        *group_commitment = commitments
+            .iter()
+            .fold(group_commitment.clone(), |acc, c| acc + &c.commitments[k - 1]);
+    }
+    refreshed
+}
+
+/// `f(index) * G` for a zero constant polynomial given the commitments to its other coefficients
+fn evaluate_refresh_commitments(commitments: &[Point<Ed25519>], index: u16) -> Point<Ed25519> {
+    evaluate_commitments(commitments, index) * &index_to_scalar(index)
+}
+
+/// Round two of a refresh: evaluate our zero-sharing for every other party.
+/// Each returned share must be delivered privately to its receiver.
+pub fn refresh_share(secret: &DkgSecret, commitments: &[RefreshCommitment]) -> Result<Vec<DkgShare>, Error> {
+    verify_refresh_commitments(secret, commitments)?;
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+
+    Ok((1..=secret.parties)
+        .filter(|&receiver_index| receiver_index != secret.index)
+        .map(|receiver_index| DkgShare {
+            sender_index: secret.index,
+            receiver_index,
+            share: polynomial.evaluate(receiver_index),
+        })
+        .collect())
+}
+
+/// Finish a refresh: add the received zero-shares to our key share and update the group commitments.
+/// The group public key, and so the wallet address, is unchanged.
+pub fn refresh_finalize(
+    key_share: &ThresholdKeyShare,
+    secret: &DkgSecret,
+    commitments: &[RefreshCommitment],
+    shares: &[DkgShare],
+) -> Result<ThresholdKeyShare, Error> {
+    if secret.index != key_share.index || secret.threshold != key_share.threshold {
+        return Err(Error::DkgFailed("the refresh secret belongs to a different key share".to_string()));
+    }
+    let refresh_id = verify_refresh_commitments(secret, commitments)?;
+    let expected = commitments
+        .iter()
+        .filter(|c| c.sender_index != secret.index)
+        .map(|c| (c.sender_index, evaluate_refresh_commitments(&c.commitments, secret.index)));
+    check_received_shares(secret.index, expected, shares)?;
+
+    let polynomial = Polynomial::from_coefficients(secret.coefficients.clone());
+    let secret_share = shares
+        .iter()
+        .fold(&key_share.secret_share + polynomial.evaluate(secret.index), |acc, s| acc + &s.share);
+
+    let refreshed = ThresholdKeyShare {
+        index: key_share.index,
+        threshold: key_share.threshold,
+        secret_share,
+        commitments: refreshed_commitments(&key_share.commitments, commitments),
+    };
+    verify_refresh(key_share, &refreshed, &refresh_id, commitments)?;
+    Ok(refreshed)
+}
+
+/// Prove a refreshed share still belongs to the same wallet and came out of refresh `refresh_id`:
+/// same group public key, same participant, new commitments made of the refresh commitments
+/// every party sent for this refresh, and a new secret that matches them
+pub fn verify_refresh(
+    old_share: &ThresholdKeyShare,
+    new_share: &ThresholdKeyShare,
+    refresh_id: &Hash,
+    commitments: &[RefreshCommitment],
+) -> Result<(), Error> {
+    if old_share.group_public_key() != new_share.group_public_key() {
+        return Err(Error::DkgFailed("the refreshed share signs for a different group key".to_string()));
+    }
+    if old_share.index != new_share.index || old_share.threshold != new_share.threshold {
+        return Err(Error::DkgFailed("the refreshed share belongs to a different participant".to_string()));
+    }
+    for (i, commitment) in commitments.iter().enumerate() {
+        if commitments[..i].iter().any(|c| c.sender_index == commitment.sender_index) {
+            return Err(Error::DkgFailed(format!(
+                "party {} sent more than one refresh commitment",
+                commitment.sender_index
+            )));
+        }
+        check_refresh_commitment(commitment, refresh_id, old_share.threshold)?;
+    }
+    if new_share.commitments != refreshed_commitments(&old_share.commitments, commitments) {
+        return Err(Error::DkgFailed(format!("the refreshed share didn't come out of refresh {}", refresh_id)));
+    }
+    if Point::generator() * &new_share.secret_share != new_share.verifying_share(new_share.index) {
+        return Err(Error::DkgFailed("the refreshed share doesn't match the group commitments".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frost::lagrange_coefficient;
+
+    fn run_dkg(threshold: u16, parties: u16) -> Vec<ThresholdKeyShare> {
+        let ceremony_id = Hash::new_unique();
+        let round_one: Vec<_> = (1..=parties).map(|i| commit(ceremony_id, i, threshold, parties).unwrap()).collect();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        let all_shares: Vec<_> = round_one.iter().flat_map(|(_, s)| share(s, &commitments).unwrap()).collect();
+
+        round_one
+            .iter()
+            .map(|(_, secret)| {
+                let received: Vec<_> = all_shares
+                    .iter()
+                    .filter(|s| s.receiver_index == secret.index)
+                    .cloned()
+                    .collect();
+                finalize(secret, &commitments, &received).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dkg_shares_interpolate_to_group_key() {
+        let shares = run_dkg(2, 3);
+        let group_key = shares[0].group_public_key().clone();
+        assert!(shares.iter().all(|s| s.group_public_key() == &group_key));
+
+        let quorum = [1u16, 3];
+        let interpolated = quorum.iter().fold(Point::zero(), |acc, &i| {
+            let lambda = lagrange_coefficient(i, &quorum).unwrap();
+            acc + Point::generator() * (lambda * &shares[usize::from(i) - 1].secret_share)
+        });
+        assert_eq!(interpolated, group_key);
+    }
+
+    #[test]
+    fn test_dkg_blames_bad_share() {
+        let ceremony_id = Hash::new_unique();
+        let round_one: Vec<_> = (1..=3).map(|i| commit(ceremony_id, i, 2, 3).unwrap()).collect();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        let mut received: Vec<_> = round_one[1..]
+            .iter()
+            .flat_map(|(_, s)| share(s, &commitments).unwrap())
+            .filter(|s| s.receiver_index == 1)
+            .collect();
+        received[1].share = Scalar::random();
+
+        match verify_shares(&round_one[0].1, &commitments, &received) {
+            Err(Error::DkgFailed(e)) => assert!(e.ends_with("parties: 3")),
+            _ => panic!("Expected DkgFailed error"),
+        }
+    }
+
+    #[test]
+    fn test_dkg_rejects_commitment_of_another_ceremony() {
+        let ceremony_id = Hash::new_unique();
+        let mut round_one: Vec<_> = (1..=3).map(|i| commit(ceremony_id, i, 2, 3).unwrap()).collect();
+
+        // Party 3's commitment from an earlier run, relabeled or not, doesn't verify in this one
+        let (mut replayed, _) = commit(Hash::new_unique(), 3, 2, 3).unwrap();
+        round_one[2].0 = replayed.clone();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        assert!(share(&round_one[0].1, &commitments).is_err());
+
+        replayed.ceremony_id = ceremony_id;
+        round_one[2].0 = replayed;
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        match share(&round_one[0].1, &commitments) {
+            Err(Error::DkgFailed(e)) => assert_eq!(e, "party 3 sent an invalid proof of knowledge"),
+            _ => panic!("Expected DkgFailed error"),
+        }
+    }
+
+    #[test]
+    fn test_refresh_keeps_group_key() {
+        let shares = run_dkg(2, 3);
+        let refresh_id = Hash::new_unique();
+        let round_one: Vec<_> = shares.iter().map(|s| refresh_commit(refresh_id, s, 3).unwrap()).collect();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        let all_shares: Vec<_> = round_one.iter().flat_map(|(_, s)| refresh_share(s, &commitments).unwrap()).collect();
+
+        let refreshed: Vec<_> = shares
+            .iter()
+            .zip(round_one.iter())
+            .map(|(share, (_, secret))| {
+                let received: Vec<_> = all_shares
+                    .iter()
+                    .filter(|s| s.receiver_index == share.index)
+                    .cloned()
+                    .collect();
+                refresh_finalize(share, secret, &commitments, &received).unwrap()
+            })
+            .collect();
+
+        for (old, new) in shares.iter().zip(refreshed.iter()) {
+            assert_eq!(old.group_pubkey(), new.group_pubkey());
+            assert_ne!(old.secret_share, new.secret_share);
+        }
+
+        // Old and refreshed shares of the same quorum interpolate to the same key, mixed ones don't
+        let quorum = [1u16, 2];
+        let interpolate = |a: &ThresholdKeyShare, b: &ThresholdKeyShare| {
+            let lambda_a = lagrange_coefficient(a.index, &quorum).unwrap();
+            let lambda_b = lagrange_coefficient(b.index, &quorum).unwrap();
+            Point::generator() * (lambda_a * &a.secret_share + lambda_b * &b.secret_share)
+        };
+        let group_key = shares[0].group_public_key().clone();
+        assert_eq!(interpolate(&refreshed[0], &refreshed[1]), group_key);
+        assert_ne!(interpolate(&shares[0], &refreshed[1]), group_key);
+    }
+
+        // The refreshed share only verifies against the refresh it came out of
+        assert!(verify_refresh(&shares[0], &refreshed[0], &refresh_id, &commitments).is_ok());
+        assert!(verify_refresh(&shares[0], &refreshed[0], &Hash::new_unique(), &commitments).is_err());
+    }
+
+    #[test]
+    fn test_refresh_rejects_commitment_of_another_refresh() {
+        let shares = run_dkg(2, 3);
+        let refresh_id = Hash::new_unique();
+        let mut round_one: Vec<_> = shares.iter().map(|s| refresh_commit(refresh_id, s, 3).unwrap()).collect();
+
+        // Party 3's commitment from an earlier refresh, relabeled or not, doesn't verify in this one
+        let (mut replayed, _) = refresh_commit(Hash::new_unique(), &shares[2], 3).unwrap();
+        round_one[2].0 = replayed.clone();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        assert!(refresh_share(&round_one[0].1, &commitments).is_err());
+
+        replayed.refresh_id = refresh_id;
+        round_one[2].0 = replayed;
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        match refresh_share(&round_one[0].1, &commitments) {
+            Err(Error::DkgFailed(e)) => assert_eq!(e, "party 3 sent an invalid refresh proof of knowledge"),
+            _ => panic!("Expected DkgFailed error"),
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::{Display, Formatter};
 use bs58::decode::Error as Bs58Error;
 use ed25519_dalek::SignatureError;
 use solana_client::client_error::ClientError;
+use solana_sdk::pubkey::Pubkey;
 use crate::serialization;
 
 /// Custom application error type
@@ -21,8 +22,28 @@ pub enum Error {
     TokenMintFailed(String),
     TokenTransferFailed(String),
     TokenAccountNotFound,
+    MintAccountError(String),
+    TokenInstructionFailed(String),
     FileReadError(String),
     SerializationError(String),
+    InvalidThreshold(String),
+    DkgFailed(String),
+    ReshareFailed(String),
+    NonceReused(String),
+    NonceLedgerError(String),
+    NoncePoolError(String),
+    NonceAccountError(String),
+    SessionMismatch(String),
+    SessionFileError(String),
+    CoordinatorError(String),
+    TransportError(String),
+    DaemonError(String),
+    LookupTableError(String),
+    ComputeBudgetError(String),
+    BatchError(String),
+    RemovedCommand(String),
+    MisbehavingParties(Vec<Pubkey>),
+    MissingParties(Vec<Pubkey>),
 }
 
 impl Display for Error {
@@ -45,8 +66,36 @@ impl Display for Error {
             Self::TokenMintFailed(e) => write!(f, "Token minting failed: {}", e),
             Self::TokenTransferFailed(e) => write!(f, "Token transfer failed: {}", e),
             Self::TokenAccountNotFound => write!(f, "Token account not found"),
+            Self::MintAccountError(e) => write!(f, "Mint account error: {}", e),
+            Self::TokenInstructionFailed(e) => write!(f, "Token instruction failed: {}", e),
             Self::FileReadError(e) => write!(f, "File read error: {}", e),
             Self::SerializationError(e) => write!(f, "Serialization error: {}", e),
+            Self::InvalidThreshold(e) => write!(f, "Invalid threshold signing set: {}", e),
+            Self::DkgFailed(e) => write!(f, "Distributed key generation failed: {}", e),
+            Self::ReshareFailed(e) => write!(f, "Resharing failed: {}", e),
+            Self::NonceReused(e) => write!(f, "Refusing to reuse nonce: {}", e),
+            Self::NonceLedgerError(e) => write!(f, "Nonce ledger error: {}", e),
+            Self::NoncePoolError(e) => write!(f, "Nonce pool error: {}", e),
+            Self::NonceAccountError(e) => write!(f, "Durable nonce error: {}", e),
+            Self::SessionMismatch(e) => write!(f, "Signing session mismatch: {}", e),
+            Self::SessionFileError(e) => write!(f, "Session file error: {}", e),
+            Self::CoordinatorError(e) => write!(f, "Coordinator error: {}", e),
+            Self::TransportError(e) => write!(f, "Secure transport error: {}", e),
+            Self::DaemonError(e) => write!(f, "Signer daemon error: {}", e),
+            Self::LookupTableError(e) => write!(f, "Address lookup table error: {}", e),
+            Self::ComputeBudgetError(e) => write!(f, "Compute budget error: {}", e),
+            Self::BatchError(e) => write!(f, "Batch payout error: {}", e),
+            Self::RemovedCommand(e) => write!(f, "Command removed: {}", e),
+            Self::MisbehavingParties(parties) => write!(
+                f,
+                "Invalid partial signatures from: {}",
+                parties.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            Self::MissingParties(parties) => write!(
+                f,
+                "Still waiting for partial signatures from: {}",
+                parties.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+            ),
         }
     }
 }
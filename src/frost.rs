@@ -0,0 +1,471 @@
+#![allow(non_snake_case)]
+
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::error::Error;
+use crate::nonce_ledger::{nonce_id, NonceLedger};
+use crate::serialization::{
+    SecretThresholdStepOne, ThresholdKeyShare, ThresholdMessage1, ThresholdPartialSignature, TransactionTemplate,
+};
+use crate::tss::{ed25519_challenge, hash_to_scalar, point_to_pubkey};
+
+/// Domain separator for the per-signer binding factors
+const BINDING_FACTOR_DOMAIN: &[u8] = b"solana-mpc-tokens/frost/binding-factor";
+
+/// A polynomial over the Ed25519 scalar field, used for Shamir secret sharing with Feldman commitments
+pub struct Polynomial {
+    coefficients: Vec<Scalar<Ed25519>>,
+}
+
+impl Polynomial {
+    /// Sample a random polynomial of the given degree with a fixed constant term
+    pub fn sample(degree: u16, constant: Scalar<Ed25519>) -> Self {
+        let mut coefficients = Vec::with_capacity(usize::from(degree) + 1);
+        coefficients.push(constant);
+        coefficients.extend((0..degree).map(|_| Scalar::random()));
+        Self { coefficients }
+    }
+
+    /// Rebuild a polynomial from its coefficients, lowest degree first
+    pub fn from_coefficients(coefficients: Vec<Scalar<Ed25519>>) -> Self {
+        Self { coefficients }
+    }
+
+    /// The coefficients, lowest degree first
+    pub fn coefficients(&self) -> &[Scalar<Ed25519>] {
+        &self.coefficients
+    }
+
+    /// Evaluate the polynomial at a participant index (Horner's method)
+    pub fn evaluate(&self, index: u16) -> Scalar<Ed25519> {
+        let x = index_to_scalar(index);
+        self.coefficients.iter().rev().fold(Scalar::zero(), |acc, c| acc * &x + c)
+    }
+
+    /// Feldman commitments `a_k * G` to every coefficient
+    pub fn commit(&self) -> Vec<Point<Ed25519>> {
+        self.coefficients.iter().map(|c| Point::generator() * c).collect()
+    }
+}
+
+/// Participant indices are the x-coordinates of the shares, they start at 1
+pub fn index_to_scalar(index: u16) -> Scalar<Ed25519> {
+    Scalar::from(u64::from(index))
+}
+
+/// Evaluate Feldman commitments "in the exponent" at a participant index, giving `f(index) * G`
+pub fn evaluate_commitments(commitments: &[Point<Ed25519>], index: u16) -> Point<Ed25519> {
+    let x = index_to_scalar(index);
+    commitments.iter().rev().fold(Point::zero(), |acc, c| acc * &x + c)
+}
+
+/// Lagrange coefficient of `index` for interpolating at zero over the given set of indices
+pub fn lagrange_coefficient(index: u16, indices: &[u16]) -> Result<Scalar<Ed25519>, Error> {
+    let x_i = index_to_scalar(index);
+    let mut numerator = Scalar::from(1u64);
+    let mut denominator = Scalar::from(1u64);
+    for &other in indices.iter().filter(|&&other| other != index) {
+        let x_j = index_to_scalar(other);
+        numerator = numerator * &x_j;
+        denominator = denominator * (&x_j - &x_i);
+    }
+    let inverse = denominator
+        .invert()
+        .ok_or_else(|| Error::InvalidThreshold(format!("Duplicate participant index {}", index)))?;
+    Ok(numerator * inverse)
+}
+
+/// Make sure `threshold` out of `parties` is a meaningful sharing
+pub fn validate_threshold(threshold: u16, parties: u16) -> Result<(), Error> {
+    if threshold == 0 || threshold > parties {
+        return Err(Error::InvalidThreshold(format!(
+            "threshold must be between 1 and the number of parties ({}), got {}",
+            parties, threshold
+        )));
+    }
+    Ok(())
+}
+
+impl ThresholdKeyShare {
+    /// The group public key every quorum signs for
+    pub fn group_public_key(&self) -> &Point<Ed25519> {
+        &self.commitments[0]
+    }
+
+    /// The group public key as a Solana address
+    pub fn group_pubkey(&self) -> Pubkey {
+        point_to_pubkey(self.group_public_key())
+    }
+
+    /// The public verification share `s_i * G` of any participant
+    pub fn verifying_share(&self, index: u16) -> Point<Ed25519> {
+        evaluate_commitments(&self.commitments, index)
+    }
+}
+
+/// Split a freshly generated key into `parties` shares, any `threshold` of which can sign.
+/// The full private key only exists inside this function and is dropped before returning.
+pub fn keygen_with_dealer(threshold: u16, parties: u16) -> Result<Vec<ThresholdKeyShare>, Error> {
+    validate_threshold(threshold, parties)?;
+    let polynomial = Polynomial::sample(threshold - 1, Scalar::random());
+    let commitments = polynomial.commit();
+
+    Ok((1..=parties)
+        .map(|index| ThresholdKeyShare {
+            index,
+            threshold,
+            secret_share: polynomial.evaluate(index),
+            commitments: commitments.clone(),
+        })
+        .collect())
+}
+
+/// Generate the hiding and binding nonces and their commitments
+/// This is the first step in the threshold signing process
+/// The nonces are recorded in the party's nonce ledger so step two can refuse to use them twice
+pub fn step_one(
+    key_share: &ThresholdKeyShare,
+    ledger: &mut NonceLedger,
+) -> Result<(ThresholdMessage1, SecretThresholdStepOne), Error> {
+    let nonces = [Scalar::random(), Scalar::random()];
+    let commitments = [Point::generator() * &nonces[0], Point::generator() * &nonces[1]];
+    ledger.record_generated(&nonce_id(&commitments))?;
+
+    Ok((
+        ThresholdMessage1 { sender_index: key_share.index, commitments: commitments.clone() },
+        SecretThresholdStepOne { nonces, commitments },
+    ))
+}
+
+/// Sort the signing commitments by participant and reject duplicates
+fn sort_commitments(mut commitments: Vec<ThresholdMessage1>) -> Result<Vec<ThresholdMessage1>, Error> {
+    commitments.sort_by_key(|msg| msg.sender_index);
+    if commitments.windows(2).any(|pair| pair[0].sender_index == pair[1].sender_index) {
+        return Err(Error::InvalidThreshold("Duplicate participant in the signing commitments".to_string()));
+    }
+    Ok(commitments)
+}
+
+/// Compute the binding factor of every signer, binding their nonces to the message and to the whole signing set
+fn binding_factors(
+    group_public_key: &Point<Ed25519>,
+    commitments: &[ThresholdMessage1],
+    message: &[u8],
+) -> Vec<Scalar<Ed25519>> {
+    let mut encoded_commitments = Vec::with_capacity(commitments.len() * (2 + 64));
+    for msg in commitments {
+        encoded_commitments.extend(msg.sender_index.to_le_bytes());
+        encoded_commitments.extend(&*msg.commitments[0].to_bytes(true));
+        encoded_commitments.extend(&*msg.commitments[1].to_bytes(true));
+    }
+    let group_public_key = group_public_key.to_bytes(true);
+
+    commitments
+        .iter()
+        .map(|msg| {
+            hash_to_scalar(&[
+                BINDING_FACTOR_DOMAIN,
+                &group_public_key,
+                message,
+                &encoded_commitments,
+                &msg.sender_index.to_le_bytes(),
+            ])
+        })
+        .collect()
+}
+
+/// The group commitment `R = sum(D_i + rho_i * E_i)`
+fn group_commitment(commitments: &[ThresholdMessage1], binding_factors: &[Scalar<Ed25519>]) -> Point<Ed25519> {
+    commitments
+        .iter()
+        .zip(binding_factors)
+        .fold(Point::zero(), |acc, (msg, rho)| acc + &msg.commitments[0] + &msg.commitments[1] * rho)
+}
+
+/// Produce this party's share `z_i` of the signature over `message`
+pub fn partial_sign(
+    key_share: &ThresholdKeyShare,
+    secret_state: SecretThresholdStepOne,
+    commitments: Vec<ThresholdMessage1>,
+    message: &[u8],
+) -> Result<ThresholdPartialSignature, Error> {
+    let commitments = sort_commitments(commitments)?;
+    if commitments.len() < usize::from(key_share.threshold) {
+        return Err(Error::InvalidThreshold(format!(
+            "{} signers are not enough, the threshold is {}",
+            commitments.len(),
+            key_share.threshold
+        )));
+    }
+
+    // Our own commitments must be part of the signing set exactly as we generated them
+    let position = commitments
+        .iter()
+        .position(|msg| msg.sender_index == key_share.index)
+        .ok_or(Error::KeyPairIsNotInKeys)?;
+    if commitments[position].commitments != secret_state.commitments {
+        return Err(Error::SerializationError("Our commitments don't match the secret state".to_string()));
+    }
+
+    let group_public_key = key_share.group_public_key();
+    let rhos = binding_factors(group_public_key, &commitments, message);
+    let R = group_commitment(&commitments, &rhos);
+    let challenge = ed25519_challenge(&R, group_public_key, message);
+
+    let indices: Vec<u16> = commitments.iter().map(|msg| msg.sender_index).collect();
+    let lambda = lagrange_coefficient(key_share.index, &indices)?;
+
+    let [hiding, binding] = secret_state.nonces;
+    let z = hiding + binding * &rhos[position] + lambda * &key_share.secret_share * &challenge;
+
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&z.to_bytes());
+    Ok(ThresholdPartialSignature { sender_index: key_share.index, signature: Signature::from(sig_bytes) })
+}
+
+/// Check one signer's share of the signature, `z_i * G == D_i + rho_i * E_i + lambda_i * c * Y_i`,
+/// giving back `z_i` if it holds
+fn verify_signature_share(
+    signature: &ThresholdPartialSignature,
+    verifying_share: &Point<Ed25519>,
+    commitments: &ThresholdMessage1,
+    rho: &Scalar<Ed25519>,
+    lambda: &Scalar<Ed25519>,
+    R: &Point<Ed25519>,
+    challenge: &Scalar<Ed25519>,
+) -> Option<Scalar<Ed25519>> {
+    // A share that doesn't even decode is as invalid as a wrong one
+    if signature.signature.as_ref()[..32] != *R.to_bytes(true) {
+        return None;
+    }
+    let z = Scalar::<Ed25519>::from_bytes(&signature.signature.as_ref()[32..]).ok()?;
+    let [hiding, binding] = &commitments.commitments;
+    let expected = hiding + binding * rho + verifying_share * (lambda * challenge);
+    if Point::generator() * &z != expected {
+        return None;
+    }
+    Some(z)
+}
+
+/// Verify the signature shares of a quorum and sum them into a standard Ed25519 signature on `message`.
+/// Every signer of the commitments must send exactly one share, signers whose share is invalid are
+/// reported by the address of their verifying share.
+pub fn aggregate_signatures(
+    key_share: &ThresholdKeyShare,
+    commitments: Vec<ThresholdMessage1>,
+    signatures: &[ThresholdPartialSignature],
+    message: &[u8],
+) -> Result<Signature, Error> {
+    let commitments = sort_commitments(commitments)?;
+    for (i, signature) in signatures.iter().enumerate() {
+        if signatures[..i].iter().any(|s| s.sender_index == signature.sender_index) {
+            return Err(Error::InvalidThreshold(format!(
+                "more than one partial signature from participant {}",
+                signature.sender_index
+            )));
+        }
+        if !commitments.iter().any(|msg| msg.sender_index == signature.sender_index) {
+            return Err(Error::SessionMismatch(format!(
+                "partial signature from participant {} who sent no signing commitments",
+                signature.sender_index
+            )));
+        }
+    }
+    if signatures.len() < usize::from(key_share.threshold) {
+        return Err(Error::InvalidThreshold(format!(
+            "{} partial signatures are not enough, the threshold is {}",
+            signatures.len(),
+            key_share.threshold
+        )));
+    }
+    let missing: Vec<Pubkey> = commitments
+        .iter()
+        .filter(|msg| !signatures.iter().any(|s| s.sender_index == msg.sender_index))
+        .map(|msg| point_to_pubkey(&key_share.verifying_share(msg.sender_index)))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingParties(missing));
+    }
+
+    let group_public_key = key_share.group_public_key();
+    let rhos = binding_factors(group_public_key, &commitments, message);
+    let R = group_commitment(&commitments, &rhos);
+    let challenge = ed25519_challenge(&R, group_public_key, message);
+    let indices: Vec<u16> = commitments.iter().map(|msg| msg.sender_index).collect();
+
+    let mut misbehaving = Vec::new();
+    let mut shares = Vec::with_capacity(commitments.len());
+    for (msg, rho) in commitments.iter().zip(&rhos) {
+        let signature = signatures
+            .iter()
+            .find(|s| s.sender_index == msg.sender_index)
+            .ok_or(Error::KeyPairIsNotInKeys)?;
+        let verifying_share = key_share.verifying_share(msg.sender_index);
+        let lambda = lagrange_coefficient(msg.sender_index, &indices)?;
+        match verify_signature_share(signature, &verifying_share, msg, rho, &lambda, &R, &challenge) {
+            Some(z_i) => shares.push(z_i),
+            None => misbehaving.push(point_to_pubkey(&verifying_share)),
+        }
+    }
+    if !misbehaving.is_empty() {
+        return Err(Error::MisbehavingParties(misbehaving));
+    }
+    let z = shares.into_iter().fold(Scalar::<Ed25519>::zero(), |acc, z_i| acc + z_i);
+
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&z.to_bytes());
+    Ok(Signature::from(sig_bytes))
+}
+
+/// Generate our partial signature on the transaction template (Step 2 of threshold signing)
+pub fn step_two(
+    key_share: &ThresholdKeyShare,
+    template: &TransactionTemplate,
+    first_messages: Vec<ThresholdMessage1>,
+    secret_state: SecretThresholdStepOne,
+    ledger: &mut NonceLedger,
+) -> Result<ThresholdPartialSignature, Error> {
+    let group_pubkey = key_share.group_pubkey();
+    if template.fee_payer()? != group_pubkey {
+        return Err(Error::SessionMismatch(format!(
+            "the transaction template is paid by {}, not by the group key {}",
+            template.fee_payer()?,
+            group_pubkey
+        )));
+    }
+
+    // Burn the nonces before they are used, a crash after this point can't lead to a second signature
+    ledger.consume(&nonce_id(&secret_state.commitments), &template.digest())?;
+    partial_sign(key_share, secret_state, first_messages, &template.message)
+}
+
+/// Aggregate the quorum's partial signatures into the signed transaction of the template (Step 3 of threshold signing)
+pub fn sign_and_broadcast(
+    key_share: &ThresholdKeyShare,
+    template: &TransactionTemplate,
+    first_messages: Vec<ThresholdMessage1>,
+    signatures: Vec<ThresholdPartialSignature>,
+) -> Result<VersionedTransaction, Error> {
+    // The template verifies the aggregated signature against its fee payer, the group key
+    template.to_transaction(aggregate_signatures(key_share, first_messages, &signatures, &template.message)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign_with(shares: &[ThresholdKeyShare], message: &[u8]) -> Signature {
+        let (first_messages, partials) = partial_signatures(shares, message);
+        aggregate_signatures(&shares[0], first_messages, &partials, message).unwrap()
+    }
+
+    /// Run both signing rounds for every share, giving the first messages and the partial signatures
+    fn partial_signatures(
+        shares: &[ThresholdKeyShare],
+        message: &[u8],
+    ) -> (Vec<ThresholdMessage1>, Vec<ThresholdPartialSignature>) {
+        let rounds: Vec<_> = shares.iter().map(step_one_without_ledger).collect();
+        let first_messages: Vec<_> = rounds.iter().map(|(msg, _)| msg.clone()).collect();
+        let partials = shares
+            .iter()
+            .zip(rounds.iter())
+            .map(|(share, (_, secret))| {
+                let secret = SecretThresholdStepOne { nonces: secret.nonces.clone(), commitments: secret.commitments.clone() };
+                partial_sign(share, secret, first_messages.clone(), message).unwrap()
+            })
+            .collect();
+        (first_messages, partials)
+    }
+
+    /// Step one without a ledger, the tests sign with freshly generated nonces only once
+    fn step_one_without_ledger(key_share: &ThresholdKeyShare) -> (ThresholdMessage1, SecretThresholdStepOne) {
+        let nonces = [Scalar::random(), Scalar::random()];
+        let commitments = [Point::generator() * &nonces[0], Point::generator() * &nonces[1]];
+        (
+            ThresholdMessage1 { sender_index: key_share.index, commitments: commitments.clone() },
+            SecretThresholdStepOne { nonces, commitments },
+        )
+    }
+
+    #[test]
+    fn test_any_quorum_produces_valid_signature() {
+        let shares = keygen_with_dealer(2, 3).unwrap();
+        let group_pubkey = shares[0].group_pubkey();
+        let message = b"threshold signed message";
+
+        for quorum in [[0, 1], [0, 2], [1, 2]] {
+            let signers: Vec<_> = quorum
+                .iter()
+                .map(|&i| ThresholdKeyShare {
+                    index: shares[i].index,
+                    threshold: shares[i].threshold,
+                    secret_share: shares[i].secret_share.clone(),
+                    commitments: shares[i].commitments.clone(),
+                })
+                .collect();
+            let signature = sign_with(&signers, message);
+            assert!(signature.verify(group_pubkey.as_ref(), message));
+        }
+    }
+
+    #[test]
+    fn test_aggregation_rejects_bad_shares() {
+        let shares = keygen_with_dealer(2, 3).unwrap();
+        let message = b"threshold signed message";
+        let (first_messages, mut partials) = partial_signatures(&shares[..2], message);
+
+        // Fewer shares than the threshold, or the same signer twice, never make a signature
+        assert!(aggregate_signatures(&shares[0], first_messages.clone(), &partials[..1], message).is_err());
+        let duplicated = [partials[0].clone(), partials[0].clone()];
+        assert!(aggregate_signatures(&shares[0], first_messages.clone(), &duplicated, message).is_err());
+
+        // A share over another message is caught and its signer reported
+        let (_, other_partials) = partial_signatures(&shares[..2], b"another message");
+        partials[1] = other_partials[1].clone();
+        match aggregate_signatures(&shares[0], first_messages, &partials, message) {
+            Err(Error::MisbehavingParties(parties)) => {
+                assert_eq!(parties, vec![point_to_pubkey(&shares[1].verifying_share(shares[1].index))])
+            }
+            _ => panic!("Expected MisbehavingParties error"),
+        }
+    }
+
+    #[test]
+    fn test_shares_match_verifying_shares() {
+        let shares = keygen_with_dealer(3, 5).unwrap();
+        for share in &shares {
+            assert_eq!(Point::generator() * &share.secret_share, share.verifying_share(share.index));
+        }
+    }
+
+    #[test]
+    fn test_invalid_threshold() {
+        assert!(keygen_with_dealer(0, 3).is_err());
+        assert!(keygen_with_dealer(4, 3).is_err());
+    }
+
+    #[test]
+    fn test_malformed_key_share_is_rejected() {
+        use crate::serialization::Serialize;
+
+        let shares = keygen_with_dealer(2, 3).unwrap();
+        assert_eq!(ThresholdKeyShare::deserialize_bs58(shares[0].serialize_bs58()).unwrap(), shares[0]);
+
+        let mut malformed = ThresholdKeyShare {
+            index: 0,
+            threshold: shares[0].threshold,
+            secret_share: shares[0].secret_share.clone(),
+            commitments: shares[0].commitments.clone(),
+        };
+        assert!(ThresholdKeyShare::deserialize_bs58(malformed.serialize_bs58()).is_err());
+
+        malformed.index = 1;
+        malformed.threshold = 3;
+        assert!(ThresholdKeyShare::deserialize_bs58(malformed.serialize_bs58()).is_err());
+    }
+}
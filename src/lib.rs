@@ -0,0 +1,72 @@
+//! MPC signing of Solana transactions: n-of-n MuSig2 wallets and t-of-n FROST wallets.
+//!
+//! Every party runs the same rounds on its own key:
+//! 1. [`step_one`] generates nonces for one session and one transaction template, and returns the first
+//!    message to send to the other parties along with the secret state to keep.
+//! 2. [`step_two`] signs the template once every other party's first message arrived.
+//! 3. [`sign_and_broadcast`] verifies and aggregates every partial signature into the signed transaction.
+//!
+//! [`SigningSession`] runs the same rounds as a state machine that can't be driven out of order.
+//! Every message and secret state has a base58 wire format through the [`Serialize`] trait.
+//!
+//! ```
+//! use solana_mpc_tokens::{key_agg, agg_key_to_pubkey, sign_and_broadcast, step_one, step_two, template, NonceLedger};
+//! use solana_sdk::{hash::Hash, pubkey::Pubkey, signature::{Keypair, Signer}};
+//!
+//! let keypairs = [Keypair::new(), Keypair::new()];
+//! let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+//! let wallet = agg_key_to_pubkey(&key_agg(keys.clone(), None)?);
+//! let options = template::TemplateOptions::default();
+//! let template = template::sol_transfer(0.1, &Pubkey::new_unique(), None, &wallet, Hash::new_unique(), &options)?;
+//!
+//! let session_id = Hash::new_unique();
+//! let mut ledgers = keypairs
+//!     .iter()
+//!     .map(|k| NonceLedger::open(std::env::temp_dir().join(format!("doc-ledger-{}-{}.log", k.pubkey(), session_id))))
+//!     .collect::<Result<Vec<_>, _>>()?;
+//! let mut rounds = Vec::new();
+//! for (keypair, ledger) in keypairs.iter().zip(&mut ledgers) {
+//!     rounds.push(step_one(keypair.insecure_clone(), session_id, template.digest(), ledger)?);
+//! }
+//! let first_messages: Vec<_> = rounds.iter().map(|(msg, _)| msg.clone()).collect();
+//!
+//! let mut signatures = Vec::new();
+//! for ((keypair, ledger), (_, secret_state)) in keypairs.iter().zip(&mut ledgers).zip(rounds) {
+//!     let others = first_messages.iter().filter(|msg| msg.sender != keypair.pubkey()).cloned().collect();
+//!     signatures.push(step_two(keypair.insecure_clone(), &template, keys.clone(), others, secret_state, ledger)?);
+//! }
+//!
+//! let tx = sign_and_broadcast(&template, keys, first_messages, signatures)?;
+//! assert_eq!(tx.message.static_account_keys()[0], wallet);
+//! # for keypair in &keypairs {
+//! #     std::fs::remove_file(std::env::temp_dir().join(format!("doc-ledger-{}-{}.log", keypair.pubkey(), session_id))).unwrap();
+//! # }
+//! # Ok::<(), solana_mpc_tokens::Error>(())
+//! ```
+
+pub mod batch;
+pub mod compute_budget;
+pub mod coordinator;
+pub mod daemon;
+pub mod dkg;
+pub mod error;
+pub mod frost;
+pub mod nonce_ledger;
+pub mod nonce_pool;
+pub mod reshare;
+pub mod serialization;
+pub mod session;
+pub mod signing;
+pub mod template;
+#[cfg(test)]
+mod test_utils;
+pub mod token;
+pub mod transport;
+pub mod tss;
+
+pub use error::Error;
+pub use nonce_ledger::NonceLedger;
+pub use serialization::{AggMessage1, PartialSignature, SecretAggStepOne, Serialize, TransactionTemplate};
+pub use session::Session;
+pub use signing::{SignError, SigningSession};
+pub use tss::{agg_key_to_pubkey, key_agg, sign_and_broadcast, step_one, step_two};
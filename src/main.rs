@@ -1,17 +1,114 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use clap::Parser;
 use solana_client::rpc_client::RpcClient;
-use solana_sdk::{native_token, signature::{Signer, Keypair}, hash::Hash};
+use solana_sdk::{native_token, signature::{Signer, Keypair}, hash::Hash, pubkey::Pubkey, transaction::VersionedTransaction};
+use solana_sdk::address_lookup_table::instruction::derive_lookup_table_address;
+use solana_sdk::instruction::Instruction;
+
+use solana_mpc_tokens::{
+    batch, coordinator, daemon, dkg, error, frost, nonce_ledger, nonce_pool, reshare, serialization, session, template, token, tss,
+};
+use solana_mpc_tokens::compute_budget::{ComputeBudget, MAX_COMPUTE_UNIT_LIMIT};
 
 mod cli;
-mod error;
-mod tss;
-mod serialization;
-mod token;
 
 use cli::{Options};
 use error::Error;
+use nonce_ledger::NonceLedger;
 use serialization::Serialize;
 
+// Helper function to parse a list of base58 serialized messages
+fn parse_messages<T: Serialize>(messages: &[String], what: &str) -> Result<Vec<T>, Error> {
+    messages
+        .iter()
+        .map(|msg| T::deserialize_bs58(msg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| Error::FileReadError(format!("Failed to parse {}: {}", what, e)))
+}
+
+// Helper function to parse an `index:pubkey` pair
+fn parse_indexed_pubkey(pair: &str) -> Result<(u16, Pubkey), Error> {
+    let (index, pubkey) = pair
+        .split_once(':')
+        .ok_or_else(|| Error::FileReadError(format!("Expected `index:pubkey`, found: {}", pair)))?;
+    let index = index.trim().parse::<u16>()
+        .map_err(|e| Error::FileReadError(format!("Invalid index {}: {}", index, e)))?;
+    let pubkey = pubkey.trim().parse::<Pubkey>()
+        .map_err(|e| Error::FileReadError(format!("Invalid pubkey {}: {}", pubkey, e)))?;
+    Ok((index, pubkey))
+}
+
+// Helper function to get the step 1 state of a party, either from its step 1 round
+// or from entry `pool_index` of the nonce pools, which is removed from the secret pool file
+#[allow(clippy::too_many_arguments)]
+fn round_one_state(
+    keypair: &Keypair,
+    keys: &[Pubkey],
+    first_messages: &[String],
+    secret_state: Option<String>,
+    pool_index: Option<u16>,
+    pools: &[String],
+    pool_file: Option<PathBuf>,
+    message_digest: Hash,
+) -> Result<(Vec<serialization::AggMessage1>, serialization::SecretAggStepOne), Error> {
+    let index = match pool_index {
+        Some(index) => index,
+        None => return parse_round_one(first_messages, secret_state),
+    };
+
+    let pools: Vec<serialization::NoncePool> = parse_messages(pools, "nonce pools")?;
+    let own_pool = pools.iter().find(|p| p.sender == keypair.pubkey()).ok_or(Error::KeyPairIsNotInKeys)?;
+    let path = match pool_file {
+        Some(path) => path,
+        None => nonce_pool::default_path(&keypair.pubkey(), &own_pool.pool_id)?,
+    };
+
+    // Remove the entry from the pool file before signing with it
+    let mut secret_pool = nonce_pool::load(&path)?;
+    let secret_state = secret_pool.take(keys, &pools, index, message_digest)?;
+    nonce_pool::save(&secret_pool, &path)?;
+
+    let first_messages = nonce_pool::first_messages(keys, &pools, index, message_digest)?
+        .into_iter()
+        .filter(|msg| msg.sender != keypair.pubkey())
+        .collect();
+    Ok((first_messages, secret_state))
+}
+
+// Helper function to parse the first messages and our secret state of a step 1 round
+fn parse_round_one(
+    first_messages: &[String],
+    secret_state: Option<String>,
+) -> Result<(Vec<serialization::AggMessage1>, serialization::SecretAggStepOne), Error> {
+    let first_messages = parse_messages(first_messages, "first messages")?;
+    let secret_state =
+        secret_state.ok_or_else(|| Error::FileReadError("Missing secret state from step 1".to_string()))?;
+    let secret_state = serialization::SecretAggStepOne::deserialize_bs58(&secret_state)
+        .map_err(|e| Error::FileReadError(format!("Failed to parse secret state: {}", e)))?;
+    Ok((first_messages, secret_state))
+}
+
+// Helper function to point the per-transfer commands of earlier versions to the template flow
+fn check_removed_command() -> Result<(), Error> {
+    let command = match std::env::args().nth(1) {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    match cli::REMOVED_COMMANDS.iter().find(|(old, _)| *old == command) {
+        Some((old, new)) => Err(Error::RemovedCommand(format!(
+            "`{}` is replaced by `{}`. Build the transfer once with template-token or template-sol and pass \
+             the printed template to the signing steps with --template, see \"Upgrading From Per-Transfer \
+             Commands\" in the README",
+            old, new
+        ))),
+        None => Ok(()),
+    }
+}
+
 // Helper function to parse a private key from base58 string
 fn parse_keypair(private_key: &str) -> Result<Keypair, Error> {
     let decoded = bs58::decode(private_key.trim())
@@ -21,7 +118,149 @@ fn parse_keypair(private_key: &str) -> Result<Keypair, Error> {
         .map_err(Error::WrongKeyPair)
 }
 
+// Helper function to start a signing session for a template and write it to `path`
+fn start_session(
+    intent: String,
+    keys: Vec<Pubkey>,
+    template: serialization::TransactionTemplate,
+    path: &Path,
+) -> Result<(), Error> {
+    let session = session::Session::new(intent, keys, template)?;
+    session.save(path)?;
+    println!("session id: {}", session.session_id);
+    println!("session saved to: {}", path.display());
+    Ok(())
+}
+
+// Helper function to build a template with the arguments shared by template commands,
+// fetching the lookup tables they name and estimating the compute budget if asked to
+fn build_template(
+    args: &cli::TemplateArgs,
+    rpc_client: &RpcClient,
+    build: impl Fn(Hash, &template::TemplateOptions) -> Result<serialization::TransactionTemplate, Error>,
+) -> Result<serialization::TransactionTemplate, Error> {
+    let block_hash = args.recent_block_hash.parse::<Hash>()
+        .map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e)))?;
+    let mut options = template::TemplateOptions {
+        nonce_account: args.nonce_account,
+        lookup_tables: token::get_lookup_tables(rpc_client, &args.lookup_tables)?,
+        ..Default::default()
+    };
+
+    // The fee settings are fixed here, once, so every party signs the same ones
+    let (unit_price, unit_limit) = args.compute_budget.to_compute_budget().resolve(rpc_client, |price, limit| {
+        let draft = template::TemplateOptions { compute_unit_price: price, compute_unit_limit: limit, ..options.clone() };
+        build(block_hash, &draft)?.message()
+    })?;
+    options.compute_unit_price = unit_price;
+    options.compute_unit_limit = unit_limit;
+    build(block_hash, &options)
+}
+
+// Helper function to pack payouts into transactions, each with the block hash or its own durable nonce,
+// then fix the compute budget of each one
+fn build_batch(
+    rpc_client: &RpcClient,
+    payouts: &[batch::Payout],
+    wallet: &Pubkey,
+    recent_block_hash: Option<String>,
+    nonce_accounts: &[Pubkey],
+    lookup_tables: &[Pubkey],
+    budget: &ComputeBudget,
+) -> Result<Vec<(Vec<usize>, serialization::TransactionTemplate)>, Error> {
+    let recent_block_hash = recent_block_hash
+        .map(|hash| hash.parse::<Hash>().map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e))))
+        .transpose()?;
+    let mut nonces = Vec::new();
+    for nonce_account in nonce_accounts {
+        let (nonce_value, authority) = token::get_nonce_value(rpc_client, nonce_account)?;
+        if &authority != wallet {
+            return Err(Error::NonceAccountError(format!(
+                "{} is advanced by {}, not by the wallet {}",
+                nonce_account, authority, wallet
+            )));
+        }
+        nonces.push((*nonce_account, nonce_value));
+    }
+    let lookup_tables = token::get_lookup_tables(rpc_client, lookup_tables)?;
+
+    // A durable nonce only signs one transaction, so transaction number `index` uses the nonce account number `index`
+    let build = |index: usize, instructions: Vec<Instruction>, unit_price: Option<u64>, unit_limit: Option<u32>| {
+        let (block_hash, nonce_account) = match recent_block_hash {
+            Some(block_hash) => (block_hash, None),
+            None => {
+                let (nonce_account, nonce_value) = nonces.get(index).ok_or_else(|| Error::BatchError(format!(
+                    "the payouts need more than {} transactions, pass one nonce account for each",
+                    nonces.len()
+                )))?;
+                (*nonce_value, Some(*nonce_account))
+            }
+        };
+        let options = template::TemplateOptions {
+            nonce_account,
+            lookup_tables: lookup_tables.clone(),
+            compute_unit_price: unit_price,
+            compute_unit_limit: unit_limit,
+        };
+        serialization::TransactionTemplate::build(instructions, wallet, block_hash, &options)
+    };
+
+    // Compute budget instructions have the same size whatever their values, so drafts pack like the final transactions
+    let draft_price = budget.unit_price.map(|_| 0);
+    let draft_limit = budget.unit_limit.map(|_| MAX_COMPUTE_UNIT_LIMIT);
+    let packed = batch::pack(batch::payout_instructions(rpc_client, payouts, wallet)?, |index, instructions| {
+        build(index, instructions, draft_price, draft_limit)
+    })?;
+    packed
+        .into_iter()
+        .enumerate()
+        .map(|(index, transaction)| {
+            let (unit_price, unit_limit) = budget.resolve(rpc_client, |price, limit| {
+                build(index, transaction.instructions.clone(), price, limit)?.message()
+            })?;
+            Ok((transaction.payouts, build(index, transaction.instructions, unit_price, unit_limit)?))
+        })
+        .collect()
+}
+
+// Helper function to print a template, and start its signing session if a session file is given
+fn output_template(
+    intent: String,
+    template: serialization::TransactionTemplate,
+    session_file: Option<PathBuf>,
+    keys: Vec<Pubkey>,
+) -> Result<(), Error> {
+    println!("message digest: {}", template.digest());
+    println!("transaction template: {}", template.serialize_bs58());
+    if let Some(path) = session_file {
+        start_session(intent, keys, template, &path)?;
+    }
+    Ok(())
+}
+
+// Helper function to print a signed transaction in its wire format, to be broadcast by another tool
+fn print_signed_transaction(tx: &VersionedTransaction) -> Result<(), Error> {
+    let tx_bytes = bincode::serialize(tx)
+        .map_err(|e| Error::SerializationError(format!("Failed to encode transaction: {}", e)))?;
+    println!("Transaction signature: {}", tx.signatures[0]);
+    println!("Signed transaction (base58): {}", bs58::encode(&tx_bytes).into_string());
+    println!("Signed transaction (base64): {}", BASE64.encode(&tx_bytes));
+    Ok(())
+}
+
+// Helper function to ask the operator a yes/no question on the terminal
+fn confirm(question: &str) -> Result<bool, Error> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush().map_err(|e| Error::FileReadError(format!("Failed to write to stdout: {}", e)))?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| Error::FileReadError(format!("Failed to read from stdin: {}", e)))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
 fn main() -> Result<(), Error> {
+    check_removed_command()?;
     let opts = Options::parse();
 
     match opts {
@@ -69,7 +308,7 @@ fn main() -> Result<(), Error> {
             println!("The Aggregated Public Key: {}", aggpubkey);
         }
 
-        Options::CreateToken { mint_authority_key, freeze_authority_key, decimals, net } => {
+        Options::CreateToken { mint_authority_key, freeze_authority_key, decimals, mint_args, compute_budget, net } => {
             let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
             
             // Handle mint authority - either generate new or parse provided key
@@ -99,6 +338,8 @@ fn main() -> Result<(), Error> {
                 &mint_authority_keypair.pubkey(),
                 freeze_authority_pubkey.as_ref(),
                 decimals,
+                &mint_args.to_mint_options(),
+                &compute_budget.to_compute_budget(),
             )?;
             
             println!("Token mint created successfully!");
@@ -106,7 +347,7 @@ fn main() -> Result<(), Error> {
             println!("Transaction signature: {}", signature);
         }
 
-        Options::TransferTokens { mint, from_key, to, amount, net } => {
+        Options::TransferTokens { mint, from_key, to, amount, memo, compute_budget, net } => {
             let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
             let from_keypair = parse_keypair(&from_key)?;
             
@@ -118,6 +359,8 @@ fn main() -> Result<(), Error> {
                 &from_keypair,
                 &to,
                 amount,
+                memo,
+                &compute_budget.to_compute_budget(),
             )?;
             
             println!("Token transfer successful!");
@@ -133,7 +376,7 @@ fn main() -> Result<(), Error> {
             println!("Token balance for wallet {}: {} tokens", wallet, balance);
         }
 
-        Options::MintTokens { mint, mint_authority_key, to, amount, decimals, net } => {
+        Options::MintTokens { mint, mint_authority_key, to, amount, decimals, compute_budget, net } => {
             let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
             let mint_authority_keypair = parse_keypair(&mint_authority_key)?;
             
@@ -146,6 +389,7 @@ fn main() -> Result<(), Error> {
                 &mint_authority_keypair,
                 amount,
                 decimals,
+                &compute_budget.to_compute_budget(),
             )?;
             
             println!("Tokens minted successfully!");
@@ -155,202 +399,1001 @@ fn main() -> Result<(), Error> {
             println!("Transaction signature: {}", signature);
         }
 
-        Options::AggSendStepOne { private_key } => {
-            let keypair = parse_keypair(&private_key)?;
+        Options::AggSendStepOne { private_key, key_share, message_digest, session_id, nonce_ledger } => {
+            // Parse the message digest and join the given session, or start a new one
+            let message_digest = message_digest.parse::<Hash>()
+                .map_err(|e| Error::FileReadError(format!("Invalid message digest: {}", e)))?;
+            let session_id = match session_id {
+                Some(session_id) => session_id.parse::<Hash>()
+                    .map_err(|e| Error::FileReadError(format!("Invalid session id: {}", e)))?,
+                None => Hash::new(&rand07::random::<[u8; 32]>()),
+            };
             
-            // Generate nonces for MPC step 1
-            let (public_msg, secret_state) = tss::step_one(keypair);
+            // Generate nonces for MPC step 1, as a keypair holder or as a key share holder
+            let (public_msg, secret_state) = match key_share {
+                Some(key_share) => {
+                    let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+                    let ledger_name = format!("{}-{}", key_share.group_pubkey(), key_share.index);
+                    let mut ledger = NonceLedger::open_or_default(nonce_ledger, &ledger_name)?;
+                    tss::step_one_with_share(&key_share, session_id, message_digest, &mut ledger)?
+                }
+                None => {
+                    let private_key = private_key.ok_or_else(|| Error::FileReadError("Missing private key".to_string()))?;
+                    let keypair = parse_keypair(&private_key)?;
+                    let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+                    tss::step_one(keypair, session_id, message_digest, &mut ledger)?
+                }
+            };
             
             // Output the results
+            println!("session id: {}", session_id);
             println!("secret share: {}", secret_state.serialize_bs58());
             println!("public share: {}", public_msg.serialize_bs58());
         }
 
-        Options::AggSendStepTwoToken { 
-            private_key, 
-            mint, 
-            amount, 
-            decimals, 
-            to, 
-            recent_block_hash, 
-            keys, 
-            first_messages, 
-            secret_state, 
-            net 
+        Options::AggSendStepTwo {
+            private_key,
+            key_share,
+            signers,
+            template,
+            keys,
+            first_messages,
+            secret_state,
+            pool_index,
+            pools,
+            pool_file,
+            nonce_ledger,
         } => {
-            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = serialization::TransactionTemplate::deserialize_bs58(&template)?;
+
+            // Key share holders sign for the group key with the shares of the quorum
+            if let Some(key_share) = key_share {
+                let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+                let ledger_name = format!("{}-{}", key_share.group_pubkey(), key_share.index);
+                let mut ledger = NonceLedger::open_or_default(nonce_ledger, &ledger_name)?;
+                let (parsed_first_messages, parsed_secret_state) = parse_round_one(&first_messages, secret_state)?;
+                let partial_signature = tss::step_two_with_share(
+                    &key_share,
+                    &template,
+                    signers,
+                    parsed_first_messages,
+                    parsed_secret_state,
+                    &mut ledger,
+                )?;
+                println!("partial signature: {}", partial_signature.serialize_bs58());
+                return Ok(());
+            }
+
+            let private_key = private_key.ok_or_else(|| Error::FileReadError("Missing private key".to_string()))?;
             let keypair = parse_keypair(&private_key)?;
-            
-            // Parse recent block hash
-            let block_hash = recent_block_hash.parse::<Hash>()
-                .map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e)))?;
-            
-            // Parse first messages
-            let parsed_first_messages: Result<Vec<serialization::AggMessage1>, Error> = first_messages
-                .iter()
-                .map(|msg| serialization::AggMessage1::deserialize_bs58(msg))
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| Error::FileReadError(format!("Failed to parse first messages: {}", e)));
-            let parsed_first_messages = parsed_first_messages?;
-            
-            // Parse secret state
-            let parsed_secret_state = serialization::SecretAggStepOne::deserialize_bs58(&secret_state)
-                .map_err(|e| Error::FileReadError(format!("Failed to parse secret state: {}", e)))?;
-            
-            // Generate partial signature for token transfer
-            let partial_signature = tss::step_two_token(
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+
+            // Get the step 1 state, either from the step 1 round or from the nonce pools
+            let (parsed_first_messages, parsed_secret_state) = round_one_state(
+                &keypair,
+                &keys,
+                &first_messages,
+                secret_state,
+                pool_index,
+                &pools,
+                pool_file,
+                template.digest(),
+            )?;
+
+            // Sign the exact message of the template
+            let partial_signature = tss::step_two(
                 keypair,
-                mint,
-                amount,
-                decimals,
-                to,
-                block_hash,
+                &template,
                 keys,
                 parsed_first_messages,
                 parsed_secret_state,
-                &rpc_client,
+                &mut ledger,
             )?;
-            
+
             // Output the partial signature
             println!("partial signature: {}", partial_signature.serialize_bs58());
         }
 
-        Options::AggregateSignaturesAndBroadcastToken {
+        Options::AggregateSignaturesAndBroadcast {
+            template,
+            key_share,
+            signers,
             signatures,
-            mint,
-            amount,
-            decimals,
-            to,
-            recent_block_hash,
             keys,
+            first_messages,
+            pool_index,
+            pools,
+            no_broadcast,
             net,
         } => {
             let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            
-            // Parse recent block hash
-            let block_hash = recent_block_hash.parse::<Hash>()
-                .map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e)))?;
-            
+            let template = serialization::TransactionTemplate::deserialize_bs58(&template)?;
+
             // Parse partial signatures
-            let parsed_signatures: Result<Vec<serialization::PartialSignature>, Error> = signatures
-                .iter()
-                .map(|sig| serialization::PartialSignature::deserialize_bs58(sig))
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| Error::FileReadError(format!("Failed to parse signatures: {}", e)));
-            let parsed_signatures = parsed_signatures?;
-            
-            // Aggregate signatures and create final transaction
-            let tx = tss::sign_and_broadcast_token(
-                mint,
-                amount,
-                decimals,
-                to,
-                block_hash,
-                keys,
-                parsed_signatures,
-                &rpc_client,
-            )?;
-            
+            let parsed_signatures: Vec<serialization::PartialSignature> = parse_messages(&signatures, "signatures")?;
+
+            // Parse first messages, or rebuild them from the nonce pool entry the parties signed with
+            let parsed_first_messages: Vec<serialization::AggMessage1> = match pool_index {
+                Some(index) => {
+                    let pools: Vec<serialization::NoncePool> = parse_messages(&pools, "nonce pools")?;
+                    nonce_pool::first_messages(&keys, &pools, index, template.digest())?
+                }
+                None => parse_messages(&first_messages, "first messages")?,
+            };
+
+            // Aggregate signatures into the transaction of the template
+            let tx = match key_share {
+                Some(key_share) => {
+                    let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+                    tss::sign_and_broadcast_with_shares(
+                        &key_share,
+                        &template,
+                        signers,
+                        parsed_first_messages,
+                        parsed_signatures,
+                    )?
+                }
+                None => tss::sign_and_broadcast(&template, keys, parsed_first_messages, parsed_signatures)?,
+            };
+            if no_broadcast {
+                return print_signed_transaction(&tx);
+            }
+
+            // A durable nonce must still hold the value everyone signed
+            template.check_durable_nonce(&rpc_client)?;
+
             // Send the transaction
             let signature = rpc_client.send_transaction(&tx)
                 .map_err(|e| Error::FileReadError(format!("Failed to send transaction: {}", e)))?;
-            
-            println!("Token transfer successful!");
+
+            println!("Transaction sent!");
             println!("Transaction ID: {}", signature);
-            println!("Mint: {}", mint);
-            println!("To: {}", to);
-            println!("Amount: {} tokens", amount);
         }
 
-        Options::AggSendStepTwoSol { 
-            private_key, 
-            amount, 
-            to, 
-            memo,
-            recent_block_hash, 
-            keys, 
-            first_messages, 
-            secret_state, 
-            net: _ 
+        Options::ThresholdKeygen { threshold, parties } => {
+            let shares = frost::keygen_with_dealer(threshold, parties)?;
+
+            println!("The Group Public Key: {}", shares[0].group_pubkey());
+            for share in shares {
+                println!("key share {}: {}", share.index, share.serialize_bs58());
+            }
+        }
+
+        Options::ThresholdSendStepOne { key_share, nonce_ledger } => {
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let ledger_name = format!("{}-{}", key_share.group_pubkey(), key_share.index);
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &ledger_name)?;
+
+            // Generate nonce commitments for threshold step 1
+            let (public_msg, secret_state) = frost::step_one(&key_share, &mut ledger)?;
+
+            // Output the results
+            println!("secret share: {}", secret_state.serialize_bs58());
+            println!("public share: {}", public_msg.serialize_bs58());
+        }
+
+        Options::ThresholdSendStepTwo {
+            key_share,
+            template,
+            first_messages,
+            secret_state,
+            nonce_ledger,
         } => {
-            let keypair = parse_keypair(&private_key)?;
-            
-            // Parse recent block hash
-            let block_hash = recent_block_hash.parse::<Hash>()
-                .map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e)))?;
-            
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let ledger_name = format!("{}-{}", key_share.group_pubkey(), key_share.index);
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &ledger_name)?;
+            let template = serialization::TransactionTemplate::deserialize_bs58(&template)?;
+
             // Parse first messages
-            let parsed_first_messages: Result<Vec<serialization::AggMessage1>, Error> = first_messages
-                .iter()
-                .map(|msg| serialization::AggMessage1::deserialize_bs58(msg))
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| Error::FileReadError(format!("Failed to parse first messages: {}", e)));
-            let parsed_first_messages = parsed_first_messages?;
-            
+            let parsed_first_messages: Vec<serialization::ThresholdMessage1> =
+                parse_messages(&first_messages, "first messages")?;
+
             // Parse secret state
-            let parsed_secret_state = serialization::SecretAggStepOne::deserialize_bs58(&secret_state)
+            let parsed_secret_state = serialization::SecretThresholdStepOne::deserialize_bs58(&secret_state)
                 .map_err(|e| Error::FileReadError(format!("Failed to parse secret state: {}", e)))?;
-            
-            // Generate partial signature for SOL transfer
-            let partial_signature = tss::step_two_sol(
-                keypair,
-                amount,
-                to,
-                memo,
-                block_hash,
-                keys,
+
+            // Sign the exact message of the template
+            let partial_signature = frost::step_two(
+                &key_share,
+                &template,
                 parsed_first_messages,
                 parsed_secret_state,
+                &mut ledger,
             )?;
-            
+
             // Output the partial signature
             println!("partial signature: {}", partial_signature.serialize_bs58());
         }
 
-        Options::AggregateSignaturesAndBroadcastSol {
+        Options::ThresholdAggregateSignaturesAndBroadcast {
+            key_share,
+            template,
+            first_messages,
             signatures,
-            amount,
-            to,
-            memo,
-            recent_block_hash,
-            keys,
+            no_broadcast,
             net,
         } => {
             let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
-            
-            // Parse recent block hash
-            let block_hash = recent_block_hash.parse::<Hash>()
-                .map_err(|e| Error::FileReadError(format!("Invalid block hash: {}", e)))?;
-            
-            // Parse partial signatures
-            let parsed_signatures: Result<Vec<serialization::PartialSignature>, Error> = signatures
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let template = serialization::TransactionTemplate::deserialize_bs58(&template)?;
+
+            // Parse first messages and partial signatures
+            let parsed_first_messages: Vec<serialization::ThresholdMessage1> =
+                parse_messages(&first_messages, "first messages")?;
+            let parsed_signatures: Vec<serialization::ThresholdPartialSignature> =
+                parse_messages(&signatures, "signatures")?;
+
+            // Verify every share and aggregate them into the transaction of the template
+            let tx = frost::sign_and_broadcast(&key_share, &template, parsed_first_messages, parsed_signatures)?;
+            if no_broadcast {
+                return print_signed_transaction(&tx);
+            }
+
+            // A durable nonce must still hold the value everyone signed
+            template.check_durable_nonce(&rpc_client)?;
+
+            // Send the transaction
+            let signature = rpc_client.send_transaction(&tx)
+                .map_err(|e| Error::FileReadError(format!("Failed to send transaction: {}", e)))?;
+
+            println!("Transaction sent!");
+            println!("Transaction ID: {}", signature);
+        }
+
+        Options::DkgCommit { ceremony_id, index, threshold, parties } => {
+            // Join the given ceremony, or start a new one
+            let ceremony_id = match ceremony_id {
+                Some(ceremony_id) => ceremony_id.parse::<Hash>()
+                    .map_err(|e| Error::FileReadError(format!("Invalid ceremony id: {}", e)))?,
+                None => Hash::new(&rand07::random::<[u8; 32]>()),
+            };
+            let (commitment, secret) = dkg::commit(ceremony_id, index, threshold, parties)?;
+
+            println!("ceremony id: {}", ceremony_id);
+            println!("secret: {}", secret.serialize_bs58());
+            println!("commitment: {}", commitment.serialize_bs58());
+        }
+
+        Options::DkgShare { secret, commitments } => {
+            let secret = serialization::DkgSecret::deserialize_bs58(&secret)?;
+            let commitments: Vec<serialization::DkgCommitment> = parse_messages(&commitments, "commitments")?;
+
+            // Every share is secret, send each one only to its receiver
+            for share in dkg::share(&secret, &commitments)? {
+                println!("share for party {}: {}", share.receiver_index, share.serialize_bs58());
+            }
+        }
+
+        Options::DkgVerify { secret, commitments, shares } => {
+            let secret = serialization::DkgSecret::deserialize_bs58(&secret)?;
+            let commitments: Vec<serialization::DkgCommitment> = parse_messages(&commitments, "commitments")?;
+            let shares: Vec<serialization::DkgShare> = parse_messages(&shares, "shares")?;
+
+            dkg::verify_shares(&secret, &commitments, &shares)?;
+            println!("All {} shares are consistent with the commitments", shares.len());
+        }
+
+        Options::DkgFinalize { secret, commitments, shares } => {
+            let secret = serialization::DkgSecret::deserialize_bs58(&secret)?;
+            let commitments: Vec<serialization::DkgCommitment> = parse_messages(&commitments, "commitments")?;
+            let shares: Vec<serialization::DkgShare> = parse_messages(&shares, "shares")?;
+
+            let key_share = dkg::finalize(&secret, &commitments, &shares)?;
+            println!("The Group Public Key: {}", key_share.group_pubkey());
+            println!("key share {}: {}", key_share.index, key_share.serialize_bs58());
+        }
+
+        Options::RefreshCommit { refresh_id, key_share, parties } => {
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+
+            // Join the given refresh, or start a new one
+            let refresh_id = match refresh_id {
+                Some(refresh_id) => refresh_id.parse::<Hash>()
+                    .map_err(|e| Error::FileReadError(format!("Invalid refresh id: {}", e)))?,
+                None => Hash::new(&rand07::random::<[u8; 32]>()),
+            };
+            let (commitment, secret) = dkg::refresh_commit(refresh_id, &key_share, parties)?;
+
+            println!("refresh id: {}", refresh_id);
+            println!("secret: {}", secret.serialize_bs58());
+            println!("commitment: {}", commitment.serialize_bs58());
+        }
+
+        Options::RefreshShare { secret, commitments } => {
+            let secret = serialization::DkgSecret::deserialize_bs58(&secret)?;
+            let commitments: Vec<serialization::RefreshCommitment> = parse_messages(&commitments, "commitments")?;
+
+            // Every share is secret, send each one only to its receiver
+            for share in dkg::refresh_share(&secret, &commitments)? {
+                println!("share for party {}: {}", share.receiver_index, share.serialize_bs58());
+            }
+        }
+
+        Options::RefreshFinalize { key_share, secret, commitments, shares } => {
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let secret = serialization::DkgSecret::deserialize_bs58(&secret)?;
+            let commitments: Vec<serialization::RefreshCommitment> = parse_messages(&commitments, "commitments")?;
+            let shares: Vec<serialization::DkgShare> = parse_messages(&shares, "shares")?;
+
+            let refreshed = dkg::refresh_finalize(&key_share, &secret, &commitments, &shares)?;
+            println!("The Group Public Key: {}", refreshed.group_pubkey());
+            println!("key share {}: {}", refreshed.index, refreshed.serialize_bs58());
+        }
+
+        Options::RefreshVerify { old_key_share, new_key_share, refresh_id, commitments } => {
+            let old_key_share = serialization::ThresholdKeyShare::deserialize_bs58(&old_key_share)?;
+            let new_key_share = serialization::ThresholdKeyShare::deserialize_bs58(&new_key_share)?;
+            let refresh_id = refresh_id.parse::<Hash>()
+                .map_err(|e| Error::FileReadError(format!("Invalid refresh id: {}", e)))?;
+            let commitments: Vec<serialization::RefreshCommitment> = parse_messages(&commitments, "commitments")?;
+
+            dkg::verify_refresh(&old_key_share, &new_key_share, &refresh_id, &commitments)?;
+            println!("Key share {} still signs for {}", new_key_share.index, new_key_share.group_pubkey());
+        }
+
+        Options::ReshareTranscript { key_share, old_parties, new_parties, new_threshold } => {
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let old_parties = old_parties
                 .iter()
-                .map(|sig| serialization::PartialSignature::deserialize_bs58(sig))
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|e| Error::FileReadError(format!("Failed to parse signatures: {}", e)));
-            let parsed_signatures = parsed_signatures?;
-            
-            // Aggregate signatures and create final transaction
-            let tx = tss::sign_and_broadcast_sol(
-                amount,
-                to,
-                memo.clone(),
-                block_hash,
-                keys,
-                parsed_signatures,
+                .map(|pair| parse_indexed_pubkey(pair))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let transcript = reshare::create_transcript(&key_share, old_parties, new_parties, new_threshold)?;
+            println!("Ceremony id: {}", transcript.ceremony_id);
+            println!("Transcript digest: {}", transcript.digest());
+            println!("transcript: {}", transcript.serialize_bs58());
+        }
+
+        Options::ReshareApprove { transcript, identity_key } => {
+            let transcript = serialization::ReshareTranscript::deserialize_bs58(&transcript)?;
+            let identity = parse_keypair(&identity_key)?;
+            transcript.validate()?;
+
+            println!("Ceremony id: {}", transcript.ceremony_id);
+            println!("Group public key: {}", tss::point_to_pubkey(&transcript.group_commitments[0]));
+            for (index, party) in &transcript.old_parties {
+                println!("Old party {}: {}", index, party);
+            }
+            for (position, party) in transcript.new_parties.iter().enumerate() {
+                println!("New party {}: {}", position + 1, party);
+            }
+            println!("New threshold: {} of {}", transcript.new_threshold, transcript.new_parties.len());
+            println!("Transcript digest: {}", transcript.digest());
+            println!("approval: {}", reshare::approve(&transcript, &identity).serialize_bs58());
+        }
+
+        Options::ReshareDeal { key_share, transcript, approvals } => {
+            let key_share = serialization::ThresholdKeyShare::deserialize_bs58(&key_share)?;
+            let transcript = serialization::ReshareTranscript::deserialize_bs58(&transcript)?;
+            let approvals: Vec<serialization::ReshareApproval> = parse_messages(&approvals, "approvals")?;
+
+            let (commitment, shares) = reshare::deal(&key_share, &transcript, &approvals)?;
+            println!("commitment: {}", commitment.serialize_bs58());
+            // Every share is secret, send each one only to its receiver
+            for share in shares {
+                println!("share for new party {}: {}", share.receiver_index, share.serialize_bs58());
+            }
+        }
+
+        Options::ReshareFinalize { transcript, approvals, identity, commitments, shares } => {
+            let transcript = serialization::ReshareTranscript::deserialize_bs58(&transcript)?;
+            let approvals: Vec<serialization::ReshareApproval> = parse_messages(&approvals, "approvals")?;
+            let commitments: Vec<serialization::ReshareCommitment> = parse_messages(&commitments, "commitments")?;
+            let shares: Vec<serialization::DkgShare> = parse_messages(&shares, "shares")?;
+
+            let key_share = reshare::finalize(&transcript, &approvals, &identity, &commitments, &shares)?;
+            println!("The Group Public Key: {}", key_share.group_pubkey());
+            println!("key share {}: {}", key_share.index, key_share.serialize_bs58());
+        }
+
+        Options::TemplateToken { mint, amount, decimals, to, memo, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+
+            // Build the transaction once, every party signs exactly these bytes
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::token_transfer(mint, amount, decimals, &to, memo.clone(), &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let mut intent = format!("Transfer {} base units of mint {} ({} decimals) from {} to {}", amount, mint, decimals, args.wallet, to);
+            if let Some(memo) = &memo {
+                intent.push_str(&format!(" with memo \"{}\"", memo));
+            }
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateSol { amount, to, memo, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+
+            // Build the transaction once, every party signs exactly these bytes
+            let intent = match &memo {
+                Some(memo) => format!("Transfer {} SOL from {} to {} with memo \"{}\"", amount, args.wallet, to, memo),
+                None => format!("Transfer {} SOL from {} to {}", amount, args.wallet, to),
+            };
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::sol_transfer(amount, &to, memo.clone(), &args.wallet, block_hash, options)
+            })?;
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateImport { message, encoding, session_file, keys, intent } => {
+            let bytes = match encoding {
+                cli::Encoding::Base58 => bs58::decode(message.trim()).into_vec()?,
+                cli::Encoding::Base64 => BASE64
+                    .decode(message.trim())
+                    .map_err(|e| Error::SerializationError(format!("Invalid base64 message: {}", e)))?,
+            };
+
+            // Sign the message exactly as the other tool built it, after showing what it does
+            let template = template::import(&bytes)?;
+            for line in template.describe()? {
+                println!("{}", line);
+            }
+            output_template(intent, template, session_file, keys)?;
+        }
+
+        Options::AggNoncePool { private_key, size, pool_file, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+
+            // Generate the pool and keep the secret half locally
+            let (pool, secret_pool) = nonce_pool::generate(&keypair, size, &mut ledger)?;
+            let path = match pool_file {
+                Some(path) => path,
+                None => nonce_pool::default_path(&keypair.pubkey(), &pool.pool_id)?,
+            };
+            nonce_pool::save(&secret_pool, &path)?;
+
+            println!("secret pool saved to: {}", path.display());
+            println!("nonce pool: {}", pool.serialize_bs58());
+        }
+
+        Options::CreateNonceAccount { payer_key, authority, compute_budget, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let payer = parse_keypair(&payer_key)?;
+            let nonce_keypair = Keypair::new();
+
+            let signature = token::create_nonce_account(
+                &rpc_client,
+                &payer,
+                &nonce_keypair,
+                &authority,
+                &compute_budget.to_compute_budget(),
             )?;
-            
+            let (nonce_value, _) = token::get_nonce_value(&rpc_client, &nonce_keypair.pubkey())?;
+            println!("Nonce account created!");
+            println!("Transaction ID: {}", signature);
+            println!("Nonce account: {}", nonce_keypair.pubkey());
+            println!("Authority: {}", authority);
+            println!("Nonce value: {}", nonce_value);
+        }
+
+        Options::NonceValue { nonce_account, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let (nonce_value, authority) = token::get_nonce_value(&rpc_client, &nonce_account)?;
+            println!("Nonce value: {}", nonce_value);
+            println!("Authority: {}", authority);
+        }
+
+        Options::SessionStepOne { private_key, session_file, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            let mut session = session::Session::load(&session_file)?;
+            if !session.keys.contains(&keypair.pubkey()) {
+                return Err(Error::KeyPairIsNotInKeys);
+            }
+
+            // Generate nonces bound to the session and its template, and publish the public half
+            let (public_msg, secret_state) = tss::step_one(keypair, session.session_id, session.message_digest(), &mut ledger)?;
+            session.add_first_message(public_msg)?;
+            session.save(&session_file)?;
+
+            println!("secret share: {}", secret_state.serialize_bs58());
+            println!("first message added to: {}", session_file.display());
+        }
+
+        Options::SessionStepTwo { private_key, session_file, secret_state, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            let mut session = session::Session::load(&session_file)?;
+            let missing = session.missing_first_messages();
+            if !missing.is_empty() {
+                return Err(Error::SessionMismatch(format!(
+                    "still waiting for first messages from: {}",
+                    missing.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                )));
+            }
+            let secret_state = serialization::SecretAggStepOne::deserialize_bs58(&secret_state)
+                .map_err(|e| Error::FileReadError(format!("Failed to parse secret state: {}", e)))?;
+
+            // Sign the template of the session
+            let first_messages = session.first_messages_of_others(&keypair.pubkey());
+            let partial_signature = tss::step_two(
+                keypair,
+                &session.template,
+                session.keys.clone(),
+                first_messages,
+                secret_state,
+                &mut ledger,
+            )?;
+            session.add_partial_signature(partial_signature)?;
+            session.save(&session_file)?;
+
+            println!("partial signature added to: {}", session_file.display());
+        }
+
+        Options::SessionAdd { session_file, messages } => {
+            let mut session = session::Session::load(&session_file)?;
+            for message in &messages {
+                session.add_message(message)?;
+            }
+            session.save(&session_file)?;
+            println!("{} messages added to: {}", messages.len(), session_file.display());
+        }
+
+        Options::SessionShow { session_file } => {
+            let session = session::Session::load(&session_file)?;
+            let list = |keys: Vec<Pubkey>| keys.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+
+            println!("Intent: {}", session.intent);
+            println!("Wallet: {}", session.template.fee_payer()?);
+            println!("Session id: {}", session.session_id);
+            println!("Message digest: {}", session.message_digest());
+            println!("Waiting for first messages from: {}", list(session.missing_first_messages()));
+            println!("Waiting for partial signatures from: {}", list(session.missing_partial_signatures()));
+            for line in session.template.describe()? {
+                println!("{}", line);
+            }
+        }
+
+        Options::SessionBroadcast { session_file, no_broadcast, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let session = session::Session::load(&session_file)?;
+
+            // Aggregate signatures into the transaction of the template
+            let tx = tss::sign_and_broadcast(
+                &session.template,
+                session.keys.clone(),
+                session.first_messages.clone(),
+                session.partial_signatures.clone(),
+            )?;
+            if no_broadcast {
+                return print_signed_transaction(&tx);
+            }
+
+            // A durable nonce must still hold the value everyone signed
+            session.template.check_durable_nonce(&rpc_client)?;
+
             // Send the transaction
             let signature = rpc_client.send_transaction(&tx)
                 .map_err(|e| Error::FileReadError(format!("Failed to send transaction: {}", e)))?;
-            
-            println!("SOL transfer successful!");
+
+            println!("Transaction sent!");
+            println!("Intent: {}", session.intent);
             println!("Transaction ID: {}", signature);
-            println!("To: {}", to);
-            println!("Amount: {} SOL", amount);
-            if let Some(memo_text) = memo {
-                println!("Memo: {}", memo_text);
+        }
+
+        Options::Coordinate { session_file, listen, timeout, no_broadcast, net } => {
+            let session = session::Session::load(&session_file)?;
+            let listener = std::net::TcpListener::bind(&listen)
+                .map_err(|e| Error::CoordinatorError(format!("Failed to listen on {}: {}", listen, e)))?;
+            println!("Coordinating session {} on {}", session.session_id, listen);
+            println!("Intent: {}", session.intent);
+
+            // Relay both rounds between the parties
+            let tx = coordinator::coordinate(&listener, &session, Duration::from_secs(timeout))?;
+            if no_broadcast {
+                return print_signed_transaction(&tx);
+            }
+
+            // A durable nonce must still hold the value everyone signed
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            session.template.check_durable_nonce(&rpc_client)?;
+            let signature = rpc_client.send_transaction(&tx)
+                .map_err(|e| Error::FileReadError(format!("Failed to send transaction: {}", e)))?;
+
+            println!("Transaction sent!");
+            println!("Transaction ID: {}", signature);
+        }
+
+        Options::Participate { private_key, coordinator, message_digest, yes, timeout, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            let message_digest = message_digest
+                .map(|digest| digest.parse::<Hash>())
+                .transpose()
+                .map_err(|e| Error::FileReadError(format!("Invalid message digest: {}", e)))?;
+
+            // Only sign what we expect, or what we confirm
+            let approve = |session: &session::Session| {
+                println!("Intent: {}", session.intent);
+                for line in session.template.describe()? {
+                    println!("{}", line);
+                }
+                println!("Message digest: {}", session.message_digest());
+                match message_digest {
+                    Some(digest) if digest != session.message_digest() => Err(Error::SessionMismatch(format!(
+                        "the coordinator asks to sign message {}, expected {}",
+                        session.message_digest(),
+                        digest
+                    ))),
+                    Some(_) => Ok(()),
+                    None if yes || confirm("Sign this transaction?")? => Ok(()),
+                    None => Err(Error::SessionMismatch("signing declined".to_string())),
+                }
+            };
+            let (session, tx) = coordinator::participate(
+                coordinator.as_str(),
+                keypair,
+                &mut ledger,
+                Duration::from_secs(timeout),
+                approve,
+            )?;
+
+            println!("Session {} signed", session.session_id);
+            println!("Transaction signature: {}", tx.signatures[0]);
+        }
+        Options::Daemon { private_key, listen, socket, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            println!("Signer daemon for {}", keypair.pubkey());
+            let signer = daemon::Daemon::new(keypair, ledger);
+            match socket {
+                Some(path) => {
+                    println!("Listening on {}", path.display());
+                    daemon::serve_unix(signer, &path)?;
+                }
+                None => {
+                    let addr = listen
+                        .parse::<std::net::SocketAddr>()
+                        .map_err(|e| Error::DaemonError(format!("Invalid listen address {}: {}", listen, e)))?;
+                    println!("Listening on {}", addr);
+                    daemon::serve_tcp(signer, addr)?;
+                }
+            }
+        }
+
+        Options::TemplateCreateLookupTable { recent_slot, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let recent_slot = match recent_slot {
+                Some(slot) => slot,
+                None => rpc_client
+                    .get_slot()
+                    .map_err(|e| Error::LookupTableError(format!("Failed to get the current slot: {}", e)))?,
+            };
+
+            // The table address depends on the slot, so it's fixed along with the template
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                Ok(template::create_lookup_table(&args.wallet, recent_slot, block_hash, options)?.0)
+            })?;
+            let (lookup_table, _) = derive_lookup_table_address(&args.wallet, recent_slot);
+            println!("lookup table: {}", lookup_table);
+            let intent = format!("Create address lookup table {} owned by {}", lookup_table, args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateExtendLookupTable { lookup_table, addresses, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+
+            let intent = format!("Add {} addresses to address lookup table {}", addresses.len(), lookup_table);
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::extend_lookup_table(&lookup_table, addresses.clone(), &args.wallet, block_hash, options)
+            })?;
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateCreateMint { decimals, seed, no_freeze_authority, mint_args, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let seed = seed.unwrap_or_else(|| bs58::encode(rand07::random::<[u8; 16]>()).into_string());
+
+            // The mint address comes from the wallet and the seed, there's no mint keypair to keep or lose
+            let mint_options = mint_args.to_mint_options();
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                let freeze = !no_freeze_authority;
+                Ok(template::create_mint(&seed, decimals, freeze, &mint_options, &args.wallet, block_hash, options, &rpc_client)?.0)
+            })?;
+            let mint = Pubkey::create_with_seed(&args.wallet, &seed, &mint_options.program_id())
+                .map_err(|e| Error::TokenCreationFailed(format!("Invalid seed {:?}: {}", seed, e)))?;
+            println!("mint: {}", mint);
+            println!("seed: {}", seed);
+            let intent = format!("Create mint {} ({} decimals) with {} as mint authority", mint, decimals, args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateMintTo { mint, amount, decimals, to, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::mint_to(mint, amount, decimals, &to, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Mint {} base units of mint {} ({} decimals) to {}", amount, mint, decimals, to);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateBurn { mint, amount, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::burn(mint, amount, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Burn {} base units of mint {} held by {}", amount, mint, args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateFreeze { mint, owner, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::freeze_account(mint, &owner, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Freeze the token account of {} for mint {}", owner, mint);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateThaw { mint, owner, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::thaw_account(mint, &owner, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Thaw the token account of {} for mint {}", owner, mint);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateCloseAccount { mint, destination, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let destination = destination.unwrap_or(args.wallet);
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::close_account(mint, &destination, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Close the token account of {} for mint {}, sending its rent to {}", args.wallet, mint, destination);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateApprove { mint, delegate, amount, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::approve(mint, &delegate, amount, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Let {} spend up to {} base units of mint {} held by {}", delegate, amount, mint, args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateRevoke { mint, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::revoke(mint, &args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Remove the delegate of the token account of {} for mint {}", args.wallet, mint);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateSetAuthority { account, authority_type, new_authority, remove: _, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::set_authority(
+                    &account,
+                    authority_type.to_authority_type(),
+                    new_authority.as_ref(),
+                    &args.wallet,
+                    block_hash,
+                    options,
+                    &rpc_client,
+                )
+            })?;
+            let intent = match new_authority {
+                Some(new_authority) => format!("Hand the {:?} authority of {} over to {}", authority_type, account, new_authority),
+                None => format!("Remove the {:?} authority of {} for good", authority_type, account),
+            };
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateWrapSol { amount, args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::wrap_sol(amount, &args.wallet, block_hash, options)
+            })?;
+            let intent = format!("Wrap {} SOL of {}", amount, args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateUnwrapSol { args, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let template = build_template(&args, &rpc_client, |block_hash, options| {
+                template::unwrap_sol(&args.wallet, block_hash, options, &rpc_client)
+            })?;
+            let intent = format!("Unwrap all the wrapped SOL of {}", args.wallet);
+            output_template(intent, template, args.session_file, args.keys)?;
+        }
+
+        Options::TemplateBatch {
+            csv,
+            wallet,
+            recent_block_hash,
+            nonce_accounts,
+            lookup_tables,
+            batch_file,
+            keys,
+            compute_budget,
+            net,
+        } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let payouts = batch::read_payouts(&csv)?;
+
+            // Pack the payouts once, every party signs exactly these transactions
+            let transactions = build_batch(
+                &rpc_client,
+                &payouts,
+                &wallet,
+                recent_block_hash,
+                &nonce_accounts,
+                &lookup_tables,
+                &compute_budget.to_compute_budget(),
+            )?;
+            let batch = batch::Batch::new(payouts, keys, transactions)?;
+            batch.save(&batch_file)?;
+            for (number, transaction) in batch.transactions.iter().enumerate() {
+                println!(
+                    "transaction {}: {} payouts, session id {}",
+                    number + 1,
+                    transaction.payouts.len(),
+                    transaction.session.session_id
+                );
+            }
+            println!("{} payouts in {} transactions saved to: {}", batch.payouts.len(), batch.transactions.len(), batch_file.display());
+        }
+
+        Options::BatchStepOne { private_key, batch_file, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            let mut batch = batch::Batch::load(&batch_file)?;
+            if batch.transactions.iter().any(|transaction| !transaction.session.keys.contains(&keypair.pubkey())) {
+                return Err(Error::KeyPairIsNotInKeys);
+            }
+
+            // One nonce pair per transaction, each bound to its own session and template
+            let mut secret_states = Vec::new();
+            for transaction in &mut batch.transactions {
+                let session = &mut transaction.session;
+                let (public_msg, secret_state) =
+                    tss::step_one(keypair.insecure_clone(), session.session_id, session.message_digest(), &mut ledger)?;
+                session.add_first_message(public_msg)?;
+                secret_states.push(secret_state.serialize_bs58());
+            }
+            batch.save(&batch_file)?;
+
+            println!("secret shares: {}", secret_states.join(","));
+            println!("{} first messages added to: {}", secret_states.len(), batch_file.display());
+        }
+
+        Options::BatchStepTwo { private_key, batch_file, secret_states, nonce_ledger } => {
+            let keypair = parse_keypair(&private_key)?;
+            let mut ledger = NonceLedger::open_or_default(nonce_ledger, &keypair.pubkey().to_string())?;
+            let mut batch = batch::Batch::load(&batch_file)?;
+            let missing = batch.missing_first_messages();
+            if !missing.is_empty() {
+                return Err(Error::SessionMismatch(format!(
+                    "still waiting for first messages from: {}",
+                    missing.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                )));
+            }
+            if secret_states.len() != batch.transactions.len() {
+                return Err(Error::BatchError(format!(
+                    "expected {} secret states, one per transaction, found {}",
+                    batch.transactions.len(),
+                    secret_states.len()
+                )));
+            }
+            let secret_states: Vec<serialization::SecretAggStepOne> = parse_messages(&secret_states, "secret states")?;
+
+            // Keep the signatures made before a failure, their nonces are used up either way
+            let mut result = Ok(());
+            for (transaction, secret_state) in batch.transactions.iter_mut().zip(secret_states) {
+                let session = &mut transaction.session;
+                let first_messages = session.first_messages_of_others(&keypair.pubkey());
+                match tss::step_two(
+                    keypair.insecure_clone(),
+                    &session.template,
+                    session.keys.clone(),
+                    first_messages,
+                    secret_state,
+                    &mut ledger,
+                ) {
+                    Ok(partial_signature) => session.add_partial_signature(partial_signature)?,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            batch.save(&batch_file)?;
+            result?;
+
+            println!("{} partial signatures added to: {}", batch.transactions.len(), batch_file.display());
+        }
+
+        Options::BatchAdd { batch_file, messages } => {
+            let mut batch = batch::Batch::load(&batch_file)?;
+            for message in &messages {
+                batch.add_message(message)?;
+            }
+            batch.save(&batch_file)?;
+            println!("{} messages added to: {}", messages.len(), batch_file.display());
+        }
+
+        Options::BatchShow { batch_file } => {
+            let batch = batch::Batch::load(&batch_file)?;
+            let list = |keys: Vec<Pubkey>| keys.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+
+            println!("Payouts: {} in {} transactions", batch.payouts.len(), batch.transactions.len());
+            println!("Waiting for first messages from: {}", list(batch.missing_first_messages()));
+            println!("Waiting for partial signatures from: {}", list(batch.missing_partial_signatures()));
+            for (number, transaction) in batch.transactions.iter().enumerate() {
+                println!();
+                println!("Transaction {}/{}", number + 1, batch.transactions.len());
+                println!("Session id: {}", transaction.session.session_id);
+                println!("Message digest: {}", transaction.session.message_digest());
+                for &index in &transaction.payouts {
+                    let payout = &batch.payouts[index];
+                    println!("line {}: {} of mint {} to {}", payout.line, payout.amount, payout.mint, payout.recipient);
+                }
+                for line in transaction.session.template.describe()? {
+                    println!("{}", line);
+                }
+            }
+        }
+
+        Options::BatchBroadcast { batch_file, no_broadcast, net } => {
+            let rpc_client = RpcClient::new(net.get_cluster_url().to_string());
+            let batch = batch::Batch::load(&batch_file)?;
+            let missing = batch.missing_partial_signatures();
+            if !missing.is_empty() {
+                return Err(Error::SessionMismatch(format!(
+                    "still waiting for partial signatures from: {}",
+                    missing.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                )));
+            }
+
+            // Aggregate every transaction before sending any, so a bad signature stops the whole batch
+            let txs = batch
+                .transactions
+                .iter()
+                .map(|transaction| {
+                    let session = &transaction.session;
+                    tss::sign_and_broadcast(
+                        &session.template,
+                        session.keys.clone(),
+                        session.first_messages.clone(),
+                        session.partial_signatures.clone(),
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if no_broadcast {
+                for tx in &txs {
+                    print_signed_transaction(tx)?;
+                }
+                return Ok(());
+            }
+
+            // Report every payout, a failed transaction doesn't stop the others
+            let mut failed = 0;
+            for (transaction, tx) in batch.transactions.iter().zip(&txs) {
+                let outcome = transaction
+                    .session
+                    .template
+                    .check_durable_nonce(&rpc_client)
+                    .and_then(|_| rpc_client.send_and_confirm_transaction(tx).map_err(Error::ConfirmingTransactionFailed));
+                let status = match outcome {
+                    Ok(signature) => format!("confirmed in {}", signature),
+                    Err(e) => {
+                        failed += transaction.payouts.len();
+                        format!("failed: {}", e)
+                    }
+                };
+                for &index in &transaction.payouts {
+                    let payout = &batch.payouts[index];
+                    println!("line {}: {} of mint {} to {}: {}", payout.line, payout.amount, payout.mint, payout.recipient, status);
+                }
+            }
+            if failed > 0 {
+                return Err(Error::BatchError(format!("{} of {} payouts failed", failed, batch.payouts.len())));
             }
+            println!("All {} payouts confirmed", batch.payouts.len());
         }
     }
 
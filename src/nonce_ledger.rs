@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use curv::elliptic::curves::{Ed25519, Point};
+use rand07::RngCore;
+use solana_sdk::hash::{hashv, Hash};
+
+use crate::error::Error;
+
+/// State of a nonce pair in the ledger
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NonceStatus {
+    Generated,
+    /// Consumed while signing the message with this digest
+    Consumed(String),
+}
+
+/// Append-only, per-party record of every nonce pair generated in step one and whether it was used to sign.
+/// Each line is flushed to disk before the caller proceeds, so the ledger survives restarts and crashes:
+/// a nonce is marked consumed *before* the partial signature exists, so a crash can only burn a nonce, never reuse it.
+pub struct NonceLedger {
+    path: PathBuf,
+    entries: HashMap<String, NonceStatus>,
+}
+
+/// Identifier of a nonce pair in the ledger, derived from its public part
+pub fn nonce_id(public_nonces: &[Point<Ed25519>; 2]) -> String {
+    hashv(&[&public_nonces[0].to_bytes(true), &public_nonces[1].to_bytes(true)]).to_string()
+}
+
+/// Default ledger location for a party: `~/.solana-mpc-tokens/nonce-ledger/<name>.log`
+pub fn default_path(name: &str) -> Result<PathBuf, Error> {
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| Error::NonceLedgerError("HOME is not set, pass the ledger path explicitly".to_string()))?;
+    Ok(Path::new(&home).join(".solana-mpc-tokens").join("nonce-ledger").join(format!("{}.log", name)))
+}
+
+impl NonceLedger {
+    /// Open the ledger at `path`, creating it if it doesn't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| Error::NonceLedgerError(format!("Failed to create {}: {}", parent.display(), e)))?;
+        }
+        let mut ledger = Self { path, entries: HashMap::new() };
+        ledger.reload()?;
+        Ok(ledger)
+    }
+
+    /// Open the ledger at `path` if given, otherwise at the default location for `name`
+    pub fn open_or_default(path: Option<PathBuf>, name: &str) -> Result<Self, Error> {
+        match path {
+            Some(path) => Self::open(path),
+            None => Self::open(default_path(name)?),
+        }
+    }
+
+    /// Re-read the ledger from disk, the first `consumed` line of a nonce wins.
+    /// Returns the token of that winning line for every consumed nonce.
+    fn reload(&mut self) -> Result<HashMap<String, String>, Error> {
+        let mut winners = HashMap::new();
+        self.entries.clear();
+
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(winners),
+            Err(e) => return Err(Error::NonceLedgerError(format!("Failed to read {}: {}", self.path.display(), e))),
+        };
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|e| Error::NonceLedgerError(format!("Failed to read {}: {}", self.path.display(), e)))?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["generated", id] => {
+                    self.entries.entry(id.to_string()).or_insert(NonceStatus::Generated);
+                }
+                ["consumed", id, digest, token] => {
+                    if !matches!(self.entries.get(*id), Some(NonceStatus::Consumed(_))) {
+                        self.entries.insert(id.to_string(), NonceStatus::Consumed(digest.to_string()));
+                        winners.insert(id.to_string(), token.to_string());
+                    }
+                }
+                // A torn write from a crash, the entry it belonged to was never acted upon
+                _ => {}
+            }
+        }
+        Ok(winners)
+    }
+
+    /// Durably append a line to the ledger
+    fn append(&self, line: &str) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| Error::NonceLedgerError(format!("Failed to open {}: {}", self.path.display(), e)))?;
+        file.write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| file.sync_all())
+            .map_err(|e| Error::NonceLedgerError(format!("Failed to write {}: {}", self.path.display(), e)))
+    }
+
+    /// The status of a nonce pair, `None` if this ledger never generated it
+    pub fn status(&self, id: &str) -> Option<&NonceStatus> {
+        self.entries.get(id)
+    }
+
+    /// Record a freshly generated nonce pair
+    pub fn record_generated(&mut self, id: &str) -> Result<(), Error> {
+        if self.entries.contains_key(id) {
+            return Err(Error::NonceReused(format!("nonce {} is already in the ledger", id)));
+        }
+        self.append(&format!("generated {}", id))?;
+        self.entries.insert(id.to_string(), NonceStatus::Generated);
+        Ok(())
+    }
+
+    /// Mark a nonce pair as consumed for signing the message with `message_digest`.
+    /// Fails if the nonce was already consumed, or was never generated through this ledger.
+    pub fn consume(&mut self, id: &str, message_digest: &Hash) -> Result<(), Error> {
+        self.check_unused(id)?;
+
+        // Concurrent processes may race on the same nonce, tag our attempt and check that it's the first one on disk
+        let mut token = [0u8; 16];
+        rand07::thread_rng().fill_bytes(&mut token);
+        let token = bs58::encode(token).into_string();
+        self.append(&format!("consumed {} {} {}", id, message_digest, token))?;
+
+        let winners = self.reload()?;
+        if winners.get(id) != Some(&token) {
+            return Err(Error::NonceReused(format!("nonce {} was consumed concurrently by another process", id)));
+        }
+        Ok(())
+    }
+
+    /// Fail unless the nonce pair was generated through this ledger and not used yet
+    pub fn check_unused(&mut self, id: &str) -> Result<(), Error> {
+        self.reload()?;
+        match self.entries.get(id) {
+            Some(NonceStatus::Generated) => Ok(()),
+            Some(NonceStatus::Consumed(digest)) => Err(Error::NonceReused(format!(
+                "nonce {} was already used to sign message {}, run step one again",
+                id, digest
+            ))),
+            None => Err(Error::NonceReused(format!(
+                "nonce {} was not generated with the ledger at {}",
+                id,
+                self.path.display()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempFile;
+    use curv::elliptic::curves::Scalar;
+    use solana_sdk::hash::hash;
+
+    #[test]
+    fn test_nonce_is_single_use_across_restarts() {
+        let file = TempFile::new("nonce-ledger", "log");
+        let id = nonce_id(&[Point::generator() * Scalar::random(), Point::generator() * Scalar::random()]);
+
+        let mut ledger = NonceLedger::open(file.path()).unwrap();
+        ledger.record_generated(&id).unwrap();
+        ledger.consume(&id, &hash(b"first message")).unwrap();
+
+        // A new process sees the nonce as consumed
+        let mut reopened = NonceLedger::open(file.path()).unwrap();
+        assert_eq!(reopened.status(&id), Some(&NonceStatus::Consumed(hash(b"first message").to_string())));
+        match reopened.consume(&id, &hash(b"second message")) {
+            Err(Error::NonceReused(_)) => {}
+            _ => panic!("Expected NonceReused error"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_nonce_is_rejected() {
+        let file = TempFile::new("nonce-ledger", "log");
+        let mut ledger = NonceLedger::open(file.path()).unwrap();
+        assert!(ledger.consume("unknown", &hash(b"message")).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use multi_party_eddsa::protocols::{musig2, ExpandedKeyPair};
+use solana_sdk::hash::{hashv, Hash};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::error::Error;
+use crate::nonce_ledger::{nonce_id, NonceLedger};
+use crate::serialization::{AggMessage1, NoncePool, SecretAggStepOne, SecretNoncePool, Serialize};
+
+/// Domain separator of the session id of a pool entry
+const POOL_SESSION_DOMAIN: &[u8] = b"solana-mpc-tokens/nonce-pool/session";
+
+/// Generate `size` nonce pairs ahead of time, so later transfers only need the step two round.
+/// Every pair is recorded in the nonce ledger, which keeps enforcing that each one signs at most once.
+pub fn generate(keypair: &Keypair, size: u16, ledger: &mut NonceLedger) -> Result<(NoncePool, SecretNoncePool), Error> {
+    if size == 0 {
+        return Err(Error::NoncePoolError("the pool must hold at least one nonce pair".to_string()));
+    }
+    let extended_keypair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let pool_id = Hash::new(&rand07::random::<[u8; 32]>());
+
+    let mut nonces = Vec::with_capacity(usize::from(size));
+    let mut entries = Vec::with_capacity(usize::from(size));
+    for index in 0..size {
+        let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&extended_keypair, None);
+        ledger.record_generated(&nonce_id(&public_nonces.R))?;
+        nonces.push(public_nonces.R.clone());
+        entries.push((index, private_nonces, public_nonces));
+    }
+
+    Ok((
+        NoncePool { sender: keypair.pubkey(), pool_id, nonces },
+        SecretNoncePool { signer: keypair.pubkey(), pool_id, entries },
+    ))
+}
+
+/// Default location of a secret pool: `~/.solana-mpc-tokens/nonce-pools/<signer>-<pool id>.pool`
+pub fn default_path(signer: &Pubkey, pool_id: &Hash) -> Result<PathBuf, Error> {
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| Error::NoncePoolError("HOME is not set, pass the pool file explicitly".to_string()))?;
+    Ok(Path::new(&home)
+        .join(".solana-mpc-tokens")
+        .join("nonce-pools")
+        .join(format!("{}-{}.pool", signer, pool_id)))
+}
+
+/// Write the secret pool, readable by its owner only. The file is replaced atomically.
+pub fn save(pool: &SecretNoncePool, path: impl AsRef<Path>) -> Result<(), Error> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| Error::NoncePoolError(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(&tmp_path)
+        .map_err(|e| Error::NoncePoolError(format!("Failed to open {}: {}", tmp_path.display(), e)))?;
+    file.write_all(pool.serialize_bs58().as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|e| Error::NoncePoolError(format!("Failed to write {}: {}", tmp_path.display(), e)))?;
+    fs::rename(&tmp_path, path)
+        .map_err(|e| Error::NoncePoolError(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+/// Read a secret pool written by `save`
+pub fn load(path: impl AsRef<Path>) -> Result<SecretNoncePool, Error> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)
+        .map_err(|e| Error::NoncePoolError(format!("Failed to read {}: {}", path.display(), e)))?;
+    Ok(SecretNoncePool::deserialize_bs58(contents.trim())?)
+}
+
+/// The session id all parties use for pool entry `index`, `pools` must follow the order of the keys
+pub fn session_id(pools: &[&NoncePool], index: u16) -> Hash {
+    let index = index.to_le_bytes();
+    let mut parts: Vec<&[u8]> = vec![POOL_SESSION_DOMAIN, &index];
+    parts.extend(pools.iter().map(|pool| pool.pool_id.as_ref()));
+    hashv(&parts)
+}
+
+/// The published pool of every party, in the order of `keys`
+fn pools_by_key<'a>(keys: &[Pubkey], pools: &'a [NoncePool]) -> Result<Vec<&'a NoncePool>, Error> {
+    keys.iter()
+        .map(|key| match pools.iter().filter(|p| &p.sender == key).collect::<Vec<_>>().as_slice() {
+            [pool] => Ok(*pool),
+            [] => Err(Error::NoncePoolError(format!("missing nonce pool of {}", key))),
+            _ => Err(Error::NoncePoolError(format!("more than one nonce pool of {}", key))),
+        })
+        .collect()
+}
+
+/// Turn entry `index` of every party's pool into the first messages of a signing session for `message_digest`
+pub fn first_messages(
+    keys: &[Pubkey],
+    pools: &[NoncePool],
+    index: u16,
+    message_digest: Hash,
+) -> Result<Vec<AggMessage1>, Error> {
+    let pools = pools_by_key(keys, pools)?;
+    let session_id = session_id(&pools, index);
+    pools
+        .iter()
+        .map(|pool| {
+            let nonces = pool.nonces.get(usize::from(index)).ok_or_else(|| {
+                Error::NoncePoolError(format!("the pool of {} has no entry {}", pool.sender, index))
+            })?;
+            Ok(AggMessage1 {
+                public_nonces: musig2::PublicPartialNonces { R: nonces.clone() },
+                sender: pool.sender,
+                session_id,
+                message_digest,
+            })
+        })
+        .collect()
+}
+
+impl SecretNoncePool {
+    /// Remove entry `index` from the pool and turn it into step one state for the session of that entry.
+    /// The caller must save the pool before signing, so the entry can't be taken again.
+    pub fn take(
+        &mut self,
+        keys: &[Pubkey],
+        pools: &[NoncePool],
+        index: u16,
+        message_digest: Hash,
+    ) -> Result<SecretAggStepOne, Error> {
+        let ordered = pools_by_key(keys, pools)?;
+        let position = self
+            .entries
+            .iter()
+            .position(|(i, _, _)| *i == index)
+            .ok_or_else(|| Error::NonceReused(format!("entry {} of pool {} was already used", index, self.pool_id)))?;
+        let (_, private_nonces, public_nonces) = self.entries.remove(position);
+
+        // Our published pool must be the one we hold the secrets for
+        let ours = ordered
+            .iter()
+            .find(|pool| pool.sender == self.signer)
+            .ok_or(Error::KeyPairIsNotInKeys)?;
+        if ours.pool_id != self.pool_id || ours.nonces.get(usize::from(index)) != Some(&public_nonces.R) {
+            return Err(Error::NoncePoolError(format!(
+                "the published pool of {} doesn't match the secret pool {}",
+                self.signer, self.pool_id
+            )));
+        }
+
+        Ok(SecretAggStepOne {
+            private_nonces,
+            public_nonces,
+            signer: self.signer,
+            session_id: session_id(&ordered, index),
+            message_digest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_ledger;
+    use solana_sdk::hash::hash;
+
+    #[test]
+    fn test_pool_entry_is_taken_once() {
+        let (mut ledger, _ledger_file) = temp_ledger("nonce-pool-ledger");
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+        let (pools, mut secrets): (Vec<_>, Vec<_>) =
+            keypairs.iter().map(|k| generate(k, 4, &mut ledger).unwrap()).unzip();
+
+        let digest = hash(b"transfer");
+        let secret_state = secrets[0].take(&keys, &pools, 2, digest).unwrap();
+        let messages = first_messages(&keys, &pools, 2, digest).unwrap();
+        assert_eq!(messages[0].public_nonces, secret_state.public_nonces);
+        assert!(messages.iter().all(|m| m.session_id == secret_state.session_id));
+
+        assert!(secrets[0].take(&keys, &pools, 2, digest).is_err());
+        assert_eq!(secrets[0].entries.len(), 3);
+    }
+}
@@ -0,0 +1,316 @@
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use solana_sdk::hash::{hash, Hash};
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::dkg::check_received_shares;
+use crate::error::Error;
+use crate::frost::{evaluate_commitments, lagrange_coefficient, Polynomial};
+use crate::serialization::{DkgShare, ReshareApproval, ReshareCommitment, ReshareTranscript, Serialize, ThresholdKeyShare};
+
+impl ReshareTranscript {
+    /// The digest every party signs to approve the ceremony
+    pub fn digest(&self) -> Hash {
+        let mut bytes = Vec::with_capacity(self.size_hint());
+        self.serialize(&mut bytes);
+        hash(&bytes)
+    }
+
+    /// Share indices of the old parties dealing the key
+    pub fn old_indices(&self) -> Vec<u16> {
+        self.old_parties.iter().map(|(index, _)| *index).collect()
+    }
+
+    /// The share index a new party will receive
+    pub fn new_index(&self, identity: &Pubkey) -> Option<u16> {
+        self.new_parties.iter().position(|p| p == identity).map(|position| position as u16 + 1)
+    }
+
+    /// Make sure the transcript describes a resharing that can succeed, whoever created it
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.group_commitments.is_empty() {
+            return Err(Error::ReshareFailed("the transcript has no group commitments".to_string()));
+        }
+        if self.old_parties.len() < self.group_commitments.len() {
+            return Err(Error::ReshareFailed(format!(
+                "{} old parties can't reshare a key with threshold {}",
+                self.old_parties.len(),
+                self.group_commitments.len()
+            )));
+        }
+        let old_indices = self.old_indices();
+        if old_indices.iter().enumerate().any(|(i, index)| *index == 0 || old_indices[..i].contains(index)) {
+            return Err(Error::ReshareFailed("old party indices must be unique and start at 1".to_string()));
+        }
+        if self.new_parties.iter().enumerate().any(|(i, identity)| self.new_parties[..i].contains(identity)) {
+            return Err(Error::ReshareFailed("new party identities must be unique".to_string()));
+        }
+        let new_count = u16::try_from(self.new_parties.len())
+            .map_err(|_| Error::ReshareFailed("too many new parties".to_string()))?;
+        if self.new_threshold == 0 || self.new_threshold > new_count {
+            return Err(Error::ReshareFailed(format!(
+                "the new threshold must be between 1 and the number of new parties ({}), got {}",
+                new_count, self.new_threshold
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Describe a resharing of the wallet held by `key_share` from `old_parties` to `new_parties`.
+/// Any set of at least `threshold` old parties can deal, the new parties can be more, fewer or different people.
+pub fn create_transcript(
+    key_share: &ThresholdKeyShare,
+    old_parties: Vec<(u16, Pubkey)>,
+    new_parties: Vec<Pubkey>,
+    new_threshold: u16,
+) -> Result<ReshareTranscript, Error> {
+    // A fresh ceremony id makes every transcript unique, even between the same parties
+    let transcript = ReshareTranscript {
+        ceremony_id: Hash::new(&rand07::random::<[u8; 32]>()),
+        group_commitments: key_share.commitments.clone(),
+        old_parties,
+        new_parties,
+        new_threshold,
+    };
+    transcript.validate()?;
+    Ok(transcript)
+}
+
+/// Sign off on a resharing transcript with a long-term identity key.
+/// The digest covers the transcript's ceremony id, so the approval can't be replayed for another ceremony.
+pub fn approve(transcript: &ReshareTranscript, identity: &Keypair) -> ReshareApproval {
+    ReshareApproval {
+        signer: identity.pubkey(),
+        signature: identity.sign_message(transcript.digest().as_ref()),
+    }
+}
+
+/// Make sure every old and new party signed off on exactly this transcript, listing the ones that didn't
+pub fn verify_approvals(transcript: &ReshareTranscript, approvals: &[ReshareApproval]) -> Result<(), Error> {
+    let digest = transcript.digest();
+    let missing: Vec<String> = transcript
+        .old_parties
+        .iter()
+        .map(|(_, identity)| identity)
+        .chain(transcript.new_parties.iter())
+        .filter(|identity| {
+            !approvals
+                .iter()
+                .any(|a| &a.signer == *identity && a.signature.verify(identity.as_ref(), digest.as_ref()))
+        })
+        .map(|identity| identity.to_string())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(Error::ReshareFailed(format!(
+            "missing or invalid approvals from: {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Old party: reshare our Lagrange-weighted share `lambda_i * s_i` to the new parties.
+/// The returned shares are secret, the one for new index `j` must only be delivered to that party.
+pub fn deal(
+    key_share: &ThresholdKeyShare,
+    transcript: &ReshareTranscript,
+    approvals: &[ReshareApproval],
+) -> Result<(ReshareCommitment, Vec<DkgShare>), Error> {
+    transcript.validate()?;
+    verify_approvals(transcript, approvals)?;
+    if key_share.commitments != transcript.group_commitments {
+        return Err(Error::ReshareFailed("our key share belongs to a different wallet".to_string()));
+    }
+    let old_indices = transcript.old_indices();
+    if !old_indices.contains(&key_share.index) {
+        return Err(Error::KeyPairIsNotInKeys);
+    }
+
+    let lambda = lagrange_coefficient(key_share.index, &old_indices)?;
+    let polynomial = Polynomial::sample(transcript.new_threshold - 1, lambda * &key_share.secret_share);
+
+    let shares = (1..=transcript.new_parties.len() as u16)
+        .map(|receiver_index| DkgShare {
+            sender_index: key_share.index,
+            receiver_index,
+            share: polynomial.evaluate(receiver_index),
+        })
+        .collect();
+
+    Ok((ReshareCommitment { sender_index: key_share.index, commitments: polynomial.commit() }, shares))
+}
+
+/// New party: combine the shares dealt by the old parties into our key share of the unchanged group key
+pub fn finalize(
+    transcript: &ReshareTranscript,
+    approvals: &[ReshareApproval],
+    identity: &Pubkey,
+    commitments: &[ReshareCommitment],
+    shares: &[DkgShare],
+) -> Result<ThresholdKeyShare, Error> {
+    transcript.validate()?;
+    verify_approvals(transcript, approvals)?;
+    let index = transcript.new_index(identity).ok_or(Error::KeyPairIsNotInKeys)?;
+    let old_indices = transcript.old_indices();
+
+    // Every old party must have reshared exactly its own weighted share, which we can check against the old commitments
+    let mut dealer_commitments = Vec::with_capacity(old_indices.len());
+    for &old_index in &old_indices {
+        let from_index: Vec<_> = commitments.iter().filter(|c| c.sender_index == old_index).collect();
+        let commitment = match from_index.as_slice() {
+            [commitment] => commitment,
+            [] => return Err(Error::ReshareFailed(format!("missing commitment from old party {}", old_index))),
+            _ => return Err(Error::ReshareFailed(format!("old party {} sent more than one commitment", old_index))),
+        };
+
+        let lambda = lagrange_coefficient(old_index, &old_indices)?;
+        let expected_constant = evaluate_commitments(&transcript.group_commitments, old_index) * &lambda;
+        if commitment.commitments.len() != usize::from(transcript.new_threshold)
+            || commitment.commitments[0] != expected_constant
+        {
+            return Err(Error::ReshareFailed(format!("old party {} dealt a share it doesn't hold", old_index)));
+        }
+        dealer_commitments.push(*commitment);
+    }
+    if commitments.len() != old_indices.len() {
+        return Err(Error::ReshareFailed("received commitments from parties outside the transcript".to_string()));
+    }
+
+    let expected = dealer_commitments
+        .iter()
+        .map(|c| (c.sender_index, evaluate_commitments(&c.commitments, index)));
+    check_received_shares(index, expected, shares)?;
+
+    let secret_share = shares.iter().fold(Scalar::<Ed25519>::zero(), |acc, s| acc + &s.share);
+    let group_commitments: Vec<Point<Ed25519>> = (0..usize::from(transcript.new_threshold))
+        .map(|k| {
+            dealer_commitments
+                .iter()
+                .fold(Point::zero(), |acc, c| acc + &c.commitments[k])
+        })
+        .collect();
+
+    let key_share = ThresholdKeyShare {
+        index,
+        threshold: transcript.new_threshold,
+        secret_share,
+        commitments: group_commitments,
+    };
+    if key_share.group_public_key() != &transcript.group_commitments[0] {
+        return Err(Error::ReshareFailed("the reshared key doesn't match the wallet's group key".to_string()));
+    }
+    if Point::generator() * &key_share.secret_share != key_share.verifying_share(index) {
+        return Err(Error::ReshareFailed("our new share doesn't match the group commitments".to_string()));
+    }
+    Ok(key_share)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frost::keygen_with_dealer;
+
+    #[test]
+    fn test_reshare_to_new_parties_keeps_group_key() {
+        let old_shares = keygen_with_dealer(2, 3).unwrap();
+        let old_identities: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let new_identities: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+
+        // Old parties 1 and 3 hand the wallet over to four new parties, three of which must sign
+        let dealers = [0usize, 2];
+        let transcript = create_transcript(
+            &old_shares[0],
+            dealers.iter().map(|&i| (old_shares[i].index, old_identities[i].pubkey())).collect(),
+            new_identities.iter().map(|k| k.pubkey()).collect(),
+            3,
+        )
+        .unwrap();
+
+        let approvals: Vec<_> = dealers
+            .iter()
+            .map(|&i| &old_identities[i])
+            .chain(new_identities.iter())
+            .map(|identity| approve(&transcript, identity))
+            .collect();
+        assert!(verify_approvals(&transcript, &approvals[1..]).is_err());
+
+        let dealt: Vec<_> = dealers.iter().map(|&i| deal(&old_shares[i], &transcript, &approvals).unwrap()).collect();
+        let commitments: Vec<_> = dealt.iter().map(|(c, _)| c.clone()).collect();
+
+        let new_shares: Vec<_> = new_identities
+            .iter()
+            .map(|identity| {
+                let index = transcript.new_index(&identity.pubkey()).unwrap();
+                let received: Vec<_> = dealt
+                    .iter()
+                    .flat_map(|(_, shares)| shares.iter().filter(|s| s.receiver_index == index).cloned())
+                    .collect();
+                finalize(&transcript, &approvals, &identity.pubkey(), &commitments, &received).unwrap()
+            })
+            .collect();
+
+        let quorum = [1u16, 2, 4];
+        let interpolated = quorum.iter().fold(Point::zero(), |acc, &i| {
+            let lambda = lagrange_coefficient(i, &quorum).unwrap();
+            acc + Point::generator() * (lambda * &new_shares[usize::from(i) - 1].secret_share)
+        });
+        assert_eq!(&interpolated, old_shares[0].group_public_key());
+        assert!(new_shares.iter().all(|s| s.group_pubkey() == old_shares[0].group_pubkey()));
+    }
+
+    #[test]
+    fn test_transcript_with_invalid_threshold_is_rejected() {
+        let old_shares = keygen_with_dealer(2, 3).unwrap();
+        let old_identities: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let new_identities: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let old_parties: Vec<_> = old_identities.iter().zip(&old_shares).map(|(k, s)| (s.index, k.pubkey())).collect();
+        let new_parties: Vec<_> = new_identities.iter().map(|k| k.pubkey()).collect();
+
+        for new_threshold in [0, 4] {
+            match create_transcript(&old_shares[0], old_parties.clone(), new_parties.clone(), new_threshold) {
+                Err(Error::ReshareFailed(_)) => {}
+                _ => panic!("Expected ReshareFailed error"),
+            }
+        }
+
+        // A transcript built by someone else is checked before anything is dealt or finalized
+        let mut transcript = create_transcript(&old_shares[0], old_parties, new_parties, 2).unwrap();
+        transcript.new_threshold = 0;
+        let approvals: Vec<_> = old_identities
+            .iter()
+            .chain(new_identities.iter())
+            .map(|identity| approve(&transcript, identity))
+            .collect();
+        match deal(&old_shares[0], &transcript, &approvals) {
+            Err(Error::ReshareFailed(_)) => {}
+            _ => panic!("Expected ReshareFailed error"),
+        }
+        match finalize(&transcript, &approvals, &new_identities[0].pubkey(), &[], &[]) {
+            Err(Error::ReshareFailed(_)) => {}
+            _ => panic!("Expected ReshareFailed error"),
+        }
+    }
+
+    #[test]
+    fn test_approvals_of_another_ceremony_are_rejected() {
+        let old_shares = keygen_with_dealer(2, 3).unwrap();
+        let identities: Vec<Keypair> = (0..4).map(|_| Keypair::new()).collect();
+        let old_parties: Vec<_> = identities[..2].iter().zip(&old_shares).map(|(k, s)| (s.index, k.pubkey())).collect();
+        let new_parties: Vec<_> = identities[2..].iter().map(|k| k.pubkey()).collect();
+
+        // The same parties and threshold still make a new ceremony with a new digest
+        let earlier = create_transcript(&old_shares[0], old_parties.clone(), new_parties.clone(), 2).unwrap();
+        let transcript = create_transcript(&old_shares[0], old_parties, new_parties, 2).unwrap();
+        assert_ne!(earlier.ceremony_id, transcript.ceremony_id);
+        assert_ne!(earlier.digest(), transcript.digest());
+
+        let replayed: Vec<_> = identities.iter().map(|identity| approve(&earlier, identity)).collect();
+        assert!(verify_approvals(&earlier, &replayed).is_ok());
+        match deal(&old_shares[0], &transcript, &replayed) {
+            Err(Error::ReshareFailed(_)) => {}
+            _ => panic!("Expected ReshareFailed error"),
+        }
+    }
+}
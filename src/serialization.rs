@@ -1,6 +1,7 @@
 use std::fmt::{Display, Formatter};
 
 use curv::elliptic::curves::{DeserializationError, Ed25519, Point, PointFromBytesError, Scalar};
+use solana_sdk::hash::{hash, Hash};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Signature;
 use multi_party_eddsa::protocols::musig2::{self, PrivatePartialNonces, PublicPartialNonces, PartialSignature as Musig2PartialSignature};
@@ -16,6 +17,7 @@ pub enum Error {
     PointDeserializationFailed { field_name: &'static str },
     ScalarDeserializationFailed { field_name: &'static str },
     MismatchMessages,
+    InvalidKeyShare(String),
 }
 
 /// Message tags for different types of serialized data
@@ -24,16 +26,52 @@ pub enum Tag {
     AggMessage1 = 1,
     PartialSignature = 2,
     SecretAggStepOne = 3,
+    ThresholdKeyShare = 4,
+    ThresholdMessage1 = 5,
+    SecretThresholdStepOne = 6,
+    ThresholdPartialSignature = 7,
+    DkgSecret = 8,
+    DkgCommitment = 9,
+    DkgShare = 10,
+    RefreshCommitment = 11,
+    ReshareTranscript = 12,
+    ReshareApproval = 13,
+    ReshareCommitment = 14,
+    NoncePool = 15,
+    SecretNoncePool = 16,
+    TransactionTemplate = 17,
+    TransportHello = 18,
+    Envelope = 19,
 }
 
-impl From<u8> for Tag {
-    fn from(t: u8) -> Self {
-        match t {
+impl TryFrom<u8> for Tag {
+    type Error = Error;
+
+    fn try_from(t: u8) -> Result<Self, Self::Error> {
+        Ok(match t {
             _ if t == Tag::AggMessage1 as u8 => Tag::AggMessage1,
             _ if t == Tag::PartialSignature as u8 => Tag::PartialSignature,
             _ if t == Tag::SecretAggStepOne as u8 => Tag::SecretAggStepOne,
-            _ => panic!("Unknown tag: {}", t),
-        }
+            _ if t == Tag::ThresholdKeyShare as u8 => Tag::ThresholdKeyShare,
+            _ if t == Tag::ThresholdMessage1 as u8 => Tag::ThresholdMessage1,
+            _ if t == Tag::SecretThresholdStepOne as u8 => Tag::SecretThresholdStepOne,
+            _ if t == Tag::ThresholdPartialSignature as u8 => Tag::ThresholdPartialSignature,
+            _ if t == Tag::DkgSecret as u8 => Tag::DkgSecret,
+            _ if t == Tag::DkgCommitment as u8 => Tag::DkgCommitment,
+            _ if t == Tag::DkgShare as u8 => Tag::DkgShare,
+            _ if t == Tag::RefreshCommitment as u8 => Tag::RefreshCommitment,
+            _ if t == Tag::ReshareTranscript as u8 => Tag::ReshareTranscript,
+            _ if t == Tag::ReshareApproval as u8 => Tag::ReshareApproval,
+            _ if t == Tag::ReshareCommitment as u8 => Tag::ReshareCommitment,
+            _ if t == Tag::NoncePool as u8 => Tag::NoncePool,
+            _ if t == Tag::SecretNoncePool as u8 => Tag::SecretNoncePool,
+            _ if t == Tag::TransactionTemplate as u8 => Tag::TransactionTemplate,
+            _ if t == Tag::TransportHello as u8 => Tag::TransportHello,
+            _ if t == Tag::Envelope as u8 => Tag::Envelope,
+            _ => {
+                return Err(Error::WrongTag { expected: "a known message".to_string(), found: format!("unknown tag {}", t) })
+            }
+        })
     }
 }
 
@@ -43,6 +81,22 @@ impl Display for Tag {
             Tag::AggMessage1 => f.write_str("Aggregation Message 1"),
             Tag::PartialSignature => f.write_str("Partial Signature"),
             Tag::SecretAggStepOne => f.write_str("Secret Aggregation Step One"),
+            Tag::ThresholdKeyShare => f.write_str("Threshold Key Share"),
+            Tag::ThresholdMessage1 => f.write_str("Threshold Message 1"),
+            Tag::SecretThresholdStepOne => f.write_str("Secret Threshold Step One"),
+            Tag::ThresholdPartialSignature => f.write_str("Threshold Partial Signature"),
+            Tag::DkgSecret => f.write_str("DKG Secret"),
+            Tag::DkgCommitment => f.write_str("DKG Commitment"),
+            Tag::DkgShare => f.write_str("DKG Share"),
+            Tag::RefreshCommitment => f.write_str("Refresh Commitment"),
+            Tag::ReshareTranscript => f.write_str("Reshare Transcript"),
+            Tag::ReshareApproval => f.write_str("Reshare Approval"),
+            Tag::ReshareCommitment => f.write_str("Reshare Commitment"),
+            Tag::NoncePool => f.write_str("Nonce Pool"),
+            Tag::SecretNoncePool => f.write_str("Secret Nonce Pool"),
+            Tag::TransactionTemplate => f.write_str("Transaction Template"),
+            Tag::TransportHello => f.write_str("Transport Hello"),
+            Tag::Envelope => f.write_str("Envelope"),
         }
     }
 }
@@ -66,6 +120,7 @@ impl Display for Error {
                 write!(f, "Failed to deserialize scalar from bytes for field: {}", field_name)
             }
             Self::MismatchMessages => write!(f, "Mismatch in messages"),
+            Self::InvalidKeyShare(e) => write!(f, "Invalid key share: {}", e),
         }
     }
 }
@@ -84,6 +139,28 @@ impl From<DeserializationError> for Error {
     }
 }
 
+/// Read a `u16` count followed by that many compressed points, returns the points and the bytes consumed
+fn read_points(b: &[u8]) -> Result<(Vec<Point<Ed25519>>, usize), Error> {
+    if b.len() < 2 {
+        return Err(Error::InputTooShort { expected: 2, found: b.len() });
+    }
+    let count = usize::from(u16::from_le_bytes([b[0], b[1]]));
+    let end = 2 + count * 32;
+    if b.len() < end {
+        return Err(Error::InputTooShort { expected: end, found: b.len() });
+    }
+    let points = b[2..end].chunks(32).map(Point::from_bytes).collect::<Result<Vec<_>, _>>()?;
+    Ok((points, end))
+}
+
+/// Append a `u16` count followed by the compressed points
+fn write_points(points: &[Point<Ed25519>], append_to: &mut Vec<u8>) {
+    append_to.extend((points.len() as u16).to_le_bytes());
+    for point in points {
+        append_to.extend(&*point.to_bytes(true));
+    }
+}
+
 /// Trait for serializing and deserializing MPC data structures
 pub trait Serialize: Sized {
     /// Serialize to base58 string
@@ -110,10 +187,13 @@ pub trait Serialize: Sized {
 }
 
 /// Message containing public nonces for MPC nonce generation (step 1)
-#[derive(Debug, PartialEq)]
+/// The nonces are bound to one signing session and to the digest of the message that will be signed
+#[derive(Clone, Debug, PartialEq)]
 pub struct AggMessage1 {
     pub public_nonces: PublicPartialNonces,
     pub sender: Pubkey,
+    pub session_id: Hash,
+    pub message_digest: Hash,
 }
 
 impl Serialize for AggMessage1 {
@@ -123,13 +203,15 @@ impl Serialize for AggMessage1 {
         append_to.extend(&*self.public_nonces.R[0].to_bytes(true));
         append_to.extend(&*self.public_nonces.R[1].to_bytes(true));
         append_to.extend(self.sender.to_bytes());
+        append_to.extend(self.session_id.to_bytes());
+        append_to.extend(self.message_digest.to_bytes());
     }
     
     fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 32 + 32 + 32 {
-            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32, found: b.len() });
+        if b.len() < 1 + 32 + 32 + 32 + 32 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 32 + 32, found: b.len() });
         }
-        let tag = Tag::from(b[0]);
+        let tag = Tag::try_from(b[0])?;
         if tag != Tag::AggMessage1 {
             return Err(Error::WrongTag { expected: Tag::AggMessage1.to_string(), found: tag.to_string() });
         }
@@ -138,19 +220,25 @@ impl Serialize for AggMessage1 {
         let mut sender_bytes = [0u8; 32];
         sender_bytes.copy_from_slice(&b[64 + 1..64 + 32 + 1]);
         let sender = Pubkey::from(sender_bytes);
-        Ok(Self { public_nonces, sender })
+        let session_id = Hash::new(&b[1 + 96..1 + 128]);
+        let message_digest = Hash::new(&b[1 + 128..1 + 160]);
+        Ok(Self { public_nonces, sender, session_id, message_digest })
     }
     
     fn size_hint(&self) -> usize {
-        1 + 32 + 32 + 32
+        1 + 32 + 32 + 32 + 32 + 32
     }
 }
 
 /// Secret state from step one of MPC nonce generation
+/// Remembers who generated the nonces and for which session and message, step two refuses anything else
 #[derive(Debug, PartialEq)]
 pub struct SecretAggStepOne {
     pub private_nonces: PrivatePartialNonces,
     pub public_nonces: PublicPartialNonces,
+    pub signer: Pubkey,
+    pub session_id: Hash,
+    pub message_digest: Hash,
 }
 
 impl Serialize for SecretAggStepOne {
@@ -162,14 +250,17 @@ impl Serialize for SecretAggStepOne {
         append_to.extend(&*self.private_nonces.r[1].to_bytes());
         append_to.extend(&*self.public_nonces.R[0].to_bytes(true));
         append_to.extend(&*self.public_nonces.R[1].to_bytes(true));
+        append_to.extend(self.signer.to_bytes());
+        append_to.extend(self.session_id.to_bytes());
+        append_to.extend(self.message_digest.to_bytes());
     }
     
     fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 32 + 32 + 32 + 32 {
-            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 32, found: b.len() });
+        if b.len() < 1 + 32 + 32 + 32 + 32 + 32 + 32 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 32 + 32 + 32 + 32, found: b.len() });
         }
 
-        let tag = Tag::from(b[0]);
+        let tag = Tag::try_from(b[0])?;
         if tag != Tag::SecretAggStepOne {
             return Err(Error::WrongTag { expected: Tag::SecretAggStepOne.to_string(), found: tag.to_string() });
         }
@@ -179,40 +270,58 @@ impl Serialize for SecretAggStepOne {
         let public_nonces = PublicPartialNonces {
             R: [Point::from_bytes(&b[1 + 64..1 + 64 + 32])?, Point::from_bytes(&b[1 + 96..1 + 96 + 32])?],
         };
-        Ok(Self { private_nonces, public_nonces })
+        let mut signer_bytes = [0u8; 32];
+        signer_bytes.copy_from_slice(&b[1 + 128..1 + 160]);
+        let signer = Pubkey::from(signer_bytes);
+        let session_id = Hash::new(&b[1 + 160..1 + 192]);
+        let message_digest = Hash::new(&b[1 + 192..1 + 224]);
+        Ok(Self { private_nonces, public_nonces, signer, session_id, message_digest })
     }
     
     fn size_hint(&self) -> usize {
-        1 + 32 + 32 + 32 + 32
+        1 + 32 + 32 + 32 + 32 + 32 + 32 + 32
     }
 }
 
-/// Partial signature for MPC signing
-#[derive(Debug, PartialEq)]
-pub struct PartialSignature(pub Signature);
+/// Partial signature for MPC signing, tagged with the party that produced it and its signing session
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartialSignature {
+    pub sender: Pubkey,
+    pub session_id: Hash,
+    pub signature: Signature,
+}
 
 impl Serialize for PartialSignature {
     fn serialize(&self, append_to: &mut Vec<u8>) {
         append_to.reserve(self.size_hint());
         append_to.push(Tag::PartialSignature as u8);
-        append_to.extend(self.0.as_ref());
+        append_to.extend(self.sender.to_bytes());
+        append_to.extend(self.session_id.to_bytes());
+        append_to.extend(self.signature.as_ref());
     }
     
     fn deserialize(b: &[u8]) -> Result<Self, Error> {
-        if b.len() < 1 + 64 {
-            return Err(Error::InputTooShort { expected: 1 + 64, found: b.len() });
+        if b.len() < 1 + 32 + 32 + 64 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 64, found: b.len() });
         }
-        let tag = Tag::from(b[0]);
+        let tag = Tag::try_from(b[0])?;
         if tag != Tag::PartialSignature {
             return Err(Error::WrongTag { expected: Tag::PartialSignature.to_string(), found: tag.to_string() });
         }
+        let mut sender_bytes = [0u8; 32];
+        sender_bytes.copy_from_slice(&b[1..1 + 32]);
+        let session_id = Hash::new(&b[1 + 32..1 + 64]);
         let mut sig_bytes = [0u8; 64];
-        sig_bytes.copy_from_slice(&b[1..1 + 64]);
-        Ok(PartialSignature(Signature::from(sig_bytes)))
+        sig_bytes.copy_from_slice(&b[1 + 64..1 + 128]);
+        Ok(PartialSignature {
+            sender: Pubkey::from(sender_bytes),
+            session_id,
+            signature: Signature::from(sig_bytes),
+        })
     }
     
     fn size_hint(&self) -> usize {
-        1 + 64
+        1 + 32 + 32 + 64
     }
 }
 
@@ -227,8 +336,8 @@ impl PartialSignature {
 
     pub fn to_musig2_partial_signature(&self) -> Result<Musig2PartialSignature, Error> {
         Ok(Musig2PartialSignature {
-            R: Self::deserialize_r(&self.0.as_ref()[..32])?,
-            my_partial_s: Self::deserialize_s(&self.0.as_ref()[32..])?,
+            R: Self::deserialize_r(&self.signature.as_ref()[..32])?,
+            my_partial_s: Self::deserialize_s(&self.signature.as_ref()[32..])?,
         })
     }
 
@@ -239,8 +348,8 @@ impl PartialSignature {
 
         // Make sure all the `R`s are the same
         if !signatures[1..].iter()
-            .map(|s| &s.0.as_ref()[..32])
-            .all(|s| s == &signatures[0].0.as_ref()[..32]) {
+            .map(|s| &s.signature.as_ref()[..32])
+            .all(|s| s == &signatures[0].signature.as_ref()[..32]) {
             return Err(Error::MismatchMessages);
         }
 
@@ -250,7 +359,7 @@ impl PartialSignature {
         // Convert remaining signatures
         let partial_sigs: Vec<_> = signatures[1..]
             .iter()
-            .map(|s| Self::deserialize_s(&s.0.as_ref()[32..]))
+            .map(|s| Self::deserialize_s(&s.signature.as_ref()[32..]))
             .collect::<Result<_, _>>()?;
 
         // Aggregate using MuSig2
@@ -264,3 +373,754 @@ impl PartialSignature {
         Ok(Signature::from(sig_bytes))
     }
 }
+
+/// Secret share of a t-of-n threshold (FROST) wallet
+#[derive(Debug, PartialEq)]
+pub struct ThresholdKeyShare {
+    pub index: u16,
+    pub threshold: u16,
+    pub secret_share: Scalar<Ed25519>,
+    /// Feldman commitments to the sharing polynomial, the first one is the group public key
+    pub commitments: Vec<Point<Ed25519>>,
+}
+
+impl Serialize for ThresholdKeyShare {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ThresholdKeyShare as u8);
+        append_to.extend(self.index.to_le_bytes());
+        append_to.extend(self.threshold.to_le_bytes());
+        append_to.extend(&*self.secret_share.to_bytes());
+        write_points(&self.commitments, append_to);
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 2 + 32 + 2 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 2 + 32 + 2 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ThresholdKeyShare {
+            return Err(Error::WrongTag { expected: Tag::ThresholdKeyShare.to_string(), found: tag.to_string() });
+        }
+        let index = u16::from_le_bytes([b[1], b[2]]);
+        let threshold = u16::from_le_bytes([b[3], b[4]]);
+        let secret_share = Scalar::from_bytes(&b[5..5 + 32])?;
+        let (commitments, _) = read_points(&b[37..])?;
+        if commitments.is_empty() {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 2 + 32 + 2 + 32, found: b.len() });
+        }
+        // Index 0 is where the polynomial evaluates to the group secret itself
+        if index == 0 {
+            return Err(Error::InvalidKeyShare("index 0 is where the polynomial gives the group secret".to_string()));
+        }
+        if commitments.len() != usize::from(threshold) {
+            return Err(Error::InvalidKeyShare(format!(
+                "a key share with threshold {} needs {} commitments, found {}",
+                threshold,
+                threshold,
+                commitments.len()
+            )));
+        }
+        Ok(Self { index, threshold, secret_share, commitments })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 2 + 32 + 2 + self.commitments.len() * 32
+    }
+}
+
+/// Message containing a signer's hiding and binding nonce commitments (threshold step 1)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThresholdMessage1 {
+    pub sender_index: u16,
+    /// `[hiding, binding]` nonce commitments
+    pub commitments: [Point<Ed25519>; 2],
+}
+
+impl Serialize for ThresholdMessage1 {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ThresholdMessage1 as u8);
+        append_to.extend(self.sender_index.to_le_bytes());
+        append_to.extend(&*self.commitments[0].to_bytes(true));
+        append_to.extend(&*self.commitments[1].to_bytes(true));
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 32 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 32 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ThresholdMessage1 {
+            return Err(Error::WrongTag { expected: Tag::ThresholdMessage1.to_string(), found: tag.to_string() });
+        }
+        let sender_index = u16::from_le_bytes([b[1], b[2]]);
+        let commitments = [Point::from_bytes(&b[3..3 + 32])?, Point::from_bytes(&b[3 + 32..3 + 64])?];
+        Ok(Self { sender_index, commitments })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 32 + 32
+    }
+}
+
+/// Secret state from step one of threshold signing
+#[derive(Debug, PartialEq)]
+pub struct SecretThresholdStepOne {
+    /// `[hiding, binding]` nonces
+    pub nonces: [Scalar<Ed25519>; 2],
+    pub commitments: [Point<Ed25519>; 2],
+}
+
+impl Serialize for SecretThresholdStepOne {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::SecretThresholdStepOne as u8);
+        append_to.extend(&*self.nonces[0].to_bytes());
+        append_to.extend(&*self.nonces[1].to_bytes());
+        append_to.extend(&*self.commitments[0].to_bytes(true));
+        append_to.extend(&*self.commitments[1].to_bytes(true));
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 32 + 32 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::SecretThresholdStepOne {
+            return Err(Error::WrongTag { expected: Tag::SecretThresholdStepOne.to_string(), found: tag.to_string() });
+        }
+        let nonces = [Scalar::from_bytes(&b[1..1 + 32])?, Scalar::from_bytes(&b[1 + 32..1 + 64])?];
+        let commitments = [Point::from_bytes(&b[1 + 64..1 + 96])?, Point::from_bytes(&b[1 + 96..1 + 128])?];
+        Ok(Self { nonces, commitments })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 32 + 32 + 32
+    }
+}
+
+/// Partial signature for threshold signing, `R || z_i` of the sending party
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThresholdPartialSignature {
+    pub sender_index: u16,
+    pub signature: Signature,
+}
+
+impl Serialize for ThresholdPartialSignature {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ThresholdPartialSignature as u8);
+        append_to.extend(self.sender_index.to_le_bytes());
+        append_to.extend(self.signature.as_ref());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 64 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 64, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ThresholdPartialSignature {
+            return Err(Error::WrongTag { expected: Tag::ThresholdPartialSignature.to_string(), found: tag.to_string() });
+        }
+        let sender_index = u16::from_le_bytes([b[1], b[2]]);
+        let mut sig_bytes = [0u8; 64];
+        sig_bytes.copy_from_slice(&b[3..3 + 64]);
+        Ok(Self { sender_index, signature: Signature::from(sig_bytes) })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 64
+    }
+}
+
+/// Secret state of a DKG participant: its own sharing polynomial
+#[derive(Debug, PartialEq)]
+pub struct DkgSecret {
+    pub index: u16,
+    pub threshold: u16,
+    pub parties: u16,
+    pub coefficients: Vec<Scalar<Ed25519>>,
+}
+
+impl Serialize for DkgSecret {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::DkgSecret as u8);
+        append_to.extend(self.index.to_le_bytes());
+        append_to.extend(self.threshold.to_le_bytes());
+        append_to.extend(self.parties.to_le_bytes());
+        append_to.extend((self.coefficients.len() as u16).to_le_bytes());
+        for coefficient in &self.coefficients {
+            append_to.extend(&*coefficient.to_bytes());
+        }
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 2 + 2 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 2 + 2 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::DkgSecret {
+            return Err(Error::WrongTag { expected: Tag::DkgSecret.to_string(), found: tag.to_string() });
+        }
+        let index = u16::from_le_bytes([b[1], b[2]]);
+        let threshold = u16::from_le_bytes([b[3], b[4]]);
+        let parties = u16::from_le_bytes([b[5], b[6]]);
+        let count = usize::from(u16::from_le_bytes([b[7], b[8]]));
+        if b.len() < 9 + count * 32 {
+            return Err(Error::InputTooShort { expected: 9 + count * 32, found: b.len() });
+        }
+        let coefficients = b[9..9 + count * 32]
+            .chunks(32)
+            .map(Scalar::from_bytes)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { index, threshold, parties, coefficients })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 2 + 2 + 2 + self.coefficients.len() * 32
+    }
+}
+
+/// Public round one DKG message: commitments to the sender's polynomial and a proof of knowledge of its secret
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DkgCommitment {
+    /// The DKG run this commitment belongs to, agreed on by every party
+    pub ceremony_id: Hash,
+    pub sender_index: u16,
+    pub commitments: Vec<Point<Ed25519>>,
+    /// Schnorr proof of knowledge of the constant term, `(R, mu)`
+    pub proof_R: Point<Ed25519>,
+    pub proof_mu: Scalar<Ed25519>,
+}
+
+impl Serialize for DkgCommitment {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::DkgCommitment as u8);
+        append_to.extend(self.ceremony_id.to_bytes());
+        append_to.extend(self.sender_index.to_le_bytes());
+        write_points(&self.commitments, append_to);
+        append_to.extend(&*self.proof_R.to_bytes(true));
+        append_to.extend(&*self.proof_mu.to_bytes());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 2 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 2 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::DkgCommitment {
+            return Err(Error::WrongTag { expected: Tag::DkgCommitment.to_string(), found: tag.to_string() });
+        }
+        let ceremony_id = Hash::new(&b[1..1 + 32]);
+        let sender_index = u16::from_le_bytes([b[1 + 32], b[2 + 32]]);
+        let (commitments, read) = read_points(&b[3 + 32..])?;
+        let offset = 3 + 32 + read;
+        if b.len() < offset + 64 {
+            return Err(Error::InputTooShort { expected: offset + 64, found: b.len() });
+        }
+        #[allow(non_snake_case)]
+        let proof_R = Point::from_bytes(&b[offset..offset + 32])?;
+        let proof_mu = Scalar::from_bytes(&b[offset + 32..offset + 64])?;
+        Ok(Self { ceremony_id, sender_index, commitments, proof_R, proof_mu })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 2 + 2 + self.commitments.len() * 32 + 32 + 32
+    }
+}
+
+/// Round two DKG message: the sender's polynomial evaluated at the receiver's index.
+/// This is secret and must only be delivered to the receiver.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DkgShare {
+    pub sender_index: u16,
+    pub receiver_index: u16,
+    pub share: Scalar<Ed25519>,
+}
+
+impl Serialize for DkgShare {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::DkgShare as u8);
+        append_to.extend(self.sender_index.to_le_bytes());
+        append_to.extend(self.receiver_index.to_le_bytes());
+        append_to.extend(&*self.share.to_bytes());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 2 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 2 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::DkgShare {
+            return Err(Error::WrongTag { expected: Tag::DkgShare.to_string(), found: tag.to_string() });
+        }
+        let sender_index = u16::from_le_bytes([b[1], b[2]]);
+        let receiver_index = u16::from_le_bytes([b[3], b[4]]);
+        let share = Scalar::from_bytes(&b[5..5 + 32])?;
+        Ok(Self { sender_index, receiver_index, share })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 2 + 32
+    }
+}
+
+/// Public round one refresh message: commitments to the non-constant coefficients of a zero-sharing,
+/// with a proof of knowledge of the first one bound to the refresh
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RefreshCommitment {
+    /// The refresh this commitment belongs to, agreed on by every party
+    pub refresh_id: Hash,
+    pub sender_index: u16,
+    pub commitments: Vec<Point<Ed25519>>,
+    /// Schnorr proof of knowledge of the first coefficient, `(R, mu)`
+    pub proof_R: Point<Ed25519>,
+    pub proof_mu: Scalar<Ed25519>,
+}
+
+impl Serialize for RefreshCommitment {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::RefreshCommitment as u8);
+        append_to.extend(self.refresh_id.to_bytes());
+        append_to.extend(self.sender_index.to_le_bytes());
+        write_points(&self.commitments, append_to);
+        append_to.extend(&*self.proof_R.to_bytes(true));
+        append_to.extend(&*self.proof_mu.to_bytes());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 2 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 2 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::RefreshCommitment {
+            return Err(Error::WrongTag { expected: Tag::RefreshCommitment.to_string(), found: tag.to_string() });
+        }
+        let refresh_id = Hash::new(&b[1..1 + 32]);
+        let sender_index = u16::from_le_bytes([b[1 + 32], b[2 + 32]]);
+        let (commitments, read) = read_points(&b[3 + 32..])?;
+        let offset = 3 + 32 + read;
+        if b.len() < offset + 64 {
+            return Err(Error::InputTooShort { expected: offset + 64, found: b.len() });
+        }
+        #[allow(non_snake_case)]
+        let proof_R = Point::from_bytes(&b[offset..offset + 32])?;
+        let proof_mu = Scalar::from_bytes(&b[offset + 32..offset + 64])?;
+        Ok(Self { refresh_id, sender_index, commitments, proof_R, proof_mu })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 2 + 2 + self.commitments.len() * 32 + 32 + 32
+    }
+}
+
+/// The public description of a resharing ceremony every old and new party signs off on
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReshareTranscript {
+    /// Random id of this ceremony, so approvals of an earlier transcript with the same parties don't verify
+    pub ceremony_id: Hash,
+    /// The current group commitments, the first one is the group public key that must be preserved
+    pub group_commitments: Vec<Point<Ed25519>>,
+    /// The old participants dealing the key, as `(share index, identity)`
+    pub old_parties: Vec<(u16, Pubkey)>,
+    /// Identities of the new participants, the new share index is the position in this list plus one
+    pub new_parties: Vec<Pubkey>,
+    pub new_threshold: u16,
+}
+
+impl Serialize for ReshareTranscript {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ReshareTranscript as u8);
+        append_to.extend(self.ceremony_id.to_bytes());
+        write_points(&self.group_commitments, append_to);
+        append_to.extend((self.old_parties.len() as u16).to_le_bytes());
+        for (index, identity) in &self.old_parties {
+            append_to.extend(index.to_le_bytes());
+            append_to.extend(identity.to_bytes());
+        }
+        append_to.extend((self.new_parties.len() as u16).to_le_bytes());
+        for identity in &self.new_parties {
+            append_to.extend(identity.to_bytes());
+        }
+        append_to.extend(self.new_threshold.to_le_bytes());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ReshareTranscript {
+            return Err(Error::WrongTag { expected: Tag::ReshareTranscript.to_string(), found: tag.to_string() });
+        }
+        let ceremony_id = Hash::new(&b[1..1 + 32]);
+        let (group_commitments, read) = read_points(&b[1 + 32..])?;
+        let mut offset = 1 + 32 + read;
+
+        let read_u16 = |offset: usize| -> Result<u16, Error> {
+            if b.len() < offset + 2 {
+                return Err(Error::InputTooShort { expected: offset + 2, found: b.len() });
+            }
+            Ok(u16::from_le_bytes([b[offset], b[offset + 1]]))
+        };
+        let read_pubkey = |offset: usize| -> Result<Pubkey, Error> {
+            if b.len() < offset + 32 {
+                return Err(Error::InputTooShort { expected: offset + 32, found: b.len() });
+            }
+            let mut key_bytes = [0u8; 32];
+            key_bytes.copy_from_slice(&b[offset..offset + 32]);
+            Ok(Pubkey::from(key_bytes))
+        };
+
+        let old_count = read_u16(offset)?;
+        offset += 2;
+        let mut old_parties = Vec::with_capacity(usize::from(old_count));
+        for _ in 0..old_count {
+            old_parties.push((read_u16(offset)?, read_pubkey(offset + 2)?));
+            offset += 2 + 32;
+        }
+
+        let new_count = read_u16(offset)?;
+        offset += 2;
+        let mut new_parties = Vec::with_capacity(usize::from(new_count));
+        for _ in 0..new_count {
+            new_parties.push(read_pubkey(offset)?);
+            offset += 32;
+        }
+
+        let new_threshold = read_u16(offset)?;
+        Ok(Self { ceremony_id, group_commitments, old_parties, new_parties, new_threshold })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 2 + self.group_commitments.len() * 32 + 2 + self.old_parties.len() * (2 + 32) + 2 + self.new_parties.len() * 32 + 2
+    }
+}
+
+/// A party's signature over the digest of a resharing transcript
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReshareApproval {
+    pub signer: Pubkey,
+    pub signature: Signature,
+}
+
+impl Serialize for ReshareApproval {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ReshareApproval as u8);
+        append_to.extend(self.signer.to_bytes());
+        append_to.extend(self.signature.as_ref());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 64 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 64, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ReshareApproval {
+            return Err(Error::WrongTag { expected: Tag::ReshareApproval.to_string(), found: tag.to_string() });
+        }
+        let mut signer_bytes = [0u8; 32];
+        signer_bytes.copy_from_slice(&b[1..1 + 32]);
+        let mut sig_bytes = [0u8; 64];
+        sig_bytes.copy_from_slice(&b[1 + 32..1 + 32 + 64]);
+        Ok(Self { signer: Pubkey::from(signer_bytes), signature: Signature::from(sig_bytes) })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 64
+    }
+}
+
+/// Public message of an old party: commitments to the polynomial resharing its Lagrange-weighted share
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReshareCommitment {
+    pub sender_index: u16,
+    pub commitments: Vec<Point<Ed25519>>,
+}
+
+impl Serialize for ReshareCommitment {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::ReshareCommitment as u8);
+        append_to.extend(self.sender_index.to_le_bytes());
+        write_points(&self.commitments, append_to);
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::ReshareCommitment {
+            return Err(Error::WrongTag { expected: Tag::ReshareCommitment.to_string(), found: tag.to_string() });
+        }
+        let sender_index = u16::from_le_bytes([b[1], b[2]]);
+        let (commitments, _) = read_points(&b[3..])?;
+        Ok(Self { sender_index, commitments })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + 2 + self.commitments.len() * 32
+    }
+}
+
+/// Public nonces a party generated ahead of time, entry `i` is used for the transfer signed with pool index `i`
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoncePool {
+    pub sender: Pubkey,
+    pub pool_id: Hash,
+    pub nonces: Vec<[Point<Ed25519>; 2]>,
+}
+
+impl Serialize for NoncePool {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::NoncePool as u8);
+        append_to.extend(self.sender.to_bytes());
+        append_to.extend(self.pool_id.to_bytes());
+        let points: Vec<Point<Ed25519>> = self.nonces.iter().flat_map(|n| n.iter().cloned()).collect();
+        write_points(&points, append_to);
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 32 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::NoncePool {
+            return Err(Error::WrongTag { expected: Tag::NoncePool.to_string(), found: tag.to_string() });
+        }
+        let mut sender_bytes = [0u8; 32];
+        sender_bytes.copy_from_slice(&b[1..1 + 32]);
+        let pool_id = Hash::new(&b[1 + 32..1 + 64]);
+        let (points, _) = read_points(&b[1 + 64..])?;
+        if points.len() % 2 != 0 {
+            return Err(Error::PointDeserializationFailed { field_name: "nonce pool" });
+        }
+        let nonces = points.chunks(2).map(|pair| [pair[0].clone(), pair[1].clone()]).collect();
+        Ok(Self { sender: Pubkey::from(sender_bytes), pool_id, nonces })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 32 + 2 + self.nonces.len() * 64
+    }
+}
+
+/// Secret part of a nonce pool, kept by its owner. Entries are removed once they are used
+#[derive(Debug, PartialEq)]
+pub struct SecretNoncePool {
+    pub signer: Pubkey,
+    pub pool_id: Hash,
+    pub entries: Vec<(u16, PrivatePartialNonces, PublicPartialNonces)>,
+}
+
+impl Serialize for SecretNoncePool {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::SecretNoncePool as u8);
+        append_to.extend(self.signer.to_bytes());
+        append_to.extend(self.pool_id.to_bytes());
+        append_to.extend((self.entries.len() as u16).to_le_bytes());
+        for (index, private_nonces, public_nonces) in &self.entries {
+            append_to.extend(index.to_le_bytes());
+            append_to.extend(&*private_nonces.r[0].to_bytes());
+            append_to.extend(&*private_nonces.r[1].to_bytes());
+            append_to.extend(&*public_nonces.R[0].to_bytes(true));
+            append_to.extend(&*public_nonces.R[1].to_bytes(true));
+        }
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 32 + 2 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 2, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::SecretNoncePool {
+            return Err(Error::WrongTag { expected: Tag::SecretNoncePool.to_string(), found: tag.to_string() });
+        }
+        let mut signer_bytes = [0u8; 32];
+        signer_bytes.copy_from_slice(&b[1..1 + 32]);
+        let pool_id = Hash::new(&b[1 + 32..1 + 64]);
+        let count = usize::from(u16::from_le_bytes([b[1 + 64], b[1 + 65]]));
+        let expected = 1 + 32 + 32 + 2 + count * (2 + 128);
+        if b.len() < expected {
+            return Err(Error::InputTooShort { expected, found: b.len() });
+        }
+
+        #[allow(non_snake_case)]
+        let entries = b[1 + 66..expected]
+            .chunks(2 + 128)
+            .map(|e| {
+                let index = u16::from_le_bytes([e[0], e[1]]);
+                let private_nonces =
+                    PrivatePartialNonces { r: [Scalar::from_bytes(&e[2..2 + 32])?, Scalar::from_bytes(&e[2 + 32..2 + 64])?] };
+                let public_nonces = PublicPartialNonces {
+                    R: [Point::from_bytes(&e[2 + 64..2 + 96])?, Point::from_bytes(&e[2 + 96..2 + 128])?],
+                };
+                Ok((index, private_nonces, public_nonces))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self { signer: Pubkey::from(signer_bytes), pool_id, entries })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 32 + 2 + self.entries.len() * (2 + 128)
+    }
+}
+
+/// The exact message bytes of a transaction every party signs, together with their digest
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionTemplate {
+    pub message: Vec<u8>,
+}
+
+impl Serialize for TransactionTemplate {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::TransactionTemplate as u8);
+        append_to.extend((self.message.len() as u16).to_le_bytes());
+        append_to.extend(&self.message);
+        append_to.extend(hash(&self.message).to_bytes());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 2 + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + 32, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::TransactionTemplate {
+            return Err(Error::WrongTag { expected: Tag::TransactionTemplate.to_string(), found: tag.to_string() });
+        }
+        let len = usize::from(u16::from_le_bytes([b[1], b[2]]));
+        if b.len() < 1 + 2 + len + 32 {
+            return Err(Error::InputTooShort { expected: 1 + 2 + len + 32, found: b.len() });
+        }
+        let message = b[3..3 + len].to_vec();
+        if hash(&message).as_ref() != &b[3 + len..3 + len + 32] {
+            return Err(Error::MismatchMessages);
+        }
+        Ok(Self { message })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 2 + self.message.len() + 32
+    }
+}
+
+/// A party's ephemeral transport key for one session, signed with its long-term identity
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportHello {
+    pub sender: Pubkey,
+    pub session_id: Hash,
+    pub ephemeral: Point<Ed25519>,
+    pub signature: Signature,
+}
+
+impl Serialize for TransportHello {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.reserve(self.size_hint());
+        append_to.push(Tag::TransportHello as u8);
+        append_to.extend(self.sender.to_bytes());
+        append_to.extend(self.session_id.to_bytes());
+        append_to.extend(&*self.ephemeral.to_bytes(true));
+        append_to.extend(self.signature.as_ref());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 32 + 32 + 64 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 64, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::TransportHello {
+            return Err(Error::WrongTag { expected: Tag::TransportHello.to_string(), found: tag.to_string() });
+        }
+        let mut sender_bytes = [0u8; 32];
+        sender_bytes.copy_from_slice(&b[1..1 + 32]);
+        let session_id = Hash::new(&b[1 + 32..1 + 64]);
+        let ephemeral = Point::from_bytes(&b[1 + 64..1 + 96])?;
+        let mut sig_bytes = [0u8; 64];
+        sig_bytes.copy_from_slice(&b[1 + 96..1 + 160]);
+        Ok(Self { sender: Pubkey::from(sender_bytes), session_id, ephemeral, signature: Signature::from(sig_bytes) })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 32 + 32 + 64
+    }
+}
+
+/// A round message encrypted for one recipient, signed by its sender
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub session_id: Hash,
+    pub round: u8,
+    pub ciphertext: Vec<u8>,
+    pub signature: Signature,
+}
+
+impl Envelope {
+    /// The bytes covered by the sender's signature, everything but the signature itself
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size_hint());
+        bytes.push(Tag::Envelope as u8);
+        bytes.extend(self.sender.to_bytes());
+        bytes.extend(self.recipient.to_bytes());
+        bytes.extend(self.session_id.to_bytes());
+        bytes.push(self.round);
+        bytes.extend((self.ciphertext.len() as u16).to_le_bytes());
+        bytes.extend(&self.ciphertext);
+        bytes
+    }
+}
+
+impl Serialize for Envelope {
+    fn serialize(&self, append_to: &mut Vec<u8>) {
+        append_to.extend(self.signed_bytes());
+        append_to.extend(self.signature.as_ref());
+    }
+
+    fn deserialize(b: &[u8]) -> Result<Self, Error> {
+        if b.len() < 1 + 32 + 32 + 32 + 1 + 2 + 64 {
+            return Err(Error::InputTooShort { expected: 1 + 32 + 32 + 32 + 1 + 2 + 64, found: b.len() });
+        }
+        let tag = Tag::try_from(b[0])?;
+        if tag != Tag::Envelope {
+            return Err(Error::WrongTag { expected: Tag::Envelope.to_string(), found: tag.to_string() });
+        }
+        let mut sender_bytes = [0u8; 32];
+        sender_bytes.copy_from_slice(&b[1..1 + 32]);
+        let mut recipient_bytes = [0u8; 32];
+        recipient_bytes.copy_from_slice(&b[1 + 32..1 + 64]);
+        let session_id = Hash::new(&b[1 + 64..1 + 96]);
+        let round = b[1 + 96];
+        let len = usize::from(u16::from_le_bytes([b[1 + 97], b[1 + 98]]));
+        let end = 1 + 99 + len;
+        if b.len() < end + 64 {
+            return Err(Error::InputTooShort { expected: end + 64, found: b.len() });
+        }
+        let mut sig_bytes = [0u8; 64];
+        sig_bytes.copy_from_slice(&b[end..end + 64]);
+        Ok(Self {
+            sender: Pubkey::from(sender_bytes),
+            recipient: Pubkey::from(recipient_bytes),
+            session_id,
+            round,
+            ciphertext: b[1 + 99..end].to_vec(),
+            signature: Signature::from(sig_bytes),
+        })
+    }
+
+    fn size_hint(&self) -> usize {
+        1 + 32 + 32 + 32 + 1 + 2 + self.ciphertext.len() + 64
+    }
+}
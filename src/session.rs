@@ -0,0 +1,243 @@
+use std::fs;
+use std::path::Path;
+
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::error::Error;
+use crate::serialization::{AggMessage1, PartialSignature, Serialize, TransactionTemplate};
+
+/// A signing session shared by all parties: what is being signed, by whom, and every round message so far.
+/// Secret state never goes into the session, each party keeps its own.
+#[derive(Debug, PartialEq)]
+pub struct Session {
+    /// Human readable description of the transaction, the template is what actually gets signed
+    pub intent: String,
+    pub keys: Vec<Pubkey>,
+    pub session_id: Hash,
+    pub template: TransactionTemplate,
+    pub first_messages: Vec<AggMessage1>,
+    pub partial_signatures: Vec<PartialSignature>,
+}
+
+/// On-disk JSON layout of a session, every binary value is base58 encoded
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct SessionFile {
+    intent: String,
+    wallet: String,
+    keys: Vec<String>,
+    session_id: String,
+    message_digest: String,
+    template: String,
+    first_messages: Vec<String>,
+    partial_signatures: Vec<String>,
+}
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, Error>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse::<T>().map_err(|e| Error::SessionFileError(format!("Invalid {} {}: {}", what, value, e)))
+}
+
+/// The template must be paid for by the MPC wallet of `keys`
+fn check_wallet(keys: &[Pubkey], template: &TransactionTemplate) -> Result<(), Error> {
+    let wallet = crate::tss::agg_key_to_pubkey(&crate::tss::key_agg(keys.to_vec(), None)?);
+    if template.fee_payer()? != wallet {
+        return Err(Error::SessionMismatch(format!(
+            "the transaction template is paid by {}, not by the aggregated key {}",
+            template.fee_payer()?,
+            wallet
+        )));
+    }
+    Ok(())
+}
+
+impl SessionFile {
+    pub(crate) fn from_session(session: &Session) -> Result<Self, Error> {
+        Ok(Self {
+            intent: session.intent.clone(),
+            wallet: session.template.fee_payer()?.to_string(),
+            keys: session.keys.iter().map(|key| key.to_string()).collect(),
+            session_id: session.session_id.to_string(),
+            message_digest: session.message_digest().to_string(),
+            template: session.template.serialize_bs58(),
+            first_messages: session.first_messages.iter().map(|msg| msg.serialize_bs58()).collect(),
+            partial_signatures: session.partial_signatures.iter().map(|sig| sig.serialize_bs58()).collect(),
+        })
+    }
+
+    /// Check and decode the session, `source` names where it was read from in errors
+    pub(crate) fn into_session(self, source: &str) -> Result<Session, Error> {
+        let keys = self.keys.iter().map(|key| parse(key, "key")).collect::<Result<Vec<Pubkey>, _>>()?;
+        let mut session = Session {
+            intent: self.intent,
+            keys,
+            session_id: parse(&self.session_id, "session id")?,
+            template: TransactionTemplate::deserialize_bs58(&self.template)?,
+            first_messages: Vec::new(),
+            partial_signatures: Vec::new(),
+        };
+
+        // The redundant fields are there for people reading the file, make sure they weren't edited
+        if parse::<Hash>(&self.message_digest, "message digest")? != session.message_digest()
+            || parse::<Pubkey>(&self.wallet, "wallet")? != session.template.fee_payer()?
+        {
+            return Err(Error::SessionFileError(format!("{} doesn't match its transaction template", source)));
+        }
+        check_wallet(&session.keys, &session.template)?;
+        for msg in &self.first_messages {
+            session.add_first_message(AggMessage1::deserialize_bs58(msg)?)?;
+        }
+        for sig in &self.partial_signatures {
+            session.add_partial_signature(PartialSignature::deserialize_bs58(sig)?)?;
+        }
+        Ok(session)
+    }
+}
+
+/// Write a file atomically, through a temporary file renamed over it
+pub(crate) fn write_atomically(path: &Path, contents: String) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents + "\n")
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| Error::SessionFileError(format!("Failed to write {}: {}", path.display(), e)))
+}
+
+impl Session {
+    /// Start a new session for `template`, signed by the MPC wallet of `keys`
+    pub fn new(intent: String, keys: Vec<Pubkey>, template: TransactionTemplate) -> Result<Self, Error> {
+        Self::join(intent, keys, Hash::new(&rand07::random::<[u8; 32]>()), template)
+    }
+
+    /// Join the existing session `session_id`, without any round messages yet
+    pub fn join(intent: String, keys: Vec<Pubkey>, session_id: Hash, template: TransactionTemplate) -> Result<Self, Error> {
+        check_wallet(&keys, &template)?;
+        Ok(Self { intent, keys, session_id, template, first_messages: Vec::new(), partial_signatures: Vec::new() })
+    }
+
+    /// The digest every party commits to in step one
+    pub fn message_digest(&self) -> Hash {
+        self.template.digest()
+    }
+
+    /// The first messages of everyone but `signer`, as step two expects them
+    pub fn first_messages_of_others(&self, signer: &Pubkey) -> Vec<AggMessage1> {
+        self.first_messages.iter().filter(|msg| &msg.sender != signer).cloned().collect()
+    }
+
+    /// Parties that haven't posted their first message yet
+    pub fn missing_first_messages(&self) -> Vec<Pubkey> {
+        self.keys.iter().filter(|key| !self.first_messages.iter().any(|m| &m.sender == *key)).copied().collect()
+    }
+
+    /// Parties that haven't posted their partial signature yet
+    pub fn missing_partial_signatures(&self) -> Vec<Pubkey> {
+        self.keys.iter().filter(|key| !self.partial_signatures.iter().any(|s| &s.sender == *key)).copied().collect()
+    }
+
+    /// Record a party's first message, it must belong to this session and come from one of the keys.
+    /// Adding the same message twice is a no-op, a different one from the same party is rejected.
+    pub fn add_first_message(&mut self, msg: AggMessage1) -> Result<(), Error> {
+        if !self.keys.contains(&msg.sender) {
+            return Err(Error::SessionMismatch(format!("first message from {} who is not in the keys", msg.sender)));
+        }
+        if msg.session_id != self.session_id || msg.message_digest != self.message_digest() {
+            return Err(Error::SessionMismatch(format!(
+                "first message from {} belongs to session {} for message {}, expected session {} for message {}",
+                msg.sender,
+                msg.session_id,
+                msg.message_digest,
+                self.session_id,
+                self.message_digest()
+            )));
+        }
+        match self.first_messages.iter().find(|m| m.sender == msg.sender) {
+            Some(existing) if existing == &msg => Ok(()),
+            Some(_) => Err(Error::SessionMismatch(format!("{} already posted a different first message", msg.sender))),
+            None => {
+                self.first_messages.push(msg);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record a party's partial signature, with the same rules as first messages
+    pub fn add_partial_signature(&mut self, sig: PartialSignature) -> Result<(), Error> {
+        if !self.keys.contains(&sig.sender) {
+            return Err(Error::SessionMismatch(format!("partial signature from {} who is not in the keys", sig.sender)));
+        }
+        if sig.session_id != self.session_id {
+            return Err(Error::SessionMismatch(format!(
+                "partial signature from {} belongs to session {}, expected {}",
+                sig.sender, sig.session_id, self.session_id
+            )));
+        }
+        match self.partial_signatures.iter().find(|s| s.sender == sig.sender) {
+            Some(existing) if existing == &sig => Ok(()),
+            Some(_) => Err(Error::SessionMismatch(format!("{} already posted a different partial signature", sig.sender))),
+            None => {
+                self.partial_signatures.push(sig);
+                Ok(())
+            }
+        }
+    }
+
+    /// Record a round message of either kind, telling them apart by their tag
+    pub fn add_message(&mut self, message: &str) -> Result<(), Error> {
+        if let Ok(msg) = AggMessage1::deserialize_bs58(message) {
+            return self.add_first_message(msg);
+        }
+        let sig = PartialSignature::deserialize_bs58(message)
+            .map_err(|e| Error::SessionFileError(format!("Not a first message or partial signature: {}", e)))?;
+        self.add_partial_signature(sig)
+    }
+
+    /// Read a session file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| Error::SessionFileError(format!("Failed to read {}: {}", path.display(), e)))?;
+        let file: SessionFile = serde_json::from_str(&contents)
+            .map_err(|e| Error::SessionFileError(format!("Failed to parse {}: {}", path.display(), e)))?;
+        file.into_session(&path.display().to_string())
+    }
+
+    /// Write the session file, replacing it atomically
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let contents = serde_json::to_string_pretty(&SessionFile::from_session(self)?)
+            .map_err(|e| Error::SessionFileError(format!("Failed to encode session: {}", e)))?;
+        write_atomically(path, contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sol_transfer_session, temp_ledger, TempFile};
+
+    #[test]
+    fn test_session_file_round_trip() {
+        let (keypairs, mut session) = sol_transfer_session(2);
+        let keys = session.keys.clone();
+        let (mut ledger, _ledger_file) = temp_ledger("session-ledger");
+        let (msg1, _) = crate::tss::step_one(keypairs[0].insecure_clone(), session.session_id, session.message_digest(), &mut ledger).unwrap();
+        session.add_message(&msg1.serialize_bs58()).unwrap();
+        session.add_message(&msg1.serialize_bs58()).unwrap();
+        assert_eq!(session.missing_first_messages(), vec![keys[1]]);
+
+        // A first message for another transaction is rejected
+        let (other, _) = crate::tss::step_one(keypairs[1].insecure_clone(), session.session_id, Hash::new_unique(), &mut ledger).unwrap();
+        assert!(session.add_first_message(other).is_err());
+
+        // So is a message with a tag no message type uses, as it can come from anyone on the network
+        let mut unknown = bs58::decode(msg1.serialize_bs58()).into_vec().unwrap();
+        unknown[0] = u8::MAX;
+        assert!(session.add_message(&bs58::encode(unknown).into_string()).is_err());
+
+        let file = TempFile::new("session", "json");
+        session.save(file.path()).unwrap();
+        assert_eq!(Session::load(file.path()).unwrap(), session);
+    }
+}
@@ -0,0 +1,209 @@
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::VersionedTransaction;
+
+use crate::error::Error;
+use crate::nonce_ledger::NonceLedger;
+use crate::serialization::{AggMessage1, PartialSignature, SecretAggStepOne};
+use crate::session::Session;
+use crate::tss;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Our nonces were generated, waiting for the first messages of the other parties
+pub struct NoncesGenerated {
+    first_message: AggMessage1,
+    secret_state: SecretAggStepOne,
+}
+
+/// We signed, waiting for the partial signatures of the other parties
+pub struct PartiallySigned {
+    partial_signature: PartialSignature,
+}
+
+/// Every partial signature was aggregated into the signed transaction
+pub struct Aggregated {
+    transaction: VersionedTransaction,
+}
+
+/// Why [`SigningSession::sign`] failed, and whether the session survived it
+pub enum SignError {
+    /// Some first messages are still missing, the session is handed back with its nonces unused
+    NotReady(SigningSession<NoncesGenerated>, Error),
+    /// Signing failed, the session and its nonces are abandoned
+    Failed(Error),
+}
+
+impl From<SignError> for Error {
+    fn from(e: SignError) -> Self {
+        match e {
+            SignError::NotReady(_, e) | SignError::Failed(e) => e,
+        }
+    }
+}
+
+/// States in which round messages of the other parties are still expected
+pub trait Collecting: sealed::Sealed {}
+
+impl sealed::Sealed for NoncesGenerated {}
+impl sealed::Sealed for PartiallySigned {}
+impl Collecting for NoncesGenerated {}
+impl Collecting for PartiallySigned {}
+
+/// One party's side of an MPC signing session, moving from [`NoncesGenerated`] to [`PartiallySigned`] to
+/// [`Aggregated`]. Each step consumes the previous state, so the secret nonces are signed with at most once,
+/// and every round message is checked against the session before it's accepted.
+pub struct SigningSession<S> {
+    keypair: Keypair,
+    session: Session,
+    state: S,
+}
+
+impl<S> SigningSession<S> {
+    /// The session being signed, with every round message received so far
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// The party signing on this side
+    pub fn signer(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+}
+
+impl<S: Collecting> SigningSession<S> {
+    /// Record another party's first message, it must belong to this session and transaction
+    pub fn add_first_message(&mut self, msg: AggMessage1) -> Result<(), Error> {
+        self.session.add_first_message(msg)
+    }
+
+    /// Record another party's partial signature, it must belong to this session
+    pub fn add_partial_signature(&mut self, sig: PartialSignature) -> Result<(), Error> {
+        self.session.add_partial_signature(sig)
+    }
+
+    /// Record a base58 round message of either kind
+    pub fn add_message(&mut self, message: &str) -> Result<(), Error> {
+        self.session.add_message(message)
+    }
+}
+
+impl SigningSession<NoncesGenerated> {
+    /// Generate our nonces for `session` (Step 1 of MPC), recording them in `ledger`
+    pub fn start(keypair: Keypair, mut session: Session, ledger: &mut NonceLedger) -> Result<Self, Error> {
+        if !session.keys.contains(&keypair.pubkey()) {
+            return Err(Error::KeyPairIsNotInKeys);
+        }
+        let (first_message, secret_state) =
+            tss::step_one(keypair.insecure_clone(), session.session_id, session.message_digest(), ledger)?;
+        session.add_first_message(first_message.clone())?;
+        Ok(Self { keypair, session, state: NoncesGenerated { first_message, secret_state } })
+    }
+
+    /// Our first message, to be sent to every other party
+    pub fn first_message(&self) -> &AggMessage1 {
+        &self.state.first_message
+    }
+
+    /// Whether every party's first message was added, so the session can be signed
+    pub fn is_ready(&self) -> bool {
+        self.session.missing_first_messages().is_empty()
+    }
+
+    /// Sign the template once every party's first message was added (Step 2 of MPC).
+    /// A session that isn't ready yet is handed back, any other error abandons the session
+    /// and its nonces are never used again.
+    pub fn sign(self, ledger: &mut NonceLedger) -> Result<SigningSession<PartiallySigned>, SignError> {
+        let missing = self.session.missing_first_messages();
+        if !missing.is_empty() {
+            let error = Error::SessionMismatch(format!(
+                "still waiting for first messages from: {}",
+                missing.iter().map(|key| key.to_string()).collect::<Vec<_>>().join(", ")
+            ));
+            return Err(SignError::NotReady(self, error));
+        }
+        let Self { keypair, mut session, state } = self;
+        let partial_signature = tss::step_two(
+            keypair.insecure_clone(),
+            &session.template,
+            session.keys.clone(),
+            session.first_messages_of_others(&keypair.pubkey()),
+            state.secret_state,
+            ledger,
+        )
+        .map_err(SignError::Failed)?;
+        session.add_partial_signature(partial_signature.clone()).map_err(SignError::Failed)?;
+        Ok(SigningSession { keypair, session, state: PartiallySigned { partial_signature } })
+    }
+}
+
+impl SigningSession<PartiallySigned> {
+    /// Our partial signature, to be sent to every other party
+    pub fn partial_signature(&self) -> &PartialSignature {
+        &self.state.partial_signature
+    }
+
+    /// Verify and aggregate every party's partial signature into the signed transaction (Step 3 of MPC)
+    pub fn aggregate(self) -> Result<SigningSession<Aggregated>, Error> {
+        let transaction = tss::sign_and_broadcast(
+            &self.session.template,
+            self.session.keys.clone(),
+            self.session.first_messages.clone(),
+            self.session.partial_signatures.clone(),
+        )?;
+        Ok(SigningSession { keypair: self.keypair, session: self.session, state: Aggregated { transaction } })
+    }
+}
+
+impl SigningSession<Aggregated> {
+    /// The signed transaction, ready to be broadcast
+    pub fn transaction(&self) -> &VersionedTransaction {
+        &self.state.transaction
+    }
+
+    pub fn into_transaction(self) -> VersionedTransaction {
+        self.state.transaction
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sol_transfer_session, temp_ledger};
+
+    #[test]
+    fn test_signing_session_states() {
+        let (keypairs, session) = sol_transfer_session(2);
+        let wallet = session.template.fee_payer().unwrap();
+        let (mut ledger, _ledger_file) = temp_ledger("signing-ledger");
+        let join = |session: &Session| {
+            Session::join(String::new(), session.keys.clone(), session.session_id, session.template.clone()).unwrap()
+        };
+        let mut first = SigningSession::start(keypairs[0].insecure_clone(), join(&session), &mut ledger).unwrap();
+        let mut second = SigningSession::start(keypairs[1].insecure_clone(), join(&session), &mut ledger).unwrap();
+
+        // A first message of another session is rejected
+        let other = Session::new(String::new(), session.keys.clone(), session.template.clone()).unwrap();
+        let stranger = SigningSession::start(keypairs[1].insecure_clone(), other, &mut ledger).unwrap();
+        assert!(first.add_first_message(stranger.first_message().clone()).is_err());
+
+        // Signing too early hands the session back, its nonces can still be used
+        let mut first = match first.sign(&mut ledger) {
+            Err(SignError::NotReady(first, Error::SessionMismatch(_))) => first,
+            _ => panic!("Expected the session to be handed back"),
+        };
+        assert!(!first.is_ready());
+
+        first.add_first_message(second.first_message().clone()).unwrap();
+        second.add_first_message(first.first_message().clone()).unwrap();
+        assert!(first.is_ready());
+        let mut first = first.sign(&mut ledger).ok().unwrap();
+        let second = second.sign(&mut ledger).ok().unwrap();
+        first.add_partial_signature(second.partial_signature().clone()).unwrap();
+
+        let tx = first.aggregate().unwrap().into_transaction();
+        assert_eq!(tx.message.static_account_keys()[0], wallet);
+    }
+}
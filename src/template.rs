@@ -0,0 +1,696 @@
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::address_lookup_table;
+use solana_sdk::address_lookup_table_account::AddressLookupTableAccount;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::hash::{hash, Hash};
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::{v0, Message, VersionedMessage};
+use solana_sdk::native_token;
+use solana_sdk::program_error::ProgramError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Signature, SIGNATURE_BYTES};
+use solana_sdk::system_instruction::{self, SystemInstruction};
+use solana_sdk::system_program;
+use solana_sdk::transaction::VersionedTransaction;
+use spl_token::native_mint;
+use spl_token_2022::instruction::{self as token_instruction, AuthorityType};
+
+use crate::compute_budget::with_compute_budget;
+use crate::error::Error;
+use crate::serialization::TransactionTemplate;
+use crate::token::{get_token_account, get_token_program, MintOptions, TokenMint};
+
+/// How a template is assembled, whatever its instructions do
+#[derive(Clone, Debug, Default)]
+pub struct TemplateOptions {
+    /// Durable nonce account of the wallet, the recent block hash must then be its current nonce value
+    pub nonce_account: Option<Pubkey>,
+    /// Compile a v0 message loading accounts from these tables, a legacy message is built without any
+    pub lookup_tables: Vec<AddressLookupTableAccount>,
+    /// Priority fee, in micro-lamports per compute unit
+    pub compute_unit_price: Option<u64>,
+    /// Compute units the transaction may use
+    pub compute_unit_limit: Option<u32>,
+}
+
+/// Name of the well known programs
+fn program_name(program_id: &Pubkey) -> Option<&'static str> {
+    if program_id == &system_program::id() {
+        Some("System Program")
+    } else if program_id == &spl_token::id() {
+        Some("Token Program")
+    } else if program_id == &spl_token_2022::id() {
+        Some("Token-2022 Program")
+    } else if program_id == &spl_associated_token_account::id() {
+        Some("Associated Token Account Program")
+    } else if program_id == &spl_memo::id() {
+        Some("Memo Program")
+    } else if program_id == &solana_sdk::compute_budget::id() {
+        Some("Compute Budget Program")
+    } else if program_id == &address_lookup_table::program::id() {
+        Some("Address Lookup Table Program")
+    } else {
+        None
+    }
+}
+
+/// Decode the data of an instruction of a well known program
+fn decode_instruction(program_id: &Pubkey, data: &[u8]) -> Option<String> {
+    if program_id == &system_program::id() {
+        bincode::deserialize::<SystemInstruction>(data).ok().map(|ix| format!("{:?}", ix))
+    } else if program_id == &spl_token::id() {
+        spl_token::instruction::TokenInstruction::unpack(data).ok().map(|ix| format!("{:?}", ix))
+    } else if program_id == &spl_token_2022::id() {
+        spl_token_2022::instruction::TokenInstruction::unpack(data).ok().map(|ix| format!("{:?}", ix))
+    } else if program_id == &spl_memo::id() {
+        std::str::from_utf8(data).ok().map(|memo| format!("Memo {:?}", memo))
+    } else if program_id == &solana_sdk::compute_budget::id() {
+        solana_sdk::borsh1::try_from_slice_unchecked::<ComputeBudgetInstruction>(data).ok().map(|ix| format!("{:?}", ix))
+    } else {
+        None
+    }
+}
+
+impl TransactionTemplate {
+    /// Fix the message signing `instructions` with `fee_payer` as the only signer
+    pub fn new(instructions: &[Instruction], fee_payer: &Pubkey, recent_block_hash: Hash) -> Self {
+        let mut message = Message::new(instructions, Some(fee_payer));
+        message.recent_blockhash = recent_block_hash;
+        Self { message: message.serialize() }
+    }
+
+    /// Fix a v0 message signing `instructions` with `fee_payer` as the only signer,
+    /// loading every account it can from `lookup_tables`
+    pub fn new_v0(
+        instructions: &[Instruction],
+        fee_payer: &Pubkey,
+        recent_block_hash: Hash,
+        lookup_tables: &[AddressLookupTableAccount],
+    ) -> Result<Self, Error> {
+        let message = v0::Message::try_compile(fee_payer, instructions, lookup_tables, recent_block_hash)
+            .map_err(|e| Error::LookupTableError(format!("Failed to compile the v0 message: {}", e)))?;
+        Self::from_message(&VersionedMessage::V0(message))
+    }
+
+    /// Build `instructions` from `wallet` as `options` asks: advancing a durable nonce first, then setting
+    /// the compute budget, as a v0 message if lookup tables are given
+    pub fn build(
+        instructions: Vec<Instruction>,
+        wallet: &Pubkey,
+        recent_block_hash: Hash,
+        options: &TemplateOptions,
+    ) -> Result<Self, Error> {
+        let instructions = with_compute_budget(instructions, options.compute_unit_price, options.compute_unit_limit);
+        let instructions = crate::token::with_advance_nonce(instructions, options.nonce_account.as_ref(), wallet);
+        if options.lookup_tables.is_empty() {
+            return Ok(Self::new(&instructions, wallet, recent_block_hash));
+        }
+        let template = Self::new_v0(&instructions, wallet, recent_block_hash, &options.lookup_tables)?;
+        // The runtime only finds a durable nonce account among the static keys
+        if options.nonce_account.is_some() && template.nonce_account()? != options.nonce_account {
+            return Err(Error::LookupTableError(
+                "the durable nonce account can't be loaded from a lookup table".to_string(),
+            ));
+        }
+        Ok(template)
+    }
+
+    /// The digest parties commit to in step one
+    pub fn digest(&self) -> Hash {
+        hash(&self.message)
+    }
+
+    /// Use a legacy or v0 message built elsewhere as is, it must be well formed and need exactly one signature
+    pub fn from_message(message: &VersionedMessage) -> Result<Self, Error> {
+        let template = Self { message: message.serialize() };
+        template.message()?;
+        Ok(template)
+    }
+
+    /// Decode the message, making sure it's well formed and needs exactly one signature
+    pub fn message(&self) -> Result<VersionedMessage, Error> {
+        let message: VersionedMessage = bincode::deserialize(&self.message)
+            .map_err(|e| Error::SerializationError(format!("Invalid transaction template: {}", e)))?;
+        message
+            .sanitize()
+            .map_err(|e| Error::SerializationError(format!("Invalid transaction template: {}", e)))?;
+        // The bytes must be the canonical encoding, or parties could be shown a different message than they sign
+        if message.serialize() != self.message {
+            return Err(Error::SerializationError("Transaction template is not canonically encoded".to_string()));
+        }
+        if message.header().num_required_signatures != 1 {
+            return Err(Error::SerializationError(format!(
+                "Transaction template needs {} signatures, MPC wallets can only provide one",
+                message.header().num_required_signatures
+            )));
+        }
+        Ok(message)
+    }
+
+    /// The account that signs and pays for the transaction, the MPC wallet
+    pub fn fee_payer(&self) -> Result<Pubkey, Error> {
+        Ok(self.message()?.static_account_keys()[0])
+    }
+
+    /// The durable nonce account advanced by the first instruction, if the transaction uses one
+    pub fn nonce_account(&self) -> Result<Option<Pubkey>, Error> {
+        let message = self.message()?;
+        let first = match message.instructions().first() {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        let program_id = message.static_account_keys()[usize::from(first.program_id_index)];
+        let is_advance = program_id == system_program::id()
+            && matches!(bincode::deserialize::<SystemInstruction>(&first.data), Ok(SystemInstruction::AdvanceNonceAccount));
+        // The nonce account of a v0 message may only be a static key
+        match (is_advance, first.accounts.first()) {
+            (true, Some(account)) => Ok(message.static_account_keys().get(usize::from(*account)).copied()),
+            _ => Ok(None),
+        }
+    }
+
+    /// Make sure a durable nonce still holds the value the template was signed with
+    pub fn check_durable_nonce(&self, rpc_client: &RpcClient) -> Result<(), Error> {
+        if let Some(nonce_account) = self.nonce_account()? {
+            crate::token::check_nonce_value(rpc_client, &nonce_account, self.message()?.recent_blockhash())?;
+        }
+        Ok(())
+    }
+
+    /// The signed transaction, exactly the template's message with the aggregated signature
+    pub fn to_transaction(&self, signature: Signature) -> Result<VersionedTransaction, Error> {
+        let tx = VersionedTransaction { signatures: vec![signature], message: self.message()? };
+        if tx.verify_with_results().iter().any(|valid| !valid) {
+            return Err(Error::InvalidSignature);
+        }
+        Ok(tx)
+    }
+
+    /// Size of the signed transaction on the wire: the signature count, the one signature, then the message
+    pub fn transaction_size(&self) -> usize {
+        1 + SIGNATURE_BYTES + self.message.len()
+    }
+
+    /// Human readable description of the message, one line each, so parties can review what they sign
+    pub fn describe(&self) -> Result<Vec<String>, Error> {
+        let message = self.message()?;
+        let header = message.header();
+        let static_keys = message.static_account_keys();
+        let num_signers = usize::from(header.num_required_signatures);
+        let num_writable_signers = num_signers - usize::from(header.num_readonly_signed_accounts);
+        let num_writable_static = static_keys.len() - usize::from(header.num_readonly_unsigned_accounts);
+
+        // Every account an instruction can refer to: static keys first, then writable and readonly table entries
+        let mut accounts: Vec<String> = static_keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| {
+                let signer = i < num_signers;
+                let writable = if signer { i < num_writable_signers } else { i < num_writable_static };
+                let role = match (signer, writable) {
+                    (true, true) => " (signer, writable)",
+                    (true, false) => " (signer)",
+                    (false, true) => " (writable)",
+                    (false, false) => "",
+                };
+                format!("{}{}", key, role)
+            })
+            .collect();
+        let mut lines = vec![match &message {
+            VersionedMessage::Legacy(_) => "version: legacy".to_string(),
+            VersionedMessage::V0(_) => "version: v0".to_string(),
+        }];
+        let lookups = message.address_table_lookups().unwrap_or_default();
+        for lookup in lookups {
+            lines.push(format!("address lookup table: {}", lookup.account_key));
+            accounts.extend(
+                lookup.writable_indexes.iter().map(|i| format!("lookup table {} entry {} (writable)", lookup.account_key, i)),
+            );
+        }
+        for lookup in lookups {
+            accounts.extend(lookup.readonly_indexes.iter().map(|i| format!("lookup table {} entry {}", lookup.account_key, i)));
+        }
+
+        lines.push(format!("fee payer: {}", static_keys[0]));
+        match self.nonce_account()? {
+            Some(nonce_account) => lines.push(format!("durable nonce: {} of {}", message.recent_blockhash(), nonce_account)),
+            None => lines.push(format!("recent block hash: {}", message.recent_blockhash())),
+        }
+        for (number, ix) in message.instructions().iter().enumerate() {
+            let program_id = static_keys[usize::from(ix.program_id_index)];
+            match program_name(&program_id) {
+                Some(name) => lines.push(format!("instruction {}: {} ({})", number + 1, name, program_id)),
+                None => lines.push(format!("instruction {}: {}", number + 1, program_id)),
+            }
+            for account in &ix.accounts {
+                let label = accounts.get(usize::from(*account)).map_or("<invalid account index>", String::as_str);
+                lines.push(format!("  account: {}", label));
+            }
+            match decode_instruction(&program_id, &ix.data) {
+                Some(decoded) => lines.push(format!("  {}", decoded)),
+                None => lines.push(format!("  data: {}", bs58::encode(&ix.data).into_string())),
+            }
+        }
+        Ok(lines)
+    }
+}
+
+/// Template of a message built by another tool: a serialized legacy or v0 message,
+/// or an unsigned transaction carrying one, whose signatures are ignored
+pub fn import(bytes: &[u8]) -> Result<TransactionTemplate, Error> {
+    if let Ok(message) = bincode::deserialize::<VersionedMessage>(bytes) {
+        if message.serialize() == bytes {
+            return TransactionTemplate::from_message(&message);
+        }
+    }
+    let tx: VersionedTransaction = bincode::deserialize(bytes)
+        .map_err(|e| Error::SerializationError(format!("Neither a message nor a transaction: {}", e)))?;
+    TransactionTemplate::from_message(&tx.message)
+}
+
+/// Template of a token transfer from the MPC `wallet`, of an SPL Token or Token-2022 mint.
+/// Whether the recipient's token account has to be created, and the transfer fee of a Token-2022 mint,
+/// are decided here, once, for all parties.
+#[allow(clippy::too_many_arguments)]
+pub fn token_transfer(
+    mint: Pubkey,
+    amount: u64,
+    decimals: u8,
+    to: &Pubkey,
+    memo: Option<String>,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    if token_mint.decimals != decimals {
+        return Err(Error::TokenTransferFailed(format!(
+            "mint {} has {} decimals, not {}",
+            mint, token_mint.decimals, decimals
+        )));
+    }
+
+    // Wrap the SOL the wallet's wrapped SOL account is short of on the way
+    let mut instructions = Vec::new();
+    if mint == native_mint::id() {
+        instructions.extend(wrap_shortfall(rpc_client, wallet, amount)?);
+    }
+
+    // Create the destination ATA if needed, paid by the wallet
+    instructions.extend(token_mint.transfer_instructions(rpc_client, wallet, to, wallet, amount, memo)?);
+    TransactionTemplate::build(instructions, wallet, recent_block_hash, options)
+}
+
+/// Template creating a mint whose mint authority is the MPC `wallet`, as is its freeze authority if `freeze`.
+/// The mint address is derived from the wallet and `seed`, so no other key has to sign: the wallet alone
+/// creates the account and pays for it. Returns the mint address.
+#[allow(clippy::too_many_arguments)]
+pub fn create_mint(
+    seed: &str,
+    decimals: u8,
+    freeze: bool,
+    mint_options: &MintOptions,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<(TransactionTemplate, Pubkey), Error> {
+    let program_id = mint_options.program_id();
+    let mint = Pubkey::create_with_seed(wallet, seed, &program_id)
+        .map_err(|e| Error::TokenCreationFailed(format!("Invalid seed {:?}: {}", seed, e)))?;
+    if rpc_client.get_account(&mint).is_ok() {
+        return Err(Error::TokenCreationFailed(format!("{} already exists, pick another seed", mint)));
+    }
+
+    let freeze_authority = if freeze { Some(wallet) } else { None };
+    let (mint_len, initialize_instructions) =
+        mint_options.initialize_instructions(&mint, wallet, freeze_authority, decimals)?;
+    let rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(mint_len)
+        .map_err(Error::RecentHashFailed)?;
+    let mut instructions =
+        vec![system_instruction::create_account_with_seed(wallet, &mint, wallet, seed, rent, mint_len as u64, &program_id)];
+    instructions.extend(initialize_instructions);
+    Ok((TransactionTemplate::build(instructions, wallet, recent_block_hash, options)?, mint))
+}
+
+/// Template minting tokens of a mint whose mint authority is the MPC `wallet`.
+/// The recipient's token account is created if needed, paid by the wallet.
+#[allow(clippy::too_many_arguments)]
+pub fn mint_to(
+    mint: Pubkey,
+    amount: u64,
+    decimals: u8,
+    to: &Pubkey,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let destination = token_mint.associated_token_address(to);
+    let mut instructions = Vec::new();
+    if rpc_client.get_account(&destination).is_err() {
+        instructions.push(spl_associated_token_account::instruction::create_associated_token_account(
+            wallet,
+            to,
+            &mint,
+            &token_mint.program_id,
+        ));
+    }
+    instructions.push(
+        token_instruction::mint_to_checked(
+            &token_mint.program_id,
+            &mint,
+            &destination,
+            wallet,
+            &[],
+            amount,
+            decimals,
+        )
+        .map_err(|e| Error::TokenMintFailed(format!("Failed to create mint instruction: {}", e)))?,
+    );
+    TransactionTemplate::build(instructions, wallet, recent_block_hash, options)
+}
+
+/// Template of a single token program instruction signed by the MPC `wallet`, as owner or authority
+fn token_program_template(
+    instruction: Result<Instruction, ProgramError>,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+) -> Result<TransactionTemplate, Error> {
+    let instruction = instruction
+        .map_err(|e| Error::TokenInstructionFailed(format!("Failed to create instruction: {}", e)))?;
+    TransactionTemplate::build(vec![instruction], wallet, recent_block_hash, options)
+}
+
+/// Template burning tokens held by the MPC `wallet`
+pub fn burn(
+    mint: Pubkey,
+    amount: u64,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let instruction = token_instruction::burn_checked(
+        &token_mint.program_id,
+        &token_mint.associated_token_address(wallet),
+        &mint,
+        wallet,
+        &[],
+        amount,
+        token_mint.decimals,
+    );
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template freezing the token account of `owner`, the MPC `wallet` being the freeze authority of the mint
+pub fn freeze_account(
+    mint: Pubkey,
+    owner: &Pubkey,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let account = token_mint.associated_token_address(owner);
+    let instruction = token_instruction::freeze_account(&token_mint.program_id, &account, &mint, wallet, &[]);
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template thawing the frozen token account of `owner`, the MPC `wallet` being the freeze authority of the mint
+pub fn thaw_account(
+    mint: Pubkey,
+    owner: &Pubkey,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let account = token_mint.associated_token_address(owner);
+    let instruction = token_instruction::thaw_account(&token_mint.program_id, &account, &mint, wallet, &[]);
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template closing the empty token account of the MPC `wallet`, its rent going to `destination`
+pub fn close_account(
+    mint: Pubkey,
+    destination: &Pubkey,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let account = token_mint.associated_token_address(wallet);
+
+    // Only wrapped SOL accounts can be closed with a balance, it's returned along with the rent
+    let state = get_token_account(rpc_client, &account)?;
+    if !state.is_native() && state.amount != 0 {
+        return Err(Error::TokenInstructionFailed(format!(
+            "{} still holds {} base units, burn or transfer them first",
+            account, state.amount
+        )));
+    }
+    let instruction = token_instruction::close_account(&token_mint.program_id, &account, destination, wallet, &[]);
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template allowing `delegate` to transfer or burn up to `amount` tokens of the MPC `wallet`
+pub fn approve(
+    mint: Pubkey,
+    delegate: &Pubkey,
+    amount: u64,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let instruction = token_instruction::approve_checked(
+        &token_mint.program_id,
+        &token_mint.associated_token_address(wallet),
+        &mint,
+        delegate,
+        wallet,
+        &[],
+        amount,
+        token_mint.decimals,
+    );
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template removing the delegate of the token account of the MPC `wallet`
+pub fn revoke(
+    mint: Pubkey,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let token_mint = TokenMint::fetch(rpc_client, &mint)?;
+    let account = token_mint.associated_token_address(wallet);
+    let instruction = token_instruction::revoke(&token_mint.program_id, &account, wallet, &[]);
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Template handing an authority the MPC `wallet` holds over a mint or a token account to `new_authority`,
+/// or removing it for good if `None`
+pub fn set_authority(
+    target: &Pubkey,
+    authority_type: AuthorityType,
+    new_authority: Option<&Pubkey>,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    let program_id = get_token_program(rpc_client, target)?;
+    let classic = matches!(
+        authority_type,
+        AuthorityType::MintTokens | AuthorityType::FreezeAccount | AuthorityType::AccountOwner | AuthorityType::CloseAccount
+    );
+    if program_id == spl_token::id() && !classic {
+        return Err(Error::TokenInstructionFailed(format!(
+            "{} belongs to the SPL Token program, which has no {:?} authority",
+            target, authority_type
+        )));
+    }
+    let instruction =
+        token_instruction::set_authority(&program_id, target, new_authority, authority_type, wallet, &[]);
+    token_program_template(instruction, wallet, recent_block_hash, options)
+}
+
+/// Instructions moving `lamports` of the MPC `wallet` into its wrapped SOL account, created if needed
+fn wrap_instructions(wallet: &Pubkey, lamports: u64) -> Result<Vec<Instruction>, Error> {
+    let account = spl_associated_token_account::get_associated_token_address(wallet, &native_mint::id());
+    let sync_native = token_instruction::sync_native(&spl_token::id(), &account)
+        .map_err(|e| Error::TokenInstructionFailed(format!("Failed to create sync native instruction: {}", e)))?;
+    Ok(vec![
+        spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+            wallet,
+            wallet,
+            &native_mint::id(),
+            &spl_token::id(),
+        ),
+        system_instruction::transfer(wallet, &account, lamports),
+        sync_native,
+    ])
+}
+
+/// Instructions wrapping the SOL the wrapped SOL account of the MPC `wallet` is short of to hold `lamports`,
+/// none if it already holds enough
+pub(crate) fn wrap_shortfall(rpc_client: &RpcClient, wallet: &Pubkey, lamports: u64) -> Result<Vec<Instruction>, Error> {
+    let account = spl_associated_token_account::get_associated_token_address(wallet, &native_mint::id());
+    let held = get_token_account(rpc_client, &account).map_or(0, |account| account.amount);
+    if held >= lamports {
+        return Ok(Vec::new());
+    }
+    wrap_instructions(wallet, lamports - held)
+}
+
+/// Template wrapping SOL of the MPC `wallet` into wrapped SOL, held in its associated token account
+pub fn wrap_sol(
+    amount: f64,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+) -> Result<TransactionTemplate, Error> {
+    let instructions = wrap_instructions(wallet, native_token::sol_to_lamports(amount))?;
+    TransactionTemplate::build(instructions, wallet, recent_block_hash, options)
+}
+
+/// Template unwrapping all the wrapped SOL of the MPC `wallet`, closing its wrapped SOL account
+pub fn unwrap_sol(
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+    rpc_client: &RpcClient,
+) -> Result<TransactionTemplate, Error> {
+    close_account(native_mint::id(), wallet, wallet, recent_block_hash, options, rpc_client)
+}
+
+/// Template of a SOL transfer from the MPC `wallet`, with an optional memo
+pub fn sol_transfer(
+    amount: f64,
+    to: &Pubkey,
+    memo: Option<String>,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+) -> Result<TransactionTemplate, Error> {
+    let mut instructions = vec![system_instruction::transfer(wallet, to, native_token::sol_to_lamports(amount))];
+    if let Some(memo) = memo {
+        instructions.push(Instruction { program_id: spl_memo::id(), accounts: Vec::new(), data: memo.into_bytes() });
+    }
+    TransactionTemplate::build(instructions, wallet, recent_block_hash, options)
+}
+
+/// Template creating an address lookup table owned by the MPC `wallet`, which also pays for it.
+/// Returns the address of the table, derived from `recent_slot`: the transaction must land
+/// while that slot is still among the recent slot hashes, roughly within 3 minutes.
+pub fn create_lookup_table(
+    wallet: &Pubkey,
+    recent_slot: u64,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+) -> Result<(TransactionTemplate, Pubkey), Error> {
+    let (instruction, lookup_table) = address_lookup_table::instruction::create_lookup_table(*wallet, *wallet, recent_slot);
+    Ok((TransactionTemplate::build(vec![instruction], wallet, recent_block_hash, options)?, lookup_table))
+}
+
+/// Template adding `addresses` to a lookup table owned by the MPC `wallet`, which pays for the extra space
+pub fn extend_lookup_table(
+    lookup_table: &Pubkey,
+    addresses: Vec<Pubkey>,
+    wallet: &Pubkey,
+    recent_block_hash: Hash,
+    options: &TemplateOptions,
+) -> Result<TransactionTemplate, Error> {
+    let instruction = address_lookup_table::instruction::extend_lookup_table(*lookup_table, *wallet, Some(*wallet), addresses);
+    TransactionTemplate::build(vec![instruction], wallet, recent_block_hash, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::Serialize;
+
+    #[test]
+    fn test_template_round_trip_and_nonce_detection() {
+        let wallet = Pubkey::new_unique();
+        let nonce_account = Pubkey::new_unique();
+        let options = TemplateOptions { nonce_account: Some(nonce_account), ..Default::default() };
+        let template = sol_transfer(0.5, &Pubkey::new_unique(), None, &wallet, Hash::new_unique(), &options).unwrap();
+
+        let decoded = TransactionTemplate::deserialize_bs58(template.serialize_bs58()).unwrap();
+        assert_eq!(decoded.digest(), template.digest());
+        assert_eq!(decoded.fee_payer().unwrap(), wallet);
+        assert_eq!(decoded.nonce_account().unwrap(), Some(nonce_account));
+
+        let options = TemplateOptions::default();
+        let without_nonce = sol_transfer(0.5, &Pubkey::new_unique(), None, &wallet, Hash::new_unique(), &options).unwrap();
+        assert_eq!(without_nonce.nonce_account().unwrap(), None);
+    }
+
+    #[test]
+    fn test_v0_template_loads_accounts_from_lookup_tables() {
+        let wallet = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let nonce_account = Pubkey::new_unique();
+        let table = AddressLookupTableAccount { key: Pubkey::new_unique(), addresses: vec![to, nonce_account] };
+        let options = TemplateOptions { lookup_tables: vec![table.clone()], ..Default::default() };
+
+        let template = sol_transfer(0.5, &to, None, &wallet, Hash::new_unique(), &options).unwrap();
+        let description = template.describe().unwrap();
+        assert_eq!(description[0], "version: v0");
+        assert!(description.contains(&format!("  account: lookup table {} entry 0 (writable)", table.key)));
+
+        // A durable nonce account must stay a static key
+        let options = TemplateOptions { nonce_account: Some(nonce_account), lookup_tables: vec![table], ..Default::default() };
+        assert!(sol_transfer(0.5, &to, None, &wallet, Hash::new_unique(), &options).is_err());
+    }
+
+    #[test]
+    fn test_wrap_sol_funds_the_wrapped_sol_account() {
+        let wallet = Pubkey::new_unique();
+        let template = wrap_sol(1.5, &wallet, Hash::new_unique(), &TemplateOptions::default()).unwrap();
+        let message = template.message().unwrap();
+        let keys = message.static_account_keys();
+        let program_ids: Vec<Pubkey> = message.instructions().iter().map(|ix| keys[usize::from(ix.program_id_index)]).collect();
+        assert_eq!(program_ids, vec![spl_associated_token_account::id(), system_program::id(), spl_token::id()]);
+
+        // The lamports go to the wrapped SOL account, then sync_native counts them as tokens
+        let transfer = &message.instructions()[1];
+        let account = spl_associated_token_account::get_associated_token_address(&wallet, &native_mint::id());
+        assert_eq!(keys[usize::from(transfer.accounts[1])], account);
+        assert_eq!(
+            bincode::deserialize::<SystemInstruction>(&transfer.data).unwrap(),
+            SystemInstruction::Transfer { lamports: 1_500_000_000 }
+        );
+    }
+
+    #[test]
+    fn test_import_v0_message_and_unsigned_transaction() {
+        let wallet = Pubkey::new_unique();
+        let instructions = [system_instruction::transfer(&wallet, &Pubkey::new_unique(), 1_000)];
+        let message = VersionedMessage::V0(
+            solana_sdk::message::v0::Message::try_compile(&wallet, &instructions, &[], Hash::new_unique()).unwrap(),
+        );
+
+        let template = import(&message.serialize()).unwrap();
+        assert_eq!(template.fee_payer().unwrap(), wallet);
+        assert_eq!(template.describe().unwrap()[0], "version: v0");
+
+        // The same message inside an unsigned transaction gives the same template
+        let tx = VersionedTransaction { signatures: vec![Signature::default()], message };
+        assert_eq!(import(&bincode::serialize(&tx).unwrap()).unwrap(), template);
+        assert!(import(b"not a message").is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! Setup shared by the signing tests
+
+use std::path::{Path, PathBuf};
+
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::nonce_ledger::NonceLedger;
+use crate::serialization::TransactionTemplate;
+use crate::session::Session;
+use crate::template::{self, TemplateOptions};
+use crate::tss;
+
+/// A file in the temporary directory that is removed when the guard is dropped, even if the test panics
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    /// A fresh path, `name` tells which test the file belongs to
+    pub fn new(name: &str, extension: &str) -> Self {
+        let id = Hash::new(&rand07::random::<[u8; 32]>());
+        Self(std::env::temp_dir().join(format!("{}-{}.{}", name, id, extension)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // The test may have failed before creating the file
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// An empty nonce ledger in a temporary file, and the guard that removes it
+pub fn temp_ledger(name: &str) -> (NonceLedger, TempFile) {
+    let file = TempFile::new(name, "log");
+    let ledger = NonceLedger::open(file.path()).unwrap();
+    (ledger, file)
+}
+
+/// A 0.1 SOL transfer template paid by the aggregated key of `keys`
+pub fn sol_transfer_template(keys: &[Pubkey]) -> TransactionTemplate {
+    let wallet = tss::agg_key_to_pubkey(&tss::key_agg(keys.to_vec(), None).unwrap());
+    let options = TemplateOptions::default();
+    template::sol_transfer(0.1, &Pubkey::new_unique(), None, &wallet, Hash::new_unique(), &options).unwrap()
+}
+
+/// `parties` fresh keypairs and the signing session of a 0.1 SOL transfer from their aggregated key
+pub fn sol_transfer_session(parties: usize) -> (Vec<Keypair>, Session) {
+    let keypairs: Vec<Keypair> = (0..parties).map(|_| Keypair::new()).collect();
+    let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+    let template = sol_transfer_template(&keys);
+    let session = Session::new("Transfer 0.1 SOL".to_string(), keys, template).unwrap();
+    (keypairs, session)
+}
@@ -1,25 +1,257 @@
+use solana_client::nonce_utils;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::{
+    address_lookup_table::state::AddressLookupTable,
+    address_lookup_table_account::AddressLookupTableAccount,
+    hash::Hash,
+    nonce::State as NonceState,
     pubkey::Pubkey,
     signature::{Keypair, Signature, Signer},
     system_instruction,
     transaction::Transaction,
-    program_pack::Pack,
-    native_token,
     instruction::Instruction,
-    message::Message,
 };
-use spl_token::{
-    instruction::{initialize_mint, mint_to_checked, transfer},
-    state::Mint,
+use spl_token_2022::{
+    extension::{
+        interest_bearing_mint,
+        memo_transfer::MemoTransfer,
+        non_transferable::NonTransferable,
+        transfer_fee::{self, TransferFeeConfig},
+        transfer_hook::TransferHook,
+        BaseStateWithExtensions,
+        ExtensionType,
+        StateWithExtensions,
+    },
+    instruction::{initialize_mint, mint_to_checked, transfer_checked},
+    state::{Account, Mint},
 };
 use spl_associated_token_account::{
-    get_associated_token_address,
-    instruction::create_associated_token_account,
+    get_associated_token_address_with_program_id,
+    instruction::{create_associated_token_account, create_associated_token_account_idempotent},
 };
 
+use crate::compute_budget::ComputeBudget;
 use crate::error::Error;
 
+/// A mint, with what its token program requires of the instructions using it
+#[derive(Clone, Debug)]
+pub struct TokenMint {
+    pub address: Pubkey,
+    /// SPL Token or Token-2022, whichever owns the mint
+    pub program_id: Pubkey,
+    pub decimals: u8,
+    /// Fees withheld on every transfer of a Token-2022 mint with the transfer fee extension
+    pub transfer_fee: Option<TransferFeeConfig>,
+    /// Program a Token-2022 mint calls on every transfer
+    pub transfer_hook: Option<Pubkey>,
+    /// Tokens of a non-transferable Token-2022 mint can't leave the account they were minted to
+    pub non_transferable: bool,
+}
+
+impl TokenMint {
+    /// Fetch a mint and its extensions, from either token program
+    pub fn fetch(rpc_client: &RpcClient, address: &Pubkey) -> Result<Self, Error> {
+        let account = rpc_client
+            .get_account(address)
+            .map_err(|e| Error::MintAccountError(format!("Failed to fetch {}: {}", address, e)))?;
+        if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+            return Err(Error::MintAccountError(format!(
+                "{} is owned by {}, not by a token program",
+                address, account.owner
+            )));
+        }
+        let mint = StateWithExtensions::<Mint>::unpack(&account.data)
+            .map_err(|e| Error::MintAccountError(format!("{} is not a mint: {}", address, e)))?;
+        Ok(Self {
+            address: *address,
+            program_id: account.owner,
+            decimals: mint.base.decimals,
+            transfer_fee: mint.get_extension::<TransferFeeConfig>().ok().copied(),
+            transfer_hook: mint.get_extension::<TransferHook>().ok().and_then(|hook| hook.program_id.into()),
+            non_transferable: mint.get_extension::<NonTransferable>().is_ok(),
+        })
+    }
+
+    /// Associated token account of `owner` for this mint
+    pub fn associated_token_address(&self, owner: &Pubkey) -> Pubkey {
+        get_associated_token_address_with_program_id(owner, &self.address, &self.program_id)
+    }
+
+    /// Instructions transferring `amount` from the token account of `source_owner` to the one of
+    /// `destination_owner`. The destination account is created, paid by `payer`, if it doesn't exist yet,
+    /// and `memo` goes right before the transfer, as destination accounts requiring memos expect.
+    pub fn transfer_instructions(
+        &self,
+        rpc_client: &RpcClient,
+        source_owner: &Pubkey,
+        destination_owner: &Pubkey,
+        payer: &Pubkey,
+        amount: u64,
+        memo: Option<String>,
+    ) -> Result<Vec<Instruction>, Error> {
+        if self.non_transferable {
+            return Err(Error::TokenTransferFailed(format!("mint {} is non-transferable", self.address)));
+        }
+        if let Some(program_id) = self.transfer_hook {
+            return Err(Error::TokenTransferFailed(format!(
+                "mint {} calls transfer hook program {}, which isn't supported",
+                self.address, program_id
+            )));
+        }
+        let source = self.associated_token_address(source_owner);
+        let destination = self.associated_token_address(destination_owner);
+        let mut instructions = Vec::new();
+
+        match rpc_client.get_account(&destination) {
+            Ok(account) => {
+                if memo.is_none() && memo_required(&account.data) {
+                    return Err(Error::TokenTransferFailed(format!(
+                        "token account {} requires a memo on incoming transfers",
+                        destination
+                    )));
+                }
+            }
+            // Idempotent, so several transfers to the same new account can each carry the creation
+            Err(_) => instructions.push(create_associated_token_account_idempotent(
+                payer,
+                destination_owner,
+                &self.address,
+                &self.program_id,
+            )),
+        }
+        if let Some(memo) = memo {
+            instructions.push(spl_memo::build_memo(memo.as_bytes(), &[]));
+        }
+
+        // The fee is checked on chain against the one of the epoch the transfer lands in
+        let transfer_instruction = match &self.transfer_fee {
+            Some(config) => {
+                let epoch = rpc_client.get_epoch_info().map_err(Error::RecentHashFailed)?.epoch;
+                let fee = config.calculate_epoch_fee(epoch, amount).ok_or_else(|| {
+                    Error::TokenTransferFailed(format!("transfer fee of {} tokens overflows", amount))
+                })?;
+                transfer_fee::instruction::transfer_checked_with_fee(
+                    &self.program_id,
+                    &source,
+                    &self.address,
+                    &destination,
+                    source_owner,
+                    &[],
+                    amount,
+                    self.decimals,
+                    fee,
+                )
+            }
+            None => transfer_checked(
+                &self.program_id,
+                &source,
+                &self.address,
+                &destination,
+                source_owner,
+                &[],
+                amount,
+                self.decimals,
+            ),
+        }
+        .map_err(|e| Error::TokenTransferFailed(format!("Failed to create transfer instruction: {}", e)))?;
+        instructions.push(transfer_instruction);
+        Ok(instructions)
+    }
+}
+
+/// Token program owning a mint or a token account
+pub fn get_token_program(rpc_client: &RpcClient, address: &Pubkey) -> Result<Pubkey, Error> {
+    let account = rpc_client
+        .get_account(address)
+        .map_err(|e| Error::TokenInstructionFailed(format!("Failed to fetch {}: {}", address, e)))?;
+    if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+        return Err(Error::TokenInstructionFailed(format!(
+            "{} is owned by {}, not by a token program",
+            address, account.owner
+        )));
+    }
+    Ok(account.owner)
+}
+
+/// Read a token account of either token program
+pub fn get_token_account(rpc_client: &RpcClient, address: &Pubkey) -> Result<Account, Error> {
+    let account = rpc_client.get_account(address).map_err(|_| Error::TokenAccountNotFound)?;
+    StateWithExtensions::<Account>::unpack(&account.data)
+        .map(|account| account.base)
+        .map_err(|e| Error::TokenInstructionFailed(format!("{} is not a token account: {}", address, e)))
+}
+
+/// Whether a token account has the Token-2022 extension requiring a memo on incoming transfers
+fn memo_required(data: &[u8]) -> bool {
+    StateWithExtensions::<Account>::unpack(data)
+        .ok()
+        .and_then(|account| account.get_extension::<MemoTransfer>().ok().map(|ext| ext.require_incoming_transfer_memos))
+        .map_or(false, bool::from)
+}
+
+/// Token program of a new mint, and the Token-2022 extensions to initialize it with
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MintOptions {
+    /// Create a Token-2022 mint instead of an SPL Token one
+    pub token_2022: bool,
+    /// Transfer fee in basis points, and the most a single transfer pays
+    pub transfer_fee: Option<(u16, u64)>,
+    /// Interest rate in basis points
+    pub interest_rate: Option<i16>,
+}
+
+impl MintOptions {
+    /// Token program owning the new mint
+    pub fn program_id(&self) -> Pubkey {
+        if self.token_2022 { spl_token_2022::id() } else { spl_token::id() }
+    }
+
+    /// Size of the mint account, and the instructions initializing the created account as a mint.
+    /// The extensions are initialized before the mint itself, the mint authority controls them.
+    pub fn initialize_instructions(
+        &self,
+        mint: &Pubkey,
+        mint_authority: &Pubkey,
+        freeze_authority: Option<&Pubkey>,
+        decimals: u8,
+    ) -> Result<(usize, Vec<Instruction>), Error> {
+        let program_id = self.program_id();
+        let mut extensions = Vec::new();
+        let mut instructions = Vec::new();
+        if let Some((basis_points, maximum_fee)) = self.transfer_fee {
+            extensions.push(ExtensionType::TransferFeeConfig);
+            instructions.push(transfer_fee::instruction::initialize_transfer_fee_config(
+                &program_id,
+                mint,
+                Some(mint_authority),
+                Some(mint_authority),
+                basis_points,
+                maximum_fee,
+            ));
+        }
+        if let Some(rate) = self.interest_rate {
+            extensions.push(ExtensionType::InterestBearingConfig);
+            instructions.push(interest_bearing_mint::instruction::initialize(
+                &program_id,
+                mint,
+                Some(*mint_authority),
+                rate,
+            ));
+        }
+        if !extensions.is_empty() && !self.token_2022 {
+            return Err(Error::TokenCreationFailed("mint extensions need a Token-2022 mint".to_string()));
+        }
+        instructions.push(initialize_mint(&program_id, mint, mint_authority, freeze_authority, decimals));
+        let instructions = instructions
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::TokenCreationFailed(format!("Failed to create initialize mint instruction: {}", e)))?;
+        let mint_len = ExtensionType::try_calculate_account_len::<Mint>(&extensions)
+            .map_err(|e| Error::TokenCreationFailed(format!("Failed to size the mint account: {}", e)))?;
+        Ok((mint_len, instructions))
+    }
+}
+
 /// Create a new SPL token mint
 pub fn create_token_mint(
     rpc_client: &RpcClient,
@@ -27,14 +259,18 @@ pub fn create_token_mint(
     mint_authority: &Pubkey,
     freeze_authority: Option<&Pubkey>,
     decimals: u8,
+    options: &MintOptions,
+    compute_budget: &ComputeBudget,
 ) -> Result<(Pubkey, Signature), Error> {
     // Generate a new keypair for the mint account
     let mint_keypair = Keypair::new();
     let mint_pubkey = mint_keypair.pubkey();
+    let (mint_len, initialize_instructions) =
+        options.initialize_instructions(&mint_pubkey, mint_authority, freeze_authority, decimals)?;
     
     // Calculate the minimum rent for a mint account
     let mint_rent = rpc_client
-        .get_minimum_balance_for_rent_exemption(Mint::LEN)
+        .get_minimum_balance_for_rent_exemption(mint_len)
         .map_err(Error::RecentHashFailed)?;
     
     // Create the mint account
@@ -42,22 +278,14 @@ pub fn create_token_mint(
         &payer.pubkey(),
         &mint_pubkey,
         mint_rent,
-        Mint::LEN as u64,
-        &spl_token::id(),
+        mint_len as u64,
+        &options.program_id(),
     );
     
-    // Initialize the mint
-    let initialize_mint_instruction = initialize_mint(
-        &spl_token::id(),
-        &mint_pubkey,
-        mint_authority,
-        freeze_authority,
-        decimals,
-    )
-    .map_err(|e| Error::TokenCreationFailed(format!("Failed to create initialize mint instruction: {}", e)))?;
-    
     // Create and send transaction
-    let instructions = vec![create_account_instruction, initialize_mint_instruction];
+    let mut instructions = vec![create_account_instruction];
+    instructions.extend(initialize_instructions);
+    let instructions = compute_budget.apply(rpc_client, instructions, &payer.pubkey())?;
     let recent_blockhash = rpc_client
         .get_latest_blockhash()
         .map_err(Error::RecentHashFailed)?;
@@ -77,6 +305,7 @@ pub fn create_token_mint(
 }
 
 /// Mint tokens to a destination account
+#[allow(clippy::too_many_arguments)]
 pub fn mint_tokens_to(
     rpc_client: &RpcClient,
     payer: &Keypair,
@@ -85,9 +314,11 @@ pub fn mint_tokens_to(
     mint_authority: &Keypair,
     amount: u64,
     decimals: u8,
+    compute_budget: &ComputeBudget,
 ) -> Result<Signature, Error> {
     // Get or create the destination's associated token account
-    let destination_ata = get_associated_token_address(destination, mint);
+    let token_mint = TokenMint::fetch(rpc_client, mint)?;
+    let destination_ata = token_mint.associated_token_address(destination);
     
     let mut instructions = Vec::new();
     
@@ -97,14 +328,14 @@ pub fn mint_tokens_to(
             &payer.pubkey(),
             destination,
             mint,
-            &spl_token::id(),
+            &token_mint.program_id,
         );
         instructions.push(create_ata_instruction);
     }
     
     // Create mint instruction
     let mint_instruction = mint_to_checked(
-        &spl_token::id(),
+        &token_mint.program_id,
         mint,
         &destination_ata,
         &mint_authority.pubkey(),
@@ -115,6 +346,7 @@ pub fn mint_tokens_to(
     .map_err(|e| Error::TokenMintFailed(format!("Failed to create mint instruction: {}", e)))?;
     
     instructions.push(mint_instruction);
+    let instructions = compute_budget.apply(rpc_client, instructions, &payer.pubkey())?;
     
     // Create and send transaction
     let recent_blockhash = rpc_client
@@ -136,6 +368,7 @@ pub fn mint_tokens_to(
 }
 
 /// Transfer tokens from one wallet to another
+#[allow(clippy::too_many_arguments)]
 pub fn transfer_tokens(
     rpc_client: &RpcClient,
     payer: &Keypair,
@@ -143,41 +376,26 @@ pub fn transfer_tokens(
     from_wallet: &Keypair,
     to_wallet: &Pubkey,
     amount: u64,
+    memo: Option<String>,
+    compute_budget: &ComputeBudget,
 ) -> Result<Signature, Error> {
-    // Get associated token addresses
-    let from_ata = get_associated_token_address(&from_wallet.pubkey(), mint);
-    let to_ata = get_associated_token_address(to_wallet, mint);
-    
-    let mut instructions = Vec::new();
+    let token_mint = TokenMint::fetch(rpc_client, mint)?;
     
     // Check if sender ATA exists
-    if rpc_client.get_account(&from_ata).is_err() {
+    if rpc_client.get_account(&token_mint.associated_token_address(&from_wallet.pubkey())).is_err() {
         return Err(Error::TokenAccountNotFound);
     }
     
-    // Create destination ATA if it doesn't exist
-    if rpc_client.get_account(&to_ata).is_err() {
-        let create_ata_instruction = create_associated_token_account(
-            &payer.pubkey(),
-            to_wallet,
-            mint,
-            &spl_token::id(),
-        );
-        instructions.push(create_ata_instruction);
-    }
-    
-    // Create transfer instruction
-    let transfer_instruction = transfer(
-        &spl_token::id(),
-        &from_ata,
-        &to_ata,
+    // Create the destination ATA if it doesn't exist, then transfer
+    let instructions = token_mint.transfer_instructions(
+        rpc_client,
         &from_wallet.pubkey(),
-        &[&from_wallet.pubkey()],
+        to_wallet,
+        &payer.pubkey(),
         amount,
-    )
-    .map_err(|e| Error::TokenTransferFailed(format!("Failed to create transfer instruction: {}", e)))?;
-    
-    instructions.push(transfer_instruction);
+        memo,
+    )?;
+    let instructions = compute_budget.apply(rpc_client, instructions, &payer.pubkey())?;
     
     // Create and send transaction
     let recent_blockhash = rpc_client
@@ -204,7 +422,7 @@ pub fn get_token_balance(
     wallet: &Pubkey,
     mint: &Pubkey,
 ) -> Result<u64, Error> {
-    let ata = get_associated_token_address(wallet, mint);
+    let ata = TokenMint::fetch(rpc_client, mint)?.associated_token_address(wallet);
     
     match rpc_client.get_token_account_balance(&ata) {
         Ok(balance) => Ok(balance.amount.parse().unwrap_or(0)),
@@ -212,27 +430,85 @@ pub fn get_token_balance(
     }
 }
 
-/// Create an unsigned SOL transfer transaction (for MPC signing)
-pub fn create_unsigned_sol_transaction(
-    amount: f64, 
-    to: &Pubkey, 
-    memo: Option<String>, 
-    payer: &Pubkey
-) -> Transaction {
-    let amount = native_token::sol_to_lamports(amount);
-    let transfer_ins = system_instruction::transfer(payer, to, amount);
-    let msg = match memo {
-        None => Message::new(&[transfer_ins], Some(payer)),
-        Some(memo) => {
-            let memo_ins = Instruction { 
-                program_id: spl_memo::id(), 
-                accounts: Vec::new(), 
-                data: memo.into_bytes() 
-            };
-            Message::new(&[transfer_ins, memo_ins], Some(payer))
-        }
-    };
-    Transaction::new_unsigned(msg)
+/// Prepend the `advance_nonce_account` instruction if the transaction uses a durable nonce,
+/// the runtime requires it to be the first instruction
+pub fn with_advance_nonce(
+    mut instructions: Vec<Instruction>,
+    nonce_account: Option<&Pubkey>,
+    authority: &Pubkey,
+) -> Vec<Instruction> {
+    if let Some(nonce_account) = nonce_account {
+        instructions.insert(0, system_instruction::advance_nonce_account(nonce_account, authority));
+    }
+    instructions
+}
+
+/// Create a durable nonce account, `authority` (usually the aggregated key) is the only one who can advance it
+pub fn create_nonce_account(
+    rpc_client: &RpcClient,
+    payer: &Keypair,
+    nonce_keypair: &Keypair,
+    authority: &Pubkey,
+    compute_budget: &ComputeBudget,
+) -> Result<Signature, Error> {
+    let rent = rpc_client
+        .get_minimum_balance_for_rent_exemption(NonceState::size())
+        .map_err(Error::RecentHashFailed)?;
+    let instructions = system_instruction::create_nonce_account(
+        &payer.pubkey(),
+        &nonce_keypair.pubkey(),
+        authority,
+        rent,
+    );
+    let instructions = compute_budget.apply(rpc_client, instructions, &payer.pubkey())?;
+
+    let recent_blockhash = rpc_client
+        .get_latest_blockhash()
+        .map_err(Error::RecentHashFailed)?;
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&payer.pubkey()),
+        &[payer, nonce_keypair],
+        recent_blockhash,
+    );
+
+    rpc_client
+        .send_and_confirm_transaction(&transaction)
+        .map_err(|e| Error::NonceAccountError(format!("Failed to create nonce account: {}", e)))
+}
+
+/// Read the nonce value currently stored in a durable nonce account, together with its authority
+pub fn get_nonce_value(rpc_client: &RpcClient, nonce_account: &Pubkey) -> Result<(Hash, Pubkey), Error> {
+    let account = nonce_utils::get_account(rpc_client, nonce_account)
+        .map_err(|e| Error::NonceAccountError(format!("Failed to fetch {}: {}", nonce_account, e)))?;
+    let data = nonce_utils::data_from_account(&account)
+        .map_err(|e| Error::NonceAccountError(format!("{} is not a nonce account: {}", nonce_account, e)))?;
+    Ok((data.blockhash(), data.authority))
 }
 
- 
\ No newline at end of file
+/// Make sure a durable nonce still holds the value the transaction was signed with
+pub fn check_nonce_value(rpc_client: &RpcClient, nonce_account: &Pubkey, expected: &Hash) -> Result<(), Error> {
+    let (current, _) = get_nonce_value(rpc_client, nonce_account)?;
+    if &current != expected {
+        return Err(Error::NonceAccountError(format!(
+            "{} holds nonce {}, but the transaction was signed with {}, it was advanced or used already",
+            nonce_account, current, expected
+        )));
+    }
+    Ok(())
+}
+
+/// Fetch address lookup tables to compile v0 messages with
+pub fn get_lookup_tables(rpc_client: &RpcClient, addresses: &[Pubkey]) -> Result<Vec<AddressLookupTableAccount>, Error> {
+    addresses
+        .iter()
+        .map(|address| {
+            let account = rpc_client
+                .get_account(address)
+                .map_err(|e| Error::LookupTableError(format!("Failed to fetch {}: {}", address, e)))?;
+            let table = AddressLookupTable::deserialize(&account.data)
+                .map_err(|e| Error::LookupTableError(format!("{} is not an address lookup table: {}", address, e)))?;
+            Ok(AddressLookupTableAccount { key: *address, addresses: table.addresses.to_vec() })
+        })
+        .collect()
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::error::Error;
+use crate::serialization::{Envelope, TransportHello};
+
+/// Domain separator of the signature on an ephemeral transport key
+const HELLO_DOMAIN: &[u8] = b"solana-mpc-tokens/transport/hello";
+/// Domain separator of the pairwise key derivation
+const KEY_DOMAIN: &[u8] = b"solana-mpc-tokens/transport/key";
+
+/// Round of the first messages (`AggMessage1`)
+pub const ROUND_FIRST_MESSAGE: u8 = 1;
+/// Round of the partial signatures
+pub const ROUND_PARTIAL_SIGNATURE: u8 = 2;
+
+fn hello_bytes(sender: &Pubkey, session_id: &Hash, ephemeral: &Point<Ed25519>) -> Vec<u8> {
+    [HELLO_DOMAIN, sender.as_ref(), session_id.as_ref(), &*ephemeral.to_bytes(true)].concat()
+}
+
+/// Check that a hello was signed by its sender's identity key for `session_id`
+pub fn verify_hello(hello: &TransportHello, session_id: &Hash) -> Result<(), Error> {
+    if &hello.session_id != session_id {
+        return Err(Error::TransportError(format!(
+            "transport key of {} belongs to session {}, expected {}",
+            hello.sender, hello.session_id, session_id
+        )));
+    }
+    if !hello.signature.verify(hello.sender.as_ref(), &hello_bytes(&hello.sender, &hello.session_id, &hello.ephemeral)) {
+        return Err(Error::TransportError(format!("transport key of {} is not signed by its identity", hello.sender)));
+    }
+    Ok(())
+}
+
+/// Check that an envelope was signed by its sender's identity key for `session_id`
+pub fn verify_envelope(envelope: &Envelope, session_id: &Hash) -> Result<(), Error> {
+    if &envelope.session_id != session_id {
+        return Err(Error::TransportError(format!(
+            "message from {} belongs to session {}, expected {}",
+            envelope.sender, envelope.session_id, session_id
+        )));
+    }
+    if !envelope.signature.verify(envelope.sender.as_ref(), &envelope.signed_bytes()) {
+        return Err(Error::TransportError(format!("message from {} is not signed by its identity", envelope.sender)));
+    }
+    Ok(())
+}
+
+/// End-to-end channels between the parties of one session.
+/// Every party signs a fresh ephemeral key with its identity, and every pair of parties derives its own key
+/// from their ephemeral keys. Round messages are encrypted for each recipient and signed by their sender,
+/// so whoever relays them can neither read nor alter them.
+pub struct Transport {
+    identity: Keypair,
+    session_id: Hash,
+    ephemeral_secret: Scalar<Ed25519>,
+    hello: TransportHello,
+    peer_keys: HashMap<Pubkey, [u8; 32]>,
+}
+
+impl Transport {
+    /// Generate the ephemeral key of `identity` for `session_id`
+    pub fn new(identity: &Keypair, session_id: Hash) -> Self {
+        let ephemeral_secret = Scalar::random();
+        let ephemeral = Point::generator() * &ephemeral_secret;
+        let signature = identity.sign_message(&hello_bytes(&identity.pubkey(), &session_id, &ephemeral));
+        Self {
+            identity: identity.insecure_clone(),
+            session_id,
+            ephemeral_secret,
+            hello: TransportHello { sender: identity.pubkey(), session_id, ephemeral, signature },
+            peer_keys: HashMap::new(),
+        }
+    }
+
+    /// Our signed ephemeral key, to be sent to every other party
+    pub fn hello(&self) -> &TransportHello {
+        &self.hello
+    }
+
+    /// Derive a key with every other party in `keys` from their hellos, which must all be present and valid
+    pub fn add_peers(&mut self, hellos: &[TransportHello], keys: &[Pubkey]) -> Result<(), Error> {
+        let me = self.identity.pubkey();
+        for key in keys.iter().filter(|key| **key != me) {
+            let hello = match hellos.iter().filter(|h| &h.sender == key).collect::<Vec<_>>().as_slice() {
+                [hello] => *hello,
+                [] => return Err(Error::TransportError(format!("missing transport key of {}", key))),
+                _ => return Err(Error::TransportError(format!("more than one transport key of {}", key))),
+            };
+            verify_hello(hello, &self.session_id)?;
+            self.peer_keys.insert(*key, self.derive_key(hello));
+        }
+        if let Some(ours) = hellos.iter().find(|h| h.sender == me) {
+            if ours != &self.hello {
+                return Err(Error::TransportError("our transport key was replaced by the relay".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// The key shared with the sender of `hello`, the same on both sides
+    fn derive_key(&self, hello: &TransportHello) -> [u8; 32] {
+        let shared = (&hello.ephemeral * &self.ephemeral_secret).to_bytes(true);
+        let (first, second) = if self.hello.sender.to_bytes() < hello.sender.to_bytes() {
+            (&self.hello, hello)
+        } else {
+            (hello, &self.hello)
+        };
+        let info = [
+            KEY_DOMAIN,
+            first.sender.as_ref(),
+            &*first.ephemeral.to_bytes(true),
+            second.sender.as_ref(),
+            &*second.ephemeral.to_bytes(true),
+        ]
+        .concat();
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(self.session_id.as_ref()), &shared)
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        key
+    }
+
+    /// Each key encrypts at most one message per round and direction, so this nonce is never reused
+    fn nonce(sender: &Pubkey, recipient: &Pubkey, round: u8) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[0] = round;
+        nonce[1] = u8::from(sender.to_bytes() > recipient.to_bytes());
+        nonce
+    }
+
+    fn aad(sender: &Pubkey, recipient: &Pubkey, session_id: &Hash, round: u8) -> Vec<u8> {
+        [sender.as_ref(), recipient.as_ref(), session_id.as_ref(), &[round]].concat()
+    }
+
+    fn cipher(&self, peer: &Pubkey) -> Result<ChaCha20Poly1305, Error> {
+        let key = self
+            .peer_keys
+            .get(peer)
+            .ok_or_else(|| Error::TransportError(format!("no transport key for {}", peer)))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(key)))
+    }
+
+    /// Encrypt a round message for `recipient` and sign the result
+    pub fn seal(&self, recipient: &Pubkey, round: u8, message: &[u8]) -> Result<Envelope, Error> {
+        let sender = self.identity.pubkey();
+        let nonce = Self::nonce(&sender, recipient, round);
+        let aad = Self::aad(&sender, recipient, &self.session_id, round);
+        let ciphertext = self
+            .cipher(recipient)?
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: message, aad: &aad })
+            .map_err(|_| Error::TransportError("encryption failed".to_string()))?;
+
+        let mut envelope = Envelope {
+            sender,
+            recipient: *recipient,
+            session_id: self.session_id,
+            round,
+            ciphertext,
+            signature: Default::default(),
+        };
+        envelope.signature = self.identity.sign_message(&envelope.signed_bytes());
+        Ok(envelope)
+    }
+
+    /// Check and decrypt a round message addressed to us
+    pub fn open(&self, envelope: &Envelope, round: u8) -> Result<Vec<u8>, Error> {
+        let me = self.identity.pubkey();
+        if envelope.recipient != me || envelope.round != round {
+            return Err(Error::TransportError(format!(
+                "message from {} is for {} in round {}, expected {} in round {}",
+                envelope.sender, envelope.recipient, envelope.round, me, round
+            )));
+        }
+        verify_envelope(envelope, &self.session_id)?;
+        let nonce = Self::nonce(&envelope.sender, &me, round);
+        let aad = Self::aad(&envelope.sender, &me, &self.session_id, round);
+        self.cipher(&envelope.sender)?
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &envelope.ciphertext, aad: &aad })
+            .map_err(|_| Error::TransportError(format!("message from {} failed to decrypt", envelope.sender)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_the_recipient_reads_untampered_messages() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+        let session_id = Hash::new_unique();
+        let mut transports: Vec<Transport> = keypairs.iter().map(|k| Transport::new(k, session_id)).collect();
+        let hellos: Vec<TransportHello> = transports.iter().map(|t| t.hello().clone()).collect();
+        for transport in transports.iter_mut() {
+            transport.add_peers(&hellos, &keys).unwrap();
+        }
+
+        let envelope = transports[0].seal(&keys[1], ROUND_FIRST_MESSAGE, b"first message").unwrap();
+        assert_eq!(transports[1].open(&envelope, ROUND_FIRST_MESSAGE).unwrap(), b"first message");
+
+        // Not addressed to the third party, and not valid in another round
+        assert!(transports[2].open(&envelope, ROUND_FIRST_MESSAGE).is_err());
+        assert!(transports[1].open(&envelope, ROUND_PARTIAL_SIGNATURE).is_err());
+
+        // A relay flipping a bit breaks the signature
+        let mut tampered = envelope.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(transports[1].open(&tampered, ROUND_FIRST_MESSAGE).is_err());
+
+        // A forged hello isn't accepted
+        let mut forged = hellos.clone();
+        forged[0].ephemeral = Point::generator() * Scalar::random();
+        assert!(Transport::new(&keypairs[1], session_id).add_peers(&forged, &keys).is_err());
+    }
+}
@@ -1,28 +1,232 @@
 #![allow(non_snake_case)]
 
+use curv::arithmetic::Converter;
 use curv::elliptic::curves::{Ed25519, Point, Scalar};
+use curv::BigInt;
 use multi_party_eddsa::protocols::{musig2, ExpandedKeyPair};
+use sha2::{Digest, Sha512};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{Keypair, Signer, Signature};
-use solana_sdk::hash::Hash;
-use solana_sdk::transaction::Transaction;
-use solana_sdk::message::Message;
-use solana_client::rpc_client::RpcClient;
-use spl_associated_token_account;
-use spl_token;
+use solana_sdk::hash::{hash, Hash};
+use solana_sdk::transaction::VersionedTransaction;
 
 use crate::error::Error;
-use crate::serialization::{AggMessage1, SecretAggStepOne, PartialSignature};
+use crate::frost::lagrange_coefficient;
+use crate::nonce_ledger::{nonce_id, NonceLedger};
+use crate::serialization::{AggMessage1, SecretAggStepOne, PartialSignature, ThresholdKeyShare, TransactionTemplate};
 
 
 /// Helper function to convert aggregated key to Solana pubkey
 pub fn agg_key_to_pubkey(agg_key: &musig2::PublicKeyAgg) -> Pubkey {
-    let agg_bytes = agg_key.agg_public_key.to_bytes(true);
+    point_to_pubkey(&agg_key.agg_public_key)
+}
+
+/// Helper function to convert an Ed25519 point to a Solana pubkey
+pub fn point_to_pubkey(point: &Point<Ed25519>) -> Pubkey {
     let mut pubkey_bytes = [0u8; 32];
-    pubkey_bytes.copy_from_slice(&agg_bytes);
+    pubkey_bytes.copy_from_slice(&point.to_bytes(true));
     Pubkey::from(pubkey_bytes)
 }
 
+/// Hash the given parts with SHA-512 and reduce the little-endian digest modulo the group order
+pub fn hash_to_scalar(parts: &[&[u8]]) -> Scalar<Ed25519> {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    // Ed25519 interprets digests as little-endian, curv's BigInt expects big-endian
+    let mut digest = hasher.finalize().to_vec();
+    digest.reverse();
+    Scalar::from_bigint(&BigInt::from_bytes(&digest))
+}
+
+/// Compute the Ed25519 challenge `k = H(R || A || M)` exactly as a verifier does
+pub fn ed25519_challenge(R: &Point<Ed25519>, public_key: &Point<Ed25519>, message: &[u8]) -> Scalar<Ed25519> {
+    hash_to_scalar(&[&R.to_bytes(true), &public_key.to_bytes(true), message])
+}
+
+/// Our share of the signature on `message`, computed by the MuSig2 library.
+/// `other_nonces` are the public nonces of every other party.
+fn partial_sign(
+    extended_keypair: &ExpandedKeyPair,
+    private_nonces: musig2::PrivatePartialNonces,
+    public_nonces: musig2::PublicPartialNonces,
+    other_nonces: &[[Point<Ed25519>; 2]],
+    aggregated_pubkey: &musig2::PublicKeyAgg,
+    message: &[u8],
+) -> Signature {
+    let sig = musig2::partial_sign(
+        other_nonces,
+        private_nonces,
+        public_nonces,
+        aggregated_pubkey,
+        extended_keypair,
+        message,
+    );
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&*sig.R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&sig.my_partial_s.to_bytes());
+    Signature::from(sig_bytes)
+}
+
+/// The signature's `R = R_1 + b * R_2` and the nonce coefficient `b` the MuSig2 library signs with,
+/// `nonces` are the public nonces of every party.
+/// The library doesn't expose `b`, so it's taken from two of its partial signatures that only differ
+/// by their private nonces: `(0, 1)` and `(0, 0)` give shares exactly `b` apart.
+pub fn effective_nonce(
+    aggregated_pubkey: &musig2::PublicKeyAgg,
+    nonces: &[[Point<Ed25519>; 2]],
+    message: &[u8],
+) -> (Point<Ed25519>, Scalar<Ed25519>) {
+    let extended_keypair = ExpandedKeyPair::create_from_private_key([1u8; 32]);
+    let share = |r: [Scalar<Ed25519>; 2]| {
+        musig2::partial_sign(
+            &nonces[1..],
+            musig2::PrivatePartialNonces { r },
+            musig2::PublicPartialNonces { R: nonces[0].clone() },
+            aggregated_pubkey,
+            &extended_keypair,
+            message,
+        )
+    };
+    let with_b = share([Scalar::zero(), Scalar::from(1u64)]);
+    let without_b = share([Scalar::zero(), Scalar::zero()]);
+    (with_b.R, with_b.my_partial_s - without_b.my_partial_s)
+}
+
+/// Check one party's share of the signature against its key and nonces, `s_i * G == R_i1 + b * R_i2 + c * a_i * X_i`
+pub fn verify_partial_signature(
+    signature: &PartialSignature,
+    signer: &Pubkey,
+    signer_nonces: &[Point<Ed25519>; 2],
+    keys: &[Pubkey],
+    all_nonces: &[[Point<Ed25519>; 2]],
+    message: &[u8],
+) -> Result<bool, Error> {
+    let aggkey = key_agg(keys.to_vec(), Some(*signer))?;
+    let signer_point = Point::<Ed25519>::from_bytes(&signer.to_bytes())
+        .map_err(|e| Error::SerializationError(format!("Failed to deserialize point from key: {}", e)))?;
+    Ok(check_partial_signature(signature, &signer_point, signer_nonces, &aggkey, all_nonces, message))
+}
+
+/// Check a share of the signature against the signer's public key and its coefficient in `aggkey`
+fn check_partial_signature(
+    signature: &PartialSignature,
+    signer_point: &Point<Ed25519>,
+    signer_nonces: &[Point<Ed25519>; 2],
+    aggkey: &musig2::PublicKeyAgg,
+    all_nonces: &[[Point<Ed25519>; 2]],
+    message: &[u8],
+) -> bool {
+    // A share that doesn't even decode is as invalid as a wrong one
+    let (R, s) = match (
+        PartialSignature::deserialize_r(&signature.signature.as_ref()[..32]),
+        PartialSignature::deserialize_s(&signature.signature.as_ref()[32..]),
+    ) {
+        (Ok(R), Ok(s)) => (R, s),
+        _ => return false,
+    };
+
+    let (expected_R, b) = effective_nonce(aggkey, all_nonces, message);
+    if R != expected_R {
+        return false;
+    }
+    let c = ed25519_challenge(&R, &aggkey.agg_public_key, message);
+    let expected = &signer_nonces[0] + &signer_nonces[1] * &b + signer_point * (c * &aggkey.musig_coefficient);
+    Point::generator() * s == expected
+}
+
+/// Verify every party's partial signature and add them up into the signature on `message`.
+/// Signatures may come in any order, they are matched to `keys` by sender. Duplicates, strangers and
+/// missing parties are rejected, and if any share is invalid the parties that produced them are reported.
+pub fn aggregate_partial_signatures(
+    keys: &[Pubkey],
+    first_messages: &[AggMessage1],
+    signatures: &[PartialSignature],
+    message: &[u8],
+) -> Result<Signature, Error> {
+    aggregate_verified(keys, first_messages, signatures, message, |position, signature, all_nonces| {
+        verify_partial_signature(signature, &keys[position], &all_nonces[position], keys, all_nonces, message)
+    })
+}
+
+/// Match the partial signatures to `keys` and add them up once `verify` accepts every one of them.
+/// `verify` gets the position of the signer in `keys`, its share and the nonces of every key in order.
+fn aggregate_verified(
+    keys: &[Pubkey],
+    first_messages: &[AggMessage1],
+    signatures: &[PartialSignature],
+    message: &[u8],
+    verify: impl Fn(usize, &PartialSignature, &[[Point<Ed25519>; 2]]) -> Result<bool, Error>,
+) -> Result<Signature, Error> {
+    if first_messages.len() != keys.len() {
+        return Err(Error::SessionMismatch(format!(
+            "expected first messages from all {} parties, found {}",
+            keys.len(),
+            first_messages.len()
+        )));
+    }
+
+    // Everyone's nonces must belong to one session, for this message
+    let digest = hash(message);
+    let session_id = first_messages[0].session_id;
+    let mut signer_nonces = Vec::with_capacity(keys.len());
+    for key in keys {
+        let msg = match first_messages.iter().filter(|m| &m.sender == key).collect::<Vec<_>>().as_slice() {
+            [msg] => *msg,
+            [] => return Err(Error::SessionMismatch(format!("missing first message from {}", key))),
+            _ => return Err(Error::SessionMismatch(format!("more than one first message from {}", key))),
+        };
+        if msg.session_id != session_id || msg.message_digest != digest {
+            return Err(Error::SessionMismatch(format!(
+                "first message from {} belongs to a different session or message",
+                key
+            )));
+        }
+        signer_nonces.push(msg.public_nonces.R.clone());
+    }
+
+    // Match every share to its sender
+    for (i, signature) in signatures.iter().enumerate() {
+        if !keys.contains(&signature.sender) {
+            return Err(Error::SessionMismatch(format!(
+                "partial signature from {} who is not in the keys",
+                signature.sender
+            )));
+        }
+        if signatures[..i].iter().any(|s| s.sender == signature.sender) {
+            return Err(Error::SessionMismatch(format!("more than one partial signature from {}", signature.sender)));
+        }
+        if signature.session_id != session_id {
+            return Err(Error::SessionMismatch(format!(
+                "partial signature from {} belongs to session {}, expected {}",
+                signature.sender, signature.session_id, session_id
+            )));
+        }
+    }
+    let missing: Vec<Pubkey> = keys
+        .iter()
+        .filter(|key| !signatures.iter().any(|s| &s.sender == *key))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::MissingParties(missing));
+    }
+
+    let mut misbehaving = Vec::new();
+    for (position, key) in keys.iter().enumerate() {
+        let signature = signatures.iter().find(|s| &s.sender == key).ok_or(Error::KeyPairIsNotInKeys)?;
+        if !verify(position, signature, &signer_nonces)? {
+            misbehaving.push(*key);
+        }
+    }
+    if !misbehaving.is_empty() {
+        return Err(Error::MisbehavingParties(misbehaving));
+    }
+
+    Ok(PartialSignature::aggregate_signatures(signatures)?)
+}
+
 /// Create the aggregate public key from a list of public keys
 /// Pass key=None if you don't care about the coefficient (typically for key aggregation only)
 /// Pass key=Some(pubkey) if you want to get the coefficient for a specific key in the aggregation
@@ -45,292 +249,497 @@ pub fn key_agg(keys: Vec<Pubkey>, key: Option<Pubkey>) -> Result<musig2::PublicK
 
 /// Generate Message1 which contains nonce, public nonce, and commitment to nonces
 /// This is the first step in the MPC signing process
-pub fn step_one(keypair: Keypair) -> (AggMessage1, SecretAggStepOne) {
+/// The nonce pair is recorded in the party's nonce ledger so step two can refuse to use it twice,
+/// and bound to `session_id` and `message_digest` so it can only sign that one transaction
+pub fn step_one(
+    keypair: Keypair,
+    session_id: Hash,
+    message_digest: Hash,
+    ledger: &mut NonceLedger,
+) -> Result<(AggMessage1, SecretAggStepOne), Error> {
     let extended_keypair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
-
-    println!("private key: {:?}", keypair.secret().to_bytes());
-    println!("extended_keypair: {:?}", extended_keypair);
     let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&extended_keypair, None);
+    ledger.record_generated(&nonce_id(&public_nonces.R))?;
+
+    Ok((
+        AggMessage1 { sender: keypair.pubkey(), public_nonces: public_nonces.clone(), session_id, message_digest },
+        SecretAggStepOne { private_nonces, public_nonces, signer: keypair.pubkey(), session_id, message_digest },
+    ))
+}
 
-    (
-        AggMessage1 { sender: keypair.pubkey(), public_nonces: public_nonces.clone() },
-        SecretAggStepOne { private_nonces, public_nonces },
-    )
+/// Make sure our round one state and everyone else's first message belong to the same session,
+/// were generated for the transaction we're about to sign, and come from the other parties in `keys`
+fn check_session(
+    signer: &Pubkey,
+    keys: &[Pubkey],
+    first_messages: &[AggMessage1],
+    secret_state: &SecretAggStepOne,
+    message_digest: &Hash,
+) -> Result<(), Error> {
+    if &secret_state.signer != signer {
+        return Err(Error::SessionMismatch(format!(
+            "the secret state was generated by {}, not by {}",
+            secret_state.signer, signer
+        )));
+    }
+    if &secret_state.message_digest != message_digest {
+        return Err(Error::SessionMismatch(format!(
+            "the nonces were generated for message {}, but the transaction is {}",
+            secret_state.message_digest, message_digest
+        )));
+    }
+
+    let mut senders = vec![*signer];
+    for msg in first_messages {
+        if msg.session_id != secret_state.session_id {
+            return Err(Error::SessionMismatch(format!(
+                "first message from {} belongs to session {}, expected {}",
+                msg.sender, msg.session_id, secret_state.session_id
+            )));
+        }
+        if &msg.message_digest != message_digest {
+            return Err(Error::SessionMismatch(format!(
+                "first message from {} was generated for message {}, but the transaction is {}",
+                msg.sender, msg.message_digest, message_digest
+            )));
+        }
+        if !keys.contains(&msg.sender) {
+            return Err(Error::SessionMismatch(format!("first message from {} who is not in the keys", msg.sender)));
+        }
+        if senders.contains(&msg.sender) {
+            return Err(Error::SessionMismatch(format!("more than one first message from {}", msg.sender)));
+        }
+        senders.push(msg.sender);
+    }
+    if senders.len() != keys.len() {
+        return Err(Error::SessionMismatch(format!(
+            "expected first messages from {} other parties, found {}",
+            keys.len() - 1,
+            first_messages.len()
+        )));
+    }
+    Ok(())
 }
 
-/// Generate partial signature for token transfer (Step 2 of MPC)
-#[allow(clippy::too_many_arguments)]
-pub fn step_two_token(
+/// Generate our partial signature on the transaction template (Step 2 of MPC)
+/// Every party signs the template's exact message bytes, which must be paid for by the aggregated key
+pub fn step_two(
     keypair: Keypair,
-    mint: Pubkey,
-    amount: u64,
-    decimals: u8,
-    to: Pubkey,
-    recent_block_hash: Hash,
+    template: &TransactionTemplate,
     keys: Vec<Pubkey>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
-    rpc_client: &RpcClient,
+    ledger: &mut NonceLedger,
 ) -> Result<PartialSignature, Error> {
-    let other_nonces: Vec<_> = first_messages.into_iter().map(|msg1| msg1.public_nonces.R).collect();
-
     // Generate the aggregate key together with the coefficient of the current keypair
-    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
+    let aggkey = key_agg(keys.clone(), Some(keypair.pubkey()))?;
     let aggpubkey = agg_key_to_pubkey(&aggkey);
-    let extended_keypair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    if template.fee_payer()? != aggpubkey {
+        return Err(Error::SessionMismatch(format!(
+            "the transaction template is paid by {}, not by the aggregated key {}",
+            template.fee_payer()?,
+            aggpubkey
+        )));
+    }
 
-    // Create the unsigned token transaction
-    let mut tx = create_unsigned_token_transaction(mint, amount, decimals, &to, &aggpubkey, rpc_client)?;
+    // Only sign the session and transaction the nonces were generated for
+    check_session(&keypair.pubkey(), &keys, &first_messages, &secret_state, &template.digest())?;
+    let other_nonces: Vec<_> = first_messages.into_iter().map(|msg1| msg1.public_nonces.R).collect();
 
-    let signer = PartialSigner {
-        signer_private_nonce: secret_state.private_nonces,
-        signer_public_nonce: secret_state.public_nonces,
-        other_nonces,
-        extended_keypair,
-        aggregated_pubkey: aggkey,
-    };
-    
-    // Sign the transaction using a custom `PartialSigner`, this is required to comply with Solana's API.
-    tx.sign(&[&signer], recent_block_hash);
-    let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
-}
+    // Burn the nonces before they are used, a crash after this point can't lead to a second signature
+    ledger.consume(&nonce_id(&secret_state.public_nonces.R), &template.digest())?;
 
-/// Create an unsigned token transfer transaction
-pub fn create_unsigned_token_transaction(
-    mint: Pubkey,
-    amount: u64,
-    decimals: u8,
-    to: &Pubkey,
-    payer: &Pubkey,
-    _rpc_client: &RpcClient,
-) -> Result<Transaction, Error> {
-    // Calculate ATAs without checking existence
-    let source_ata = spl_associated_token_account::get_associated_token_address(payer, &mint);
-    let destination_ata = spl_associated_token_account::get_associated_token_address(to, &mint);
-    
-    // Create only the transfer instruction
-    let transfer_ix = spl_token::instruction::transfer_checked(
-        &spl_token::id(),
-        &source_ata,
-        &mint,
-        &destination_ata,
-        payer,      
-        &[],        
-        amount,
-        decimals,
-    ).map_err(|e| Error::TokenTransferFailed(format!("Failed to create transfer instruction: {}", e)))?;
-    
-    // Create message and transaction
-    let msg = Message::new(&[transfer_ix], Some(payer));
-    Ok(Transaction::new_unsigned(msg))
+    let extended_keypair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    let signature = partial_sign(
+        &extended_keypair,
+        secret_state.private_nonces,
+        secret_state.public_nonces,
+        &other_nonces,
+        &aggkey,
+        &template.message,
+    );
+    Ok(PartialSignature { sender: keypair.pubkey(), session_id: secret_state.session_id, signature })
 }
 
-/// Aggregate partial signatures and create a final signed token transfer transaction (Step 3 of MPC)
-#[allow(clippy::too_many_arguments)]
-pub fn sign_and_broadcast_token(
-    mint: Pubkey,
-    amount: u64,
-    decimals: u8,
-    to: Pubkey,
-    recent_block_hash: Hash,
+/// Aggregate partial signatures into the signed transaction of the template (Step 3 of MPC)
+pub fn sign_and_broadcast(
+    template: &TransactionTemplate,
     keys: Vec<Pubkey>,
+    first_messages: Vec<AggMessage1>,
     signatures: Vec<PartialSignature>,
-    rpc_client: &RpcClient,
-) -> Result<Transaction, Error> {
-    let aggkey = key_agg(keys.clone(), None)?;
-    let aggpubkey = agg_key_to_pubkey(&aggkey);
+) -> Result<VersionedTransaction, Error> {
+    // Verify every party's share and aggregate them
+    let sig = aggregate_partial_signatures(&keys, &first_messages, &signatures, &template.message)?;
 
-    // Calculate ATAs
-    let source_ata = spl_associated_token_account::get_associated_token_address(&aggpubkey, &mint);
-    let destination_ata = spl_associated_token_account::get_associated_token_address(&to, &mint);
-
-    // Prepare instructions
-    let mut instructions = Vec::new();
-
-    // Check and create destination ATA if needed
-    if rpc_client.get_account(&destination_ata).is_err() {
-        let create_ata_ix = spl_associated_token_account::instruction::create_associated_token_account(
-            &aggpubkey,  // fee payer
-            &to,         // wallet owner
-            &mint,       // mint
-            &spl_token::id(),
-        );
-        instructions.push(create_ata_ix);
-    }
+    // The final transaction is the template with the signature, verified against the aggregated key
+    template.to_transaction(sig)
+}
 
-    // Add transfer instruction
-    let transfer_ix = spl_token::instruction::transfer_checked(
-        &spl_token::id(),
-        &source_ata,
-        &mint,
-        &destination_ata,
-        &aggpubkey,
-        &[],
-        amount,
-        decimals,
-    ).map_err(|e| Error::TokenTransferFailed(format!("Failed to create transfer instruction: {}", e)))?;
-    instructions.push(transfer_ix);
-
-    // Create final transaction with all instructions
-    let mut final_tx = Transaction::new_with_payer(
-        &instructions,
-        Some(&aggpubkey)
-    );
-    final_tx.message.recent_blockhash = recent_block_hash;
+/// The address a DKG or dealer key share signs as in the MuSig2 rounds, the address of its verifying share
+pub fn share_signer(key_share: &ThresholdKeyShare, index: u16) -> Pubkey {
+    point_to_pubkey(&key_share.verifying_share(index))
+}
 
-    // Aggregate signatures
-    let sig = PartialSignature::aggregate_signatures(&signatures)?;
+/// The MuSig2 view of a threshold group key for the quorum `signers`: the group key takes the place of the
+/// aggregated key and the Lagrange coefficient of `index` the place of its MuSig2 coefficient
+pub fn share_key_agg(
+    key_share: &ThresholdKeyShare,
+    signers: &[u16],
+    index: u16,
+) -> Result<musig2::PublicKeyAgg, Error> {
+    let mut sorted = signers.to_vec();
+    sorted.sort_unstable();
+    if sorted.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(Error::InvalidThreshold("Duplicate participant in the signers".to_string()));
+    }
+    if signers.len() < usize::from(key_share.threshold) {
+        return Err(Error::InvalidThreshold(format!(
+            "{} signers are not enough, the threshold is {}",
+            signers.len(),
+            key_share.threshold
+        )));
+    }
+    if !signers.contains(&index) {
+        return Err(Error::KeyPairIsNotInKeys);
+    }
+    Ok(musig2::PublicKeyAgg {
+        agg_public_key: key_share.group_public_key().clone(),
+        musig_coefficient: lagrange_coefficient(index, signers)?,
+    })
+}
 
-    // Insert the signature
-    final_tx.signatures = vec![sig];
+/// Generate the first message of a key share holder, see `step_one`
+/// The share has no seed to derive nonces from, so they are sampled at random
+pub fn step_one_with_share(
+    key_share: &ThresholdKeyShare,
+    session_id: Hash,
+    message_digest: Hash,
+    ledger: &mut NonceLedger,
+) -> Result<(AggMessage1, SecretAggStepOne), Error> {
+    let private_nonces = musig2::PrivatePartialNonces { r: [Scalar::random(), Scalar::random()] };
+    let public_nonces = musig2::PublicPartialNonces {
+        R: [Point::generator() * &private_nonces.r[0], Point::generator() * &private_nonces.r[1]],
+    };
+    ledger.record_generated(&nonce_id(&public_nonces.R))?;
 
-    // Verify the resulting transaction is actually valid
-    if final_tx.verify().is_err() {
-        return Err(Error::InvalidSignature);
-    }
-    
-    Ok(final_tx)
+    let signer = share_signer(key_share, key_share.index);
+    Ok((
+        AggMessage1 { sender: signer, public_nonces: public_nonces.clone(), session_id, message_digest },
+        SecretAggStepOne { private_nonces, public_nonces, signer, session_id, message_digest },
+    ))
 }
 
-/// Generate partial signature for SOL transfer (Step 2 of MPC)
-#[allow(clippy::too_many_arguments)]
-pub fn step_two_sol(
-    keypair: Keypair,
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
-    recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
+/// Generate the partial signature of a key share holder on the transaction template, see `step_two`
+/// `signers` are the indices of the quorum, the template must be paid for by the group key
+pub fn step_two_with_share(
+    key_share: &ThresholdKeyShare,
+    template: &TransactionTemplate,
+    signers: Vec<u16>,
     first_messages: Vec<AggMessage1>,
     secret_state: SecretAggStepOne,
+    ledger: &mut NonceLedger,
 ) -> Result<PartialSignature, Error> {
-    let other_nonces: Vec<_> = first_messages.into_iter().map(|msg1| msg1.public_nonces.R).collect();
+    let aggkey = share_key_agg(key_share, &signers, key_share.index)?;
+    let group_pubkey = key_share.group_pubkey();
+    if template.fee_payer()? != group_pubkey {
+        return Err(Error::SessionMismatch(format!(
+            "the transaction template is paid by {}, not by the group key {}",
+            template.fee_payer()?,
+            group_pubkey
+        )));
+    }
 
-    // Generate the aggregate key together with the coefficient of the current keypair
-    let aggkey = key_agg(keys, Some(keypair.pubkey()))?;
-    let aggpubkey = agg_key_to_pubkey(&aggkey);
-    let extended_keypair = ExpandedKeyPair::create_from_private_key(keypair.secret().to_bytes());
+    // Only sign the session and transaction the nonces were generated for
+    let keys: Vec<Pubkey> = signers.iter().map(|&index| share_signer(key_share, index)).collect();
+    let signer = share_signer(key_share, key_share.index);
+    check_session(&signer, &keys, &first_messages, &secret_state, &template.digest())?;
+    let mut all_nonces = vec![secret_state.public_nonces.R.clone()];
+    all_nonces.extend(first_messages.into_iter().map(|msg1| msg1.public_nonces.R));
 
-    // Create the unsigned SOL transaction
-    let mut tx = crate::token::create_unsigned_sol_transaction(amount, &to, memo, &aggpubkey);
+    // Burn the nonces before they are used, a crash after this point can't lead to a second signature
+    ledger.consume(&nonce_id(&secret_state.public_nonces.R), &template.digest())?;
 
-    let signer = PartialSigner {
-        signer_private_nonce: secret_state.private_nonces,
-        signer_public_nonce: secret_state.public_nonces,
-        other_nonces,
-        extended_keypair,
-        aggregated_pubkey: aggkey,
-    };
-    
-    // Sign the transaction using a custom `PartialSigner`, this is required to comply with Solana's API.
-    tx.sign(&[&signer], recent_block_hash);
-    let sig = tx.signatures[0];
-    Ok(PartialSignature(sig))
+    // `s_i = r_i1 + b * r_i2 + c * lambda_i * x_i`, the same share the MuSig2 library computes for a keypair
+    let (R, b) = effective_nonce(&aggkey, &all_nonces, &template.message);
+    let c = ed25519_challenge(&R, &aggkey.agg_public_key, &template.message);
+    let [r1, r2] = &secret_state.private_nonces.r;
+    let s = r1 + r2 * &b + c * &aggkey.musig_coefficient * &key_share.secret_share;
+
+    let mut sig_bytes = [0u8; 64];
+    sig_bytes[..32].copy_from_slice(&R.to_bytes(true));
+    sig_bytes[32..].copy_from_slice(&s.to_bytes());
+    Ok(PartialSignature { sender: signer, session_id: secret_state.session_id, signature: Signature::from(sig_bytes) })
 }
 
-/// Aggregate partial signatures and create a final signed SOL transfer transaction (Step 3 of MPC)
-pub fn sign_and_broadcast_sol(
-    amount: f64,
-    to: Pubkey,
-    memo: Option<String>,
-    recent_block_hash: Hash,
-    keys: Vec<Pubkey>,
+/// Verify the partial signatures of the key share holders in `signers` and add them up into the signed
+/// transaction of the template, see `sign_and_broadcast`
+pub fn sign_and_broadcast_with_shares(
+    key_share: &ThresholdKeyShare,
+    template: &TransactionTemplate,
+    signers: Vec<u16>,
+    first_messages: Vec<AggMessage1>,
     signatures: Vec<PartialSignature>,
-) -> Result<Transaction, Error> {
-    let aggkey = key_agg(keys.clone(), None)?;
-    let aggpubkey = agg_key_to_pubkey(&aggkey);
+) -> Result<VersionedTransaction, Error> {
+    let keys: Vec<Pubkey> = signers.iter().map(|&index| share_signer(key_share, index)).collect();
+    let sig = aggregate_verified(
+        &keys,
+        &first_messages,
+        &signatures,
+        &template.message,
+        |position, signature, all_nonces| {
+            let index = signers[position];
+            let aggkey = share_key_agg(key_share, &signers, index)?;
+            let signer_point = key_share.verifying_share(index);
+            let nonces = &all_nonces[position];
+            Ok(check_partial_signature(signature, &signer_point, nonces, &aggkey, all_nonces, &template.message))
+        },
+    )?;
+
+    // The group key pays for the template, so it verifies the aggregated signature
+    template.to_transaction(sig)
+}
 
-    // Make sure all the `R`s are the same (first 32 bytes of each signature)
-    if !signatures[1..].iter().map(|s| &s.0.as_ref()[..32]).all(|s| s == &signatures[0].0.as_ref()[..32]) {
-        return Err(Error::SerializationError("Mismatch in number of messages".to_string()));
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{sol_transfer_template, temp_ledger, TempFile};
+    use solana_sdk::signature::{Keypair, Signer};
 
-    let deserialize_R = |s: &[u8]| {
-        Point::from_bytes(s)
-            .map_err(|e| Error::SerializationError(format!("Failed to deserialize R component: {}", e)))
-    };
-    
-    let deserialize_s = |s: &[u8]| {
-        Scalar::from_bytes(s)
-            .map_err(|e| Error::SerializationError(format!("Failed to deserialize s component: {}", e)))
-    };
+    // Run both rounds for all parties, returning the keys, first messages and partial signatures
+    fn sign_with(keypairs: &[Keypair], message: &[u8]) -> (Vec<Pubkey>, Vec<AggMessage1>, Vec<PartialSignature>) {
+        let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+        let session_id = hash(b"test session");
+        let rounds: Vec<_> = keypairs
+            .iter()
+            .map(|k| {
+                let extended_keypair = ExpandedKeyPair::create_from_private_key(k.secret().to_bytes());
+                let (private_nonces, public_nonces) = musig2::generate_partial_nonces(&extended_keypair, None);
+                let msg1 = AggMessage1 { public_nonces, sender: k.pubkey(), session_id, message_digest: hash(message) };
+                (msg1, private_nonces)
+            })
+            .collect();
+        let all_nonces: Vec<_> = rounds.iter().map(|(msg1, _)| msg1.public_nonces.R.clone()).collect();
+
+        let signatures = keypairs
+            .iter()
+            .zip(&rounds)
+            .map(|(k, (msg1, private_nonces))| {
+                let aggkey = key_agg(keys.clone(), Some(k.pubkey())).unwrap();
+                let extended_keypair = ExpandedKeyPair::create_from_private_key(k.secret().to_bytes());
+                let other_nonces: Vec<_> = all_nonces.iter().filter(|n| *n != &msg1.public_nonces.R).cloned().collect();
+                PartialSignature {
+                    sender: k.pubkey(),
+                    session_id,
+                    signature: partial_sign(
+                        &extended_keypair,
+                        private_nonces.clone(),
+                        msg1.public_nonces.clone(),
+                        &other_nonces,
+                        &aggkey,
+                        message,
+                    ),
+                }
+            })
+            .collect();
+        (keys, rounds.into_iter().map(|(msg1, _)| msg1).collect(), signatures)
+    }
 
-    // Deserialize the first signature's R and s components
-    let first_sig = musig2::PartialSignature {
-        R: deserialize_R(&signatures[0].0.as_ref()[..32])?,
-        my_partial_s: deserialize_s(&signatures[0].0.as_ref()[32..])?,
-    };
+    #[test]
+    fn test_partial_signatures_aggregate_to_valid_signature() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let message = b"transfer 10 tokens";
+        let (keys, first_messages, mut signatures) = sign_with(&keypairs, message);
+
+        // Shares can arrive in any order
+        signatures.reverse();
+        let sig = aggregate_partial_signatures(&keys, &first_messages, &signatures, message).unwrap();
+        let aggpubkey = agg_key_to_pubkey(&key_agg(keys, None).unwrap());
+        assert!(sig.verify(aggpubkey.as_ref(), message));
+    }
 
-    // Deserialize all other partial s values
-    let partial_sigs: Vec<_> = signatures[1..]
-        .iter()
-        .map(|s| deserialize_s(&s.0.as_ref()[32..]))
-        .collect::<Result<_, _>>()?;
+    #[test]
+    fn test_effective_nonce_matches_library_signatures() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let message = b"transfer 10 tokens";
+        let (keys, first_messages, signatures) = sign_with(&keypairs, message);
+
+        // Every party's share carries the R recomputed from the nonces, in whatever order they come
+        let mut nonces: Vec<_> = first_messages.iter().map(|msg| msg.public_nonces.R.clone()).collect();
+        let aggkey = key_agg(keys.clone(), None).unwrap();
+        let (R, b) = effective_nonce(&aggkey, &nonces, message);
+        nonces.reverse();
+        assert_eq!(effective_nonce(&aggkey, &nonces, message), (R.clone(), b));
+        for signature in &signatures {
+            assert_eq!(&signature.signature.as_ref()[..32], &*R.to_bytes(true));
+        }
+        for (key, msg) in keys.iter().zip(&first_messages) {
+            let signature = signatures.iter().find(|s| &s.sender == key).unwrap();
+            assert!(verify_partial_signature(signature, key, &msg.public_nonces.R, &keys, &nonces, message).unwrap());
+        }
+    }
 
-    // Add the signatures up using MuSig2 aggregation
-    let full_sig = musig2::aggregate_partial_signatures(&first_sig, &partial_sigs);
+    #[test]
+    fn test_invalid_partial_signature_is_blamed() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let message = b"transfer 10 tokens";
+        let (keys, first_messages, mut signatures) = sign_with(&keypairs, message);
+
+        // The second party replays the first party's share
+        signatures[1].signature = signatures[0].signature;
+        match aggregate_partial_signatures(&keys, &first_messages, &signatures, message) {
+            Err(Error::MisbehavingParties(parties)) => assert_eq!(parties, vec![keys[1]]),
+            _ => panic!("Expected MisbehavingParties error"),
+        }
+    }
 
-    // Convert the aggregated signature to Solana format
-    let mut sig_bytes = [0u8; 64];
-    sig_bytes[..32].copy_from_slice(&*full_sig.R.to_bytes(true));
-    sig_bytes[32..].copy_from_slice(&full_sig.s.to_bytes());
-    let sig = Signature::from(sig_bytes);
+    #[test]
+    fn test_missing_and_duplicate_partial_signatures_are_rejected() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+        let message = b"transfer 10 tokens";
+        let (keys, first_messages, mut signatures) = sign_with(&keypairs, message);
+
+        let last = signatures.pop().unwrap();
+        match aggregate_partial_signatures(&keys, &first_messages, &signatures, message) {
+            Err(Error::MissingParties(parties)) => assert_eq!(parties, vec![keys[2]]),
+            _ => panic!("Expected MissingParties error"),
+        }
 
-    // Create the same transaction again with the aggregated signature
-    let mut tx = crate::token::create_unsigned_sol_transaction(amount, &to, memo, &aggpubkey);
-    
-    // Insert the recent_block_hash and the signature
-    tx.message.recent_blockhash = recent_block_hash;
-    assert_eq!(tx.signatures.len(), 1);
-    tx.signatures[0] = sig;
-
-    // Verify the resulting transaction is actually valid
-    if tx.verify().is_err() {
-        return Err(Error::InvalidSignature);
+        signatures.push(PartialSignature { sender: last.sender, session_id: last.session_id, signature: last.signature });
+        signatures.push(last);
+        assert!(aggregate_partial_signatures(&keys, &first_messages, &signatures, message).is_err());
     }
     
-    Ok(tx)
-}
+    // A transfer template paid by the aggregated key of `keypairs`, and a fresh ledger to sign it with
+    fn signing_setup(keypairs: &[Keypair], name: &str) -> (Vec<Pubkey>, TransactionTemplate, NonceLedger, TempFile) {
+        let keys: Vec<Pubkey> = keypairs.iter().map(|k| k.pubkey()).collect();
+        let template = sol_transfer_template(&keys);
+        let (ledger, ledger_file) = temp_ledger(name);
+        (keys, template, ledger, ledger_file)
+    }
 
-struct PartialSigner {
-    signer_private_nonce: musig2::PrivatePartialNonces,
-    signer_public_nonce: musig2::PublicPartialNonces,
-    other_nonces: Vec<[Point<Ed25519>; 2]>,
-    extended_keypair: ExpandedKeyPair,
-    aggregated_pubkey: musig2::PublicKeyAgg,
-}
+    fn assert_session_mismatch(result: Result<PartialSignature, Error>) {
+        match result {
+            Err(Error::SessionMismatch(_)) => {}
+            Err(e) => panic!("Expected SessionMismatch error, got {}", e),
+            Ok(_) => panic!("Expected SessionMismatch error"),
+        }
+    }
 
-impl solana_sdk::signer::Signer for PartialSigner {
-    fn try_pubkey(&self) -> Result<Pubkey, solana_sdk::signer::SignerError> {
-        Ok(agg_key_to_pubkey(&self.aggregated_pubkey))
+    #[test]
+    fn test_step_two_rejects_nonces_of_another_message() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let (keys, template, mut ledger, _ledger_file) = signing_setup(&keypairs, "tss-other-digest");
+        let session_id = Hash::new_unique();
+
+        let (_, secret_state) = step_one(keypairs[0].insecure_clone(), session_id, Hash::new_unique(), &mut ledger).unwrap();
+        let (other, _) = step_one(keypairs[1].insecure_clone(), session_id, template.digest(), &mut ledger).unwrap();
+        assert_session_mismatch(step_two(
+            keypairs[0].insecure_clone(),
+            &template,
+            keys,
+            vec![other],
+            secret_state,
+            &mut ledger,
+        ));
     }
 
-    fn try_sign_message(&self, message: &[u8]) -> Result<solana_sdk::signature::Signature, solana_sdk::signer::SignerError> {
-        let sig = musig2::partial_sign(
-            &self.other_nonces,
-            self.signer_private_nonce.clone(),
-            self.signer_public_nonce.clone(),
-            &self.aggregated_pubkey,
-            &self.extended_keypair,
-            message,
-        );
-        let mut sig_bytes = [0u8; 64];
-        sig_bytes[..32].copy_from_slice(&*sig.R.to_bytes(true));
-        sig_bytes[32..].copy_from_slice(&sig.my_partial_s.to_bytes());
-        Ok(solana_sdk::signature::Signature::from(sig_bytes))
+    #[test]
+    fn test_step_two_rejects_secret_state_of_another_signer() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let (keys, template, mut ledger, _ledger_file) = signing_setup(&keypairs, "tss-other-signer");
+        let session_id = Hash::new_unique();
+
+        // The first party signs with the second party's nonces
+        let (other, stolen_state) =
+            step_one(keypairs[1].insecure_clone(), session_id, template.digest(), &mut ledger).unwrap();
+        assert_session_mismatch(step_two(
+            keypairs[0].insecure_clone(),
+            &template,
+            keys,
+            vec![other],
+            stolen_state,
+            &mut ledger,
+        ));
     }
 
-    fn is_interactive(&self) -> bool {
-        false
+    #[test]
+    fn test_step_two_rejects_first_message_of_another_session() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::new()).collect();
+        let (keys, template, mut ledger, _ledger_file) = signing_setup(&keypairs, "tss-other-session");
+
+        let (_, secret_state) =
+            step_one(keypairs[0].insecure_clone(), Hash::new_unique(), template.digest(), &mut ledger).unwrap();
+        let (other, _) = step_one(keypairs[1].insecure_clone(), Hash::new_unique(), template.digest(), &mut ledger).unwrap();
+        assert_session_mismatch(step_two(
+            keypairs[0].insecure_clone(),
+            &template,
+            keys,
+            vec![other],
+            secret_state,
+            &mut ledger,
+        ));
+    }
+
+    #[test]
+    fn test_dkg_shares_sign_with_musig2_rounds() {
+        use crate::dkg;
+
+        // A 2-of-3 DKG, no party ever holds the group's private key
+        let ceremony_id = Hash::new_unique();
+        let round_one: Vec<_> = (1..=3).map(|i| dkg::commit(ceremony_id, i, 2, 3).unwrap()).collect();
+        let commitments: Vec<_> = round_one.iter().map(|(c, _)| c.clone()).collect();
+        let all_shares: Vec<_> = round_one.iter().flat_map(|(_, s)| dkg::share(s, &commitments).unwrap()).collect();
+        let key_shares: Vec<_> = round_one
+            .iter()
+            .map(|(_, secret)| {
+                let received: Vec<_> = all_shares.iter().filter(|s| s.receiver_index == secret.index).cloned().collect();
+                dkg::finalize(secret, &commitments, &received).unwrap()
+            })
+            .collect();
+
+        let group_pubkey = key_shares[0].group_pubkey();
+        let options = crate::template::TemplateOptions::default();
+        let template =
+            crate::template::sol_transfer(0.1, &Pubkey::new_unique(), None, &group_pubkey, Hash::new_unique(), &options)
+                .unwrap();
+        let (mut ledger, _ledger_file) = temp_ledger("tss-dkg-shares");
+
+        // Parties 1 and 3 sign through the same rounds as keypair holders
+        let signers = vec![1u16, 3];
+        let quorum: Vec<_> = key_shares.iter().filter(|s| signers.contains(&s.index)).collect();
+        let session_id = Hash::new_unique();
+        let rounds: Vec<_> = quorum
+            .iter()
+            .map(|share| step_one_with_share(share, session_id, template.digest(), &mut ledger).unwrap())
+            .collect();
+        let first_messages: Vec<_> = rounds.iter().map(|(msg1, _)| msg1.clone()).collect();
+        let mut signatures: Vec<_> = quorum
+            .iter()
+            .zip(rounds)
+            .map(|(share, (msg1, secret_state))| {
+                let others = first_messages.iter().filter(|m| m.sender != msg1.sender).cloned().collect();
+                step_two_with_share(share, &template, signers.clone(), others, secret_state, &mut ledger).unwrap()
+            })
+            .collect();
+
+        let tx = sign_and_broadcast_with_shares(
+            &key_shares[0],
+            &template,
+            signers.clone(),
+            first_messages.clone(),
+            signatures.clone(),
+        )
+        .unwrap();
+        assert!(tx.signatures[0].verify(group_pubkey.as_ref(), &template.message));
+
+        // A share over another message is blamed on its signer
+        signatures[1].signature = signatures[0].signature;
+        match sign_and_broadcast_with_shares(&key_shares[0], &template, signers, first_messages, signatures) {
+            Err(Error::MisbehavingParties(parties)) => assert_eq!(parties, vec![share_signer(&key_shares[0], 3)]),
+            _ => panic!("Expected MisbehavingParties error"),
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use solana_sdk::signature::{Keypair, Signer};
-    
     #[test]
     fn test_key_aggregation() {
         // Generate test keypairs